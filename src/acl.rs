@@ -0,0 +1,169 @@
+use crate::bytes::Cursor;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::LittleEndian;
+
+/// Version number at the start of an on-disk ext4 ACL
+const EXT4_ACL_VERSION: u32 = 0x0001;
+
+// ACL entry tags
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+// Permission bits of an entry
+const ACL_READ: u16 = 0x04;
+const ACL_WRITE: u16 = 0x02;
+const ACL_EXECUTE: u16 = 0x01;
+
+/// Who an ACL entry applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclTag {
+    /// The file owner (`user::`)
+    UserObj,
+    /// A named user (`user:<uid>:`)
+    User(u32),
+    /// The owning group (`group::`)
+    GroupObj,
+    /// A named group (`group:<gid>:`)
+    Group(u32),
+    /// Upper bound for named users and all groups (`mask::`)
+    Mask,
+    /// Everybody else (`other::`)
+    Other,
+}
+
+/// A single ACL entry: a tag and its `rwx` permission bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    /// Permission bits: read = 4, write = 2, execute = 1
+    pub perm: u16,
+}
+
+impl AclEntry {
+    /// Permissions in `rwx` form
+    fn perm_str(perm: u16) -> String {
+        [(ACL_READ, 'r'), (ACL_WRITE, 'w'), (ACL_EXECUTE, 'x')]
+            .iter()
+            .map(|&(bit, c)| if perm & bit != 0 { c } else { '-' })
+            .collect()
+    }
+}
+
+impl core::fmt::Display for AclEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let perm = Self::perm_str(self.perm);
+        match self.tag {
+            AclTag::UserObj => write!(f, "user::{}", perm),
+            AclTag::User(uid) => write!(f, "user:{}:{}", uid, perm),
+            AclTag::GroupObj => write!(f, "group::{}", perm),
+            AclTag::Group(gid) => write!(f, "group:{}:{}", gid, perm),
+            AclTag::Mask => write!(f, "mask::{}", perm),
+            AclTag::Other => write!(f, "other::{}", perm),
+        }
+    }
+}
+
+/// A POSIX access control list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acl {
+    pub entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    /// Decode the ext4 on-disk ACL format stored in `system.posix_acl_*`
+    ///
+    /// Unlike the VFS xattr format, ext4 uses a 4-byte header and short
+    /// 4-byte entries for tags that carry no id.
+    ///
+    /// # Arguments
+    /// * `value` - Raw xattr value
+    ///
+    /// # Returns
+    /// Parsed ACL, or `None` if the version or an entry is invalid
+    pub(crate) fn parse(value: &[u8]) -> Option<Self> {
+        let mut cursor = Cursor::new(value);
+        if cursor.read_u32::<LittleEndian>().ok()? != EXT4_ACL_VERSION {
+            return None;
+        }
+
+        let mut entries = Vec::new();
+        while (cursor.position() as usize) < value.len() {
+            let tag = cursor.read_u16::<LittleEndian>().ok()?;
+            let perm = cursor.read_u16::<LittleEndian>().ok()?;
+            let tag = match tag {
+                ACL_USER_OBJ => AclTag::UserObj,
+                ACL_USER => AclTag::User(cursor.read_u32::<LittleEndian>().ok()?),
+                ACL_GROUP_OBJ => AclTag::GroupObj,
+                ACL_GROUP => AclTag::Group(cursor.read_u32::<LittleEndian>().ok()?),
+                ACL_MASK => AclTag::Mask,
+                ACL_OTHER => AclTag::Other,
+                _ => return None,
+            };
+            entries.push(AclEntry { tag, perm });
+        }
+
+        Some(Self { entries })
+    }
+
+    /// The minimal ACL equivalent to a file's permission bits
+    ///
+    /// # Arguments
+    /// * `mode` - File mode; only the `rwxrwxrwx` bits are used
+    pub fn from_mode(mode: u32) -> Self {
+        let perm = |shift: u32| ((mode >> shift) & 0o7) as u16;
+        Self {
+            entries: vec![
+                AclEntry {
+                    tag: AclTag::UserObj,
+                    perm: perm(6),
+                },
+                AclEntry {
+                    tag: AclTag::GroupObj,
+                    perm: perm(3),
+                },
+                AclEntry {
+                    tag: AclTag::Other,
+                    perm: perm(0),
+                },
+            ],
+        }
+    }
+
+    /// The mask entry's permissions, if the ACL has one
+    pub fn mask(&self) -> Option<u16> {
+        self.entries
+            .iter()
+            .find(|e| e.tag == AclTag::Mask)
+            .map(|e| e.perm)
+    }
+}
+
+impl core::fmt::Display for Acl {
+    /// Formats one entry per line like `getfacl -n`, including the
+    /// `#effective:` note when the mask restricts an entry
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mask = self.mask();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", entry)?;
+
+            let masked = matches!(
+                entry.tag,
+                AclTag::User(_) | AclTag::GroupObj | AclTag::Group(_)
+            );
+            if let Some(mask) = mask.filter(|&mask| masked && entry.perm & !mask != 0) {
+                write!(f, "\t#effective:{}", AclEntry::perm_str(entry.perm & mask))?;
+            }
+        }
+        Ok(())
+    }
+}
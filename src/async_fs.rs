@@ -0,0 +1,486 @@
+use crate::device::{BlockDevice, DeviceWindow, past_end};
+use crate::file::Ext4File;
+use crate::{
+    DirectoryEntry, FileSystem, Metadata, Qcow2Image, SparseImage, select_region,
+    split_partition_suffix,
+};
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::future::Future;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Directory entries read ahead of a [`AsyncFileSystem::read_dir`] consumer
+const DIR_STREAM_BUFFER: usize = 64;
+
+/// Granularity in which an [`AsyncBlockDevice`] is read
+const FETCH_CHUNK: u64 = 64 * 1024;
+
+/// Bytes of a file read per step of [`AsyncFileSystem::read_file`]
+const FILE_READ_WINDOW: u64 = 1024 * 1024;
+
+/// Storage that an [`AsyncFileSystem`] is read from
+///
+/// The asynchronous counterpart of [`BlockDevice`], for images behind
+/// async I/O such as object stores or tokio files.
+pub trait AsyncBlockDevice: Send + Sync + 'static {
+    /// Fill `buf` with the bytes starting at `offset`
+    ///
+    /// # Errors
+    /// Returns `UnexpectedEof` if the range extends past the end of the device
+    fn read_at(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> impl Future<Output = std::io::Result<()>> + Send;
+
+    /// Size of the device in bytes
+    fn size(&self) -> impl Future<Output = std::io::Result<u64>> + Send;
+}
+
+/// An image file or block device read with positional reads
+///
+/// Each read runs on tokio's blocking thread pool without touching a
+/// shared file position, so any number of them proceed at once.
+pub struct AsyncImage {
+    file: Arc<File>,
+    size: u64,
+}
+
+impl AsyncImage {
+    /// # Arguments
+    /// * `path` - Path to the image file or device
+    pub async fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = Arc::new(blocking(move || File::open(path)).await?);
+        let size = {
+            let file = file.clone();
+            blocking(move || BlockDevice::size(&*file)).await?
+        };
+        Ok(Self { file, size })
+    }
+}
+
+impl AsyncBlockDevice for AsyncImage {
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        let file = self.file.clone();
+        let mut data = vec![0u8; buf.len()];
+        let data = blocking(move || BlockDevice::read_at(&*file, offset, &mut data).map(|()| data))
+            .await?;
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+
+    /// Measured once when opened, since the metadata length is 0 for
+    /// block devices
+    async fn size(&self) -> std::io::Result<u64> {
+        Ok(self.size)
+    }
+}
+
+/// Chunks of an async device fetched for one call, by chunk number
+type Chunks = HashMap<u64, Vec<u8>>;
+
+thread_local! {
+    /// The chunks the filesystem code running on this thread may read,
+    /// and the ones it asked for but did not find
+    static FETCHED: RefCell<(Chunks, BTreeSet<u64>)> = RefCell::default();
+}
+
+/// The synchronous view of an async device that [`FileSystem`] parses
+///
+/// Reads are served from the chunks fetched for the current call. A read
+/// of anything else records the missing chunks and fails with
+/// `WouldBlock`; [`drive`] then fetches them and runs the step again.
+#[derive(Clone, Copy)]
+struct Fetched {
+    size: u64,
+}
+
+impl BlockDevice for Fetched {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= self.size)
+            .ok_or_else(past_end)?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        FETCHED.with_borrow_mut(|(chunks, missing)| {
+            let first = offset / FETCH_CHUNK;
+            let last = (end - 1) / FETCH_CHUNK;
+            let absent: Vec<u64> = (first..=last)
+                .filter(|chunk| !chunks.contains_key(chunk))
+                .collect();
+            if !absent.is_empty() {
+                missing.extend(absent);
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::WouldBlock,
+                    "Block not fetched yet",
+                ));
+            }
+
+            let mut done = 0;
+            for chunk in first..=last {
+                let data = &chunks[&chunk];
+                let pos = (offset + done as u64 - chunk * FETCH_CHUNK) as usize;
+                let copied = (data.len() - pos).min(buf.len() - done);
+                buf[done..done + copied].copy_from_slice(&data[pos..pos + copied]);
+                done += copied;
+            }
+            Ok(())
+        })
+    }
+
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.size)
+    }
+}
+
+/// Run a synchronous step until it has every chunk it needs
+///
+/// The step runs on the calling task against `chunks`. Whatever it could
+/// not find is fetched through the device's own futures, all at once, and
+/// the step runs again; a step that recovered from a missing read is run
+/// again too, as it decided without the data. `chunks` keeps the fetched
+/// data, so each run gets further than the last.
+async fn drive<A: AsyncBlockDevice, T>(
+    device: &Arc<A>,
+    size: u64,
+    chunks: &mut Chunks,
+    mut step: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    loop {
+        FETCHED.set((std::mem::take(chunks), BTreeSet::new()));
+        let result = step();
+        let missing;
+        (*chunks, missing) = FETCHED.take();
+        if missing.is_empty() {
+            return result;
+        }
+
+        let mut reads = JoinSet::new();
+        for chunk in missing {
+            let device = device.clone();
+            let offset = chunk * FETCH_CHUNK;
+            let mut data = vec![0u8; FETCH_CHUNK.min(size - offset) as usize];
+            reads.spawn(async move {
+                device
+                    .read_at(offset, &mut data)
+                    .await
+                    .map(|()| (chunk, data))
+            });
+        }
+        while let Some(read) = reads.join_next().await {
+            let (chunk, data) = match read {
+                Ok(read) => read?,
+                Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Err(e) => return Err(std::io::Error::other(e)),
+            };
+            chunks.insert(chunk, data);
+        }
+    }
+}
+
+/// An ext4 filesystem with an async API
+///
+/// Lookups and parsing are shared with [`FileSystem`] and run on the
+/// calling task. Blocks are fetched through the [`AsyncBlockDevice`]'s
+/// futures as the parsing finds it needs them, so no thread ever waits on
+/// the device. Clones are cheap and share the block, inode and directory
+/// caches.
+///
+/// Must be used from within a tokio runtime.
+pub struct AsyncFileSystem<A: AsyncBlockDevice = AsyncImage> {
+    fs: Arc<FileSystem<DeviceWindow<Fetched>>>,
+    device: Arc<A>,
+    /// Size of the whole device
+    size: u64,
+}
+
+impl<A: AsyncBlockDevice> Clone for AsyncFileSystem<A> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: self.fs.clone(),
+            device: self.device.clone(),
+            size: self.size,
+        }
+    }
+}
+
+impl AsyncFileSystem {
+    /// Open a raw ext4 filesystem image, as [`FileSystem::open`] does
+    ///
+    /// A `#pN` suffix selects partition N of a whole-disk image. Container
+    /// formats are not read through: Android sparse and qcow2 images have
+    /// to be opened with [`FileSystem::open`].
+    ///
+    /// # Arguments
+    /// * `path` - Path to filesystem image or device file
+    ///
+    /// # Errors
+    /// Returns `Unsupported` for sparse and qcow2 images
+    pub async fn open(path: &str) -> std::io::Result<Self> {
+        let (disk, index) = split_partition_suffix(path);
+        let device = AsyncImage::open(disk).await?;
+        Self::init(device, |device| {
+            if SparseImage::detect(device)? || Qcow2Image::detect(device)? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!(
+                        "'{}' is a container image; open it with FileSystem::open",
+                        disk
+                    ),
+                ));
+            }
+            select_region(device, disk, index)
+        })
+        .await
+    }
+}
+
+impl<A: AsyncBlockDevice> AsyncFileSystem<A> {
+    /// Initialize an ext4 filesystem stored on an async device
+    ///
+    /// # Arguments
+    /// * `device` - Device holding the filesystem, starting at offset 0
+    ///
+    /// # Errors
+    /// Returns `InvalidData` if the device does not hold an ext2/3/4 superblock
+    pub async fn from_device(device: A) -> std::io::Result<Self> {
+        Self::init(device, |device| Ok(0..device.size()?)).await
+    }
+
+    /// Open the filesystem in the region of the device picked by `region`
+    async fn init(
+        device: A,
+        mut region: impl FnMut(&Fetched) -> std::io::Result<Range<u64>>,
+    ) -> std::io::Result<Self> {
+        let size = device.size().await?;
+        let (device, raw) = (Arc::new(device), Fetched { size });
+        let mut chunks = Chunks::new();
+        let region = drive(&device, size, &mut chunks, || region(&raw)).await?;
+        let fs = drive(&device, size, &mut chunks, || {
+            FileSystem::from_device(DeviceWindow::new(
+                raw,
+                region.start,
+                region.end - region.start,
+            ))
+        })
+        .await?;
+        Ok(Self {
+            fs: Arc::new(fs),
+            device,
+            size,
+        })
+    }
+
+    /// Run a step of filesystem code, fetching what it needs
+    async fn run<T>(
+        &self,
+        chunks: &mut Chunks,
+        step: impl FnMut() -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        drive(&self.device, self.size, chunks, step).await
+    }
+
+    /// Read the whole content of a file, as [`FileSystem::read_file`] does
+    ///
+    /// The file is read a window at a time, each fetched in one go.
+    pub async fn read_file(&self, path: impl AsRef<[u8]>) -> std::io::Result<Vec<u8>> {
+        let (fs, path) = (&*self.fs, path.as_ref());
+        let mut chunks = Chunks::new();
+        let (inode_num, inode) = self
+            .run(&mut chunks, || {
+                let inode_num = fs.resolve_path(path)?;
+                let inode = fs.read_inode(inode_num)?;
+                fs.check_whole_read(inode_num, &inode)?;
+                Ok((inode_num, inode))
+            })
+            .await?;
+
+        let mut file = Ext4File::new(fs, inode_num, inode);
+        let mut content = vec![0u8; file.len() as usize];
+        for (i, window) in content.chunks_mut(FILE_READ_WINDOW as usize).enumerate() {
+            // Only the current window's data is kept
+            chunks.clear();
+            let offset = i as u64 * FILE_READ_WINDOW;
+            self.run(&mut chunks, || file.read_at(window, offset))
+                .await?;
+        }
+        Ok(content)
+    }
+
+    /// List a directory, as [`FileSystem::list_dir`] does
+    pub async fn list_dir(&self, path: impl AsRef<[u8]>) -> std::io::Result<Vec<DirectoryEntry>> {
+        let mut walk = DirWalk::new(self, path.as_ref()).await?;
+        let mut entries = Vec::new();
+        while let Some(batch) = walk.next_batch(self).await? {
+            entries.extend(batch);
+        }
+        Ok(entries)
+    }
+
+    /// Metadata of a path, as [`FileSystem::metadata`] does
+    pub async fn metadata(&self, path: impl AsRef<[u8]>) -> std::io::Result<Metadata> {
+        let path = path.as_ref();
+        self.run(&mut Chunks::new(), || self.fs.metadata(path))
+            .await
+    }
+
+    /// Stream the entries of a directory as its blocks are read
+    ///
+    /// Unlike [`list_dir`](Self::list_dir), large directories are not
+    /// collected in memory first. Reading stops once the stream is dropped.
+    ///
+    /// # Returns
+    /// A stream of entries that ends after the first error
+    pub fn read_dir(
+        &self,
+        path: impl AsRef<[u8]>,
+    ) -> impl Stream<Item = std::io::Result<DirectoryEntry>> + Send + 'static {
+        let (fs, path) = (self.clone(), path.as_ref().to_vec());
+        let (tx, rx) = mpsc::channel(DIR_STREAM_BUFFER);
+        tokio::spawn(async move {
+            let result = async {
+                let mut walk = DirWalk::new(&fs, &path).await?;
+                while let Some(batch) = walk.next_batch(&fs).await? {
+                    for entry in batch {
+                        // A failed send means the stream was dropped
+                        if tx.send(Ok(entry)).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+                Ok(())
+            };
+            if let Err(e) = result.await {
+                let _ = tx.send(Err(e)).await;
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+}
+
+/// A walk over a directory's blocks that hands out entries in batches
+///
+/// Each batch resumes at the block after the last one visited, so a
+/// large directory is neither held in memory nor parsed more than once.
+struct DirWalk {
+    inode_num: u32,
+    /// Position of the next block to visit, or `None` once all were
+    next: Option<usize>,
+}
+
+impl DirWalk {
+    async fn new<A: AsyncBlockDevice>(
+        fs: &AsyncFileSystem<A>,
+        path: &[u8],
+    ) -> std::io::Result<Self> {
+        let inode_num = fs.run(&mut Chunks::new(), || fs.fs.dir_inode(path)).await?;
+        Ok(Self {
+            inode_num,
+            next: Some(0),
+        })
+    }
+
+    /// The entries of the next few blocks, or `None` at the end
+    async fn next_batch<A: AsyncBlockDevice>(
+        &mut self,
+        fs: &AsyncFileSystem<A>,
+    ) -> std::io::Result<Option<Vec<DirectoryEntry>>> {
+        let Some(mut next) = self.next else {
+            return Ok(None);
+        };
+        let mut batch = Vec::new();
+        let finished = fs
+            .run(&mut Chunks::new(), || {
+                // A run cut short by a missing block resumes after the
+                // blocks it did visit
+                let mut finished = true;
+                fs.fs
+                    .visit_dir(self.inode_num, false, next, |position, entries| {
+                        batch.extend(entries);
+                        next = position + 1;
+                        finished = batch.len() < DIR_STREAM_BUFFER;
+                        finished
+                    })?;
+                Ok(finished)
+            })
+            .await?;
+        self.next = (!finished).then_some(next);
+        Ok(Some(batch))
+    }
+}
+
+/// Run blocking file I/O on tokio's blocking thread pool
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> std::io::Result<T> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(std::io::Error::other(e)),
+    }
+}
+
+#[test]
+fn test_async_filesystem() {
+    use tokio_stream::StreamExt;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let fs = AsyncFileSystem::open("ext4.img").await.unwrap();
+        assert_eq!(
+            fs.read_file("/home/zyu379/test_file.txt").await.unwrap(),
+            b"hello from ext4 test\n"
+        );
+        assert!(fs.metadata("/bigdir").await.unwrap().is_dir());
+
+        // Files spanning holes and several fetches match the sync reader
+        let sync = FileSystem::open("ext4.img").unwrap();
+        assert_eq!(
+            fs.read_file("/sparse.bin").await.unwrap(),
+            sync.read_file("/sparse.bin").unwrap()
+        );
+
+        let listed = fs.list_dir("/bigdir").await.unwrap();
+        let streamed: Vec<_> = fs
+            .read_dir("/bigdir")
+            .collect::<std::io::Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 502);
+        assert_eq!(streamed, listed);
+
+        // Errors arrive through the stream
+        let mut missing = Box::pin(fs.read_dir("/missing"));
+        assert!(missing.next().await.unwrap().is_err());
+        assert!(missing.next().await.is_none());
+
+        let image = AsyncImage::open("ext4.img").await.unwrap();
+        let fs = AsyncFileSystem::from_device(image).await.unwrap();
+        assert!(fs.list_dir("/").await.is_ok());
+
+        // Container images are refused rather than read as raw
+        let image = std::fs::read("ext4.img").unwrap();
+        let path = crate::TempPath::new("async.qcow2");
+        std::fs::write(&path, crate::qcow2::to_qcow2(&image, None, None, |_| true)).unwrap();
+        let error = AsyncFileSystem::open(path.to_str().unwrap())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+        assert!(FileSystem::open(path.to_str().unwrap()).is_ok());
+    });
+}
@@ -0,0 +1,79 @@
+use crate::io;
+
+use byteorder::ByteOrder;
+
+/// Reads integers from a byte slice, like `std::io::Cursor` with
+/// byteorder's `ReadBytesExt`, but without needing `std`
+pub(crate) struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: u64,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub(crate) fn position(&self) -> u64 {
+        self.pos
+    }
+
+    pub(crate) fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+
+    /// Fill `out` from the current position
+    ///
+    /// # Errors
+    /// Returns `UnexpectedEof`, without moving, if the slice ends first
+    pub(crate) fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    pub(crate) fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn read_u16<B: ByteOrder>(&mut self) -> io::Result<u16> {
+        Ok(B::read_u16(self.take(2)?))
+    }
+
+    pub(crate) fn read_u32<B: ByteOrder>(&mut self) -> io::Result<u32> {
+        Ok(B::read_u32(self.take(4)?))
+    }
+
+    pub(crate) fn read_u64<B: ByteOrder>(&mut self) -> io::Result<u64> {
+        Ok(B::read_u64(self.take(8)?))
+    }
+
+    pub(crate) fn read_u32_into<B: ByteOrder>(&mut self, out: &mut [u32]) -> io::Result<()> {
+        B::read_u32_into(self.take(out.len() * 4)?, out);
+        Ok(())
+    }
+
+    /// The next `len` bytes, advancing past them
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = usize::try_from(self.pos)
+            .ok()
+            .and_then(|start| self.buf.get(start..start.checked_add(len)?))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected end of buffer")
+            })?;
+        self.pos += len as u64;
+        Ok(bytes)
+    }
+}
+
+#[test]
+fn test_cursor() {
+    use byteorder::LittleEndian;
+
+    let mut cursor = Cursor::new(&[1, 0, 2, 0, 0, 0, 3]);
+    assert_eq!(cursor.read_u16::<LittleEndian>().unwrap(), 1);
+    assert_eq!(cursor.read_u32::<LittleEndian>().unwrap(), 2);
+    assert_eq!(cursor.position(), 6);
+    // A short read fails and leaves the position alone
+    assert!(cursor.read_u16::<LittleEndian>().is_err());
+    assert_eq!(cursor.read_u8().unwrap(), 3);
+}
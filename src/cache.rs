@@ -0,0 +1,530 @@
+use crate::device::{BlockBuf, BlockDevice};
+use crate::io;
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Cache budget used unless [`FileSystem::set_cache_capacity`] changes it
+///
+/// [`FileSystem::set_cache_capacity`]: crate::FileSystem::set_cache_capacity
+pub(crate) const DEFAULT_CACHE_CAPACITY: usize = 8 << 20;
+
+/// Parsed inodes kept for path resolution and metadata queries
+pub(crate) const INODE_CACHE_ENTRIES: usize = 8192;
+
+/// Directory lookups kept for path resolution, including failed ones
+pub(crate) const DENTRY_CACHE_ENTRIES: usize = 65536;
+
+/// Block size used until the superblock has been read
+const INITIAL_CACHE_BLOCK_SIZE: u64 = 1024;
+
+/// Hit and miss counters of the block cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Block lookups served from memory
+    pub hits: u64,
+    /// Block lookups that had to read the device
+    pub misses: u64,
+    /// Blocks dropped to stay within the byte budget
+    pub evictions: u64,
+    /// Bytes currently held
+    pub bytes: usize,
+    /// Byte budget
+    pub capacity: usize,
+}
+
+impl core::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let lookups = self.hits + self.misses;
+        let ratio = if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 * 100.0 / lookups as f64
+        };
+        writeln!(f, "Hits: {} ({:.1}%)", self.hits, ratio)?;
+        writeln!(f, "Misses: {}", self.misses)?;
+        writeln!(f, "Evictions: {}", self.evictions)?;
+        write!(f, "Cached: {} of {} bytes", self.bytes, self.capacity)
+    }
+}
+
+/// Least-recently-used map holding at most `capacity` entries
+pub(crate) struct LruCache<K, V> {
+    entries: BTreeMap<K, (V, u64)>,
+    /// Keys by last use, oldest first
+    lru: BTreeMap<u64, K>,
+    tick: u64,
+    capacity: usize,
+}
+
+impl<K: Ord + Clone, V> LruCache<K, V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            capacity,
+        }
+    }
+
+    /// Look up a key, marking it as most recently used
+    pub(crate) fn get(&mut self, key: &K) -> Option<&V> {
+        let (_, last_used) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        if let Some(key) = self.lru.remove(last_used) {
+            self.lru.insert(self.tick, key);
+        }
+        *last_used = self.tick;
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Insert or replace an entry, evicting the least recently used one if full
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        self.remove(&key);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity
+            && let Some((_, oldest)) = self.lru.pop_first()
+        {
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.lru.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, last_used) = self.entries.remove(key)?;
+        self.lru.remove(&last_used);
+        Some(value)
+    }
+
+    /// Keep only the entries for which `keep` returns true
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let lru = &mut self.lru;
+        self.entries.retain(|key, (value, last_used)| {
+            let kept = keep(key, value);
+            if !kept {
+                lru.remove(last_used);
+            }
+            kept
+        });
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+    }
+}
+
+/// A cached block and its position in the LRU order
+struct CachedBlock {
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+/// Guards caches that are shared between threads
+///
+/// Without `std` there are no threads to share with, so a `RefCell` does.
+#[cfg(feature = "std")]
+pub(crate) type Lock<T> = std::sync::Mutex<T>;
+#[cfg(not(feature = "std"))]
+pub(crate) type Lock<T> = core::cell::RefCell<T>;
+
+/// Lock a cache shared between threads
+///
+/// A thread that panicked while holding the lock cannot have left the
+/// cache inconsistent in a way that matters: at worst an entry or a
+/// counter update is missing, so the poison flag is ignored.
+#[cfg(feature = "std")]
+pub(crate) fn lock<T>(lock: &Lock<T>) -> std::sync::MutexGuard<'_, T> {
+    lock.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn lock<T>(lock: &Lock<T>) -> core::cell::RefMut<'_, T> {
+    lock.borrow_mut()
+}
+
+/// Access a cache through a unique reference, without locking
+fn get_mut<T>(lock: &mut Lock<T>) -> &mut T {
+    #[cfg(feature = "std")]
+    {
+        lock.get_mut()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        lock.get_mut()
+    }
+}
+
+/// The mutable state of a [`CachedDevice`], behind its lock
+struct BlockCache {
+    blocks: BTreeMap<u64, CachedBlock>,
+    /// Block numbers by `last_used`, oldest first
+    lru: BTreeMap<u64, u64>,
+    tick: u64,
+    /// Bumped whenever blocks are dropped or written, so a miss that read
+    /// the device meanwhile does not cache what may now be stale
+    generation: u64,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    /// Copy from a cached block into `buf`, marking it as recently used
+    ///
+    /// # Returns
+    /// Number of bytes copied, or `None` if the block is not cached
+    fn copy(&mut self, block: u64, within: usize, buf: &mut [u8]) -> Option<usize> {
+        let cached = self.blocks.get_mut(&block)?;
+        self.tick += 1;
+        self.stats.hits += 1;
+        self.lru.remove(&cached.last_used);
+        self.lru.insert(self.tick, block);
+        cached.last_used = self.tick;
+        Some(copy_from(&cached.data, within, buf))
+    }
+
+    fn insert(&mut self, block: u64, data: Vec<u8>) {
+        self.tick += 1;
+        self.stats.bytes += data.len();
+        self.lru.insert(self.tick, block);
+        let cached = CachedBlock {
+            data,
+            last_used: self.tick,
+        };
+        // Another thread may have read the same block in the meantime
+        if let Some(old) = self.blocks.insert(block, cached) {
+            self.lru.remove(&old.last_used);
+            self.stats.bytes -= old.data.len();
+        }
+        self.evict();
+    }
+
+    fn clear(&mut self) {
+        self.blocks.clear();
+        self.lru.clear();
+        self.stats.bytes = 0;
+        self.generation += 1;
+    }
+
+    /// Drop least recently used blocks until within the budget
+    fn evict(&mut self) {
+        while self.stats.bytes > self.stats.capacity {
+            let Some((_, block)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(cached) = self.blocks.remove(&block) {
+                self.stats.bytes -= cached.data.len();
+                self.stats.evictions += 1;
+            }
+        }
+    }
+}
+
+/// Copy the bytes of a block from `within` onwards into `buf`
+fn copy_from(data: &[u8], within: usize, buf: &mut [u8]) -> usize {
+    let n = data.len().saturating_sub(within).min(buf.len());
+    buf[..n].copy_from_slice(&data[within..within + n]);
+    n
+}
+
+/// Least-recently-used cache of whole blocks in front of a device
+///
+/// Reads are split into blocks, and each block is fetched from the device
+/// once and then served from memory until it is evicted. Writes go
+/// through to the device and update any cached copy. The cache sits
+/// behind a lock, which is not held while the device is read, so threads
+/// sharing the device only wait for each other on bookkeeping.
+pub(crate) struct CachedDevice<D> {
+    inner: D,
+    /// Size of the underlying device, so the last block may be partial
+    size: u64,
+    block_size: u64,
+    cache: Lock<BlockCache>,
+}
+
+impl<D: BlockDevice> CachedDevice<D> {
+    pub(crate) fn new(inner: D, capacity: usize) -> io::Result<Self> {
+        let size = inner.size()?;
+        Ok(Self {
+            inner,
+            size,
+            block_size: INITIAL_CACHE_BLOCK_SIZE,
+            cache: Lock::new(BlockCache {
+                blocks: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                tick: 0,
+                generation: 0,
+                stats: CacheStats {
+                    capacity,
+                    ..CacheStats::default()
+                },
+            }),
+        })
+    }
+
+    /// Switch to the filesystem's block size, dropping everything cached
+    pub(crate) fn set_block_size(&mut self, block_size: u32) {
+        self.block_size = block_size as u64;
+        self.clear();
+    }
+
+    /// Change the byte budget, evicting blocks if it shrank
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        let mut cache = lock(&self.cache);
+        cache.stats.capacity = capacity;
+        cache.evict();
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        lock(&self.cache).stats
+    }
+
+    /// Drop all cached blocks; the counters are kept
+    pub(crate) fn clear(&self) {
+        lock(&self.cache).clear();
+    }
+
+    /// Read without consulting or filling the cache
+    ///
+    /// Used for file content, so streaming a large file does not push the
+    /// metadata that lookups depend on out of the cache.
+    pub(crate) fn read_uncached(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_at(offset, buf)
+    }
+
+    /// Drop cached blocks overlapping a byte range, so the next read of
+    /// them goes to the device
+    pub(crate) fn invalidate(&self, offset: u64, len: u64) {
+        let end = offset.saturating_add(len);
+        let block_size = self.block_size;
+        let cache = &mut *lock(&self.cache);
+        cache.generation += 1;
+        let (lru, bytes) = (&mut cache.lru, &mut cache.stats.bytes);
+        cache.blocks.retain(|&block, cached| {
+            let start = block * block_size;
+            let overlaps = start < end && offset < start + cached.data.len() as u64;
+            if overlaps {
+                lru.remove(&cached.last_used);
+                *bytes -= cached.data.len();
+            }
+            !overlaps
+        });
+    }
+
+    pub(crate) fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Copy from a block into `buf`, reading and caching it on a miss
+    ///
+    /// # Returns
+    /// Number of bytes copied, which is only short at the end of the device
+    fn read_block(&self, block: u64, within: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let generation = {
+            let mut cache = lock(&self.cache);
+            if let Some(n) = cache.copy(block, within, buf) {
+                return Ok(n);
+            }
+            cache.generation
+        };
+
+        let start = block * self.block_size;
+        let len = self.block_size.min(self.size.saturating_sub(start));
+        if len == 0 {
+            return Err(past_end());
+        }
+        let mut data = vec![0u8; len as usize];
+        self.inner.read_at(start, &mut data)?;
+        let n = copy_from(&data, within, buf);
+
+        let mut cache = lock(&self.cache);
+        cache.stats.misses += 1;
+        // Invalidated or written while the device was read
+        if cache.generation == generation {
+            cache.insert(block, data);
+        }
+        Ok(n)
+    }
+}
+
+fn past_end() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Read past the end of the device",
+    )
+}
+
+impl<D: BlockDevice> BlockDevice for CachedDevice<D> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if self.inner.is_zero_copy() || self.stats().capacity < self.block_size as usize {
+            return self.inner.read_at(offset, buf);
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = (pos % self.block_size) as usize;
+            match self.read_block(pos / self.block_size, within, &mut buf[done..])? {
+                0 => return Err(past_end()),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+
+    /// Mapped devices lend out their blocks directly, bypassing the cache
+    fn read_buf(&self, offset: u64, len: usize) -> io::Result<BlockBuf> {
+        if self.inner.is_zero_copy() {
+            return self.inner.read_buf(offset, len);
+        }
+        let mut buf = vec![0u8; len];
+        self.read_at(offset, &mut buf)?;
+        Ok(BlockBuf::Owned(buf))
+    }
+
+    fn is_zero_copy(&self) -> bool {
+        self.inner.is_zero_copy()
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_at(offset, buf)?;
+
+        // Keep cached copies of the written range current
+        let end = offset + buf.len() as u64;
+        let cache = get_mut(&mut self.cache);
+        cache.generation += 1;
+        for block in offset / self.block_size..end.div_ceil(self.block_size) {
+            let Some(cached) = cache.blocks.get_mut(&block) else {
+                continue;
+            };
+            let start = block * self.block_size;
+            let from = offset.max(start);
+            let to = end.min(start + cached.data.len() as u64);
+            if from < to {
+                cached.data[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(&buf[(from - offset) as usize..(to - offset) as usize]);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_block_cache() {
+    let data: Vec<u8> = (0..4096u32).map(|i| i as u8).collect();
+    let mut cache = CachedDevice::new(data, 2048).unwrap();
+
+    // A read spanning two blocks misses on both, then hits on both
+    let mut buf = [0u8; 8];
+    cache.read_at(1020, &mut buf).unwrap();
+    assert_eq!(buf, [252, 253, 254, 255, 0, 1, 2, 3]);
+    cache.read_at(1020, &mut buf).unwrap();
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.bytes), (2, 2, 2048));
+
+    // A third block evicts the least recently used one, block 0
+    cache.read_at(1024, &mut buf[..1]).unwrap();
+    cache.read_at(2048, &mut buf[..1]).unwrap();
+    assert_eq!(cache.stats().evictions, 1);
+    cache.read_at(1024, &mut buf[..1]).unwrap();
+    cache.read_at(0, &mut buf[..1]).unwrap();
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (4, 4, 2));
+
+    // Writes update the cached copy
+    cache.write_at(1, &[0xAA, 0xBB]).unwrap();
+    cache.read_at(0, &mut buf[..4]).unwrap();
+    assert_eq!(buf[..4], [0, 0xAA, 0xBB, 3]);
+
+    assert!(cache.read_at(4090, &mut buf).is_err());
+    cache.set_capacity(0);
+    assert_eq!(cache.stats().bytes, 0);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_invalidate_during_read() {
+    use std::sync::{Arc, Barrier, Mutex};
+
+    /// Pauses its first read after copying the data, until released
+    struct Paused {
+        data: Mutex<Vec<u8>>,
+        pause: Mutex<Option<Arc<Barrier>>>,
+    }
+
+    impl BlockDevice for Paused {
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+            let start = offset as usize;
+            buf.copy_from_slice(&self.data.lock().unwrap()[start..start + buf.len()]);
+            let pause = self.pause.lock().unwrap().take();
+            if let Some(pause) = pause {
+                pause.wait();
+                pause.wait();
+            }
+            Ok(())
+        }
+
+        fn size(&self) -> io::Result<u64> {
+            Ok(self.data.lock().unwrap().len() as u64)
+        }
+    }
+
+    let device = Paused {
+        data: Mutex::new(vec![1u8; 4096]),
+        pause: Mutex::new(None),
+    };
+    let cache = CachedDevice::new(device, 4096).unwrap();
+    let pause = Arc::new(Barrier::new(2));
+    *cache.inner.pause.lock().unwrap() = Some(pause.clone());
+
+    // The block changes and is invalidated while a miss is reading it
+    std::thread::scope(|scope| {
+        let reader = scope.spawn(|| {
+            let mut buf = [0u8; 1];
+            cache.read_at(0, &mut buf).unwrap();
+            buf[0]
+        });
+        pause.wait();
+        cache.inner.data.lock().unwrap().fill(2);
+        cache.invalidate(0, 1);
+        pause.wait();
+        assert_eq!(reader.join().unwrap(), 1);
+    });
+
+    let mut buf = [0u8; 1];
+    cache.read_at(0, &mut buf).unwrap();
+    assert_eq!(buf[0], 2);
+}
+
+#[test]
+fn test_lru_cache() {
+    let mut cache = LruCache::new(2);
+    cache.insert(1, "one");
+    cache.insert(2, "two");
+    assert_eq!(cache.get(&1), Some(&"one"));
+
+    // 2 is now the least recently used entry
+    cache.insert(3, "three");
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.get(&1), Some(&"one"));
+
+    cache.retain(|&key, _| key != 1);
+    assert_eq!(cache.get(&1), None);
+    cache.insert(4, "four");
+    assert_eq!(cache.get(&3), Some(&"three"));
+    assert_eq!(cache.get(&4), Some(&"four"));
+}
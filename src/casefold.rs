@@ -0,0 +1,186 @@
+use crate::io;
+
+#[cfg(feature = "std")]
+use crate::unicode_12_1::is_assigned;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use caseless::Caseless;
+#[cfg(feature = "std")]
+use unicode_normalization::UnicodeNormalization;
+
+/// `s_encoding` value for UTF-8 with Unicode 12.1 case folding
+pub(crate) const EXT4_ENC_UTF8_12_1: u16 = 1;
+
+/// Code points with the Default_Ignorable_Code_Point property
+///
+/// The kernel's `nfdicf` tables map these to nothing, so names that only
+/// differ by e.g. a zero-width space or soft hyphen compare equal.
+#[cfg(feature = "std")]
+const DEFAULT_IGNORABLE: &[(char, char)] = &[
+    ('\u{00AD}', '\u{00AD}'),
+    ('\u{034F}', '\u{034F}'),
+    ('\u{061C}', '\u{061C}'),
+    ('\u{115F}', '\u{1160}'),
+    ('\u{17B4}', '\u{17B5}'),
+    ('\u{180B}', '\u{180E}'),
+    ('\u{200B}', '\u{200F}'),
+    ('\u{202A}', '\u{202E}'),
+    ('\u{2060}', '\u{206F}'),
+    ('\u{3164}', '\u{3164}'),
+    ('\u{FE00}', '\u{FE0F}'),
+    ('\u{FEFF}', '\u{FEFF}'),
+    ('\u{FFA0}', '\u{FFA0}'),
+    ('\u{FFF0}', '\u{FFF8}'),
+    ('\u{1BCA0}', '\u{1BCA3}'),
+    ('\u{1D173}', '\u{1D17A}'),
+    ('\u{E0000}', '\u{E0FFF}'),
+];
+
+#[cfg(feature = "std")]
+fn is_default_ignorable(c: char) -> bool {
+    DEFAULT_IGNORABLE
+        .iter()
+        .any(|&(start, end)| (start..=end).contains(&c))
+}
+
+/// Casefold a name the way the kernel's `utf8_casefold()` does
+///
+/// Applies full Unicode case folding followed by canonical decomposition
+/// (NFD), dropping default-ignorable code points. Two names refer to the
+/// same entry of a casefolded directory exactly when their folded forms
+/// are equal, and the folded form is what the htree hash is computed over.
+///
+/// The kernel pins its tables to Unicode 12.1. Unicode's stability policy
+/// keeps the folding and decomposition of assigned characters unchanged in
+/// later versions, so the newer tables of `caseless` and
+/// `unicode-normalization` agree for every name the kernel can fold.
+///
+/// # Arguments
+/// * `name` - File name to fold
+///
+/// # Returns
+/// The folded name, or `None` if it contains code points assigned after
+/// Unicode 12.1; the kernel cannot fold those and falls back to the raw
+/// bytes for both matching and hashing
+#[cfg(feature = "std")]
+pub(crate) fn casefold(name: &str) -> Option<String> {
+    fold_chars(name).map(Iterator::collect)
+}
+
+/// The characters of the folded form of `name`, produced lazily
+#[cfg(feature = "std")]
+fn fold_chars(name: &str) -> Option<impl Iterator<Item = char> + '_> {
+    if !name.chars().all(is_assigned) {
+        return None;
+    }
+    Some(
+        name.chars()
+            .default_case_fold()
+            .nfd()
+            .filter(|&c| !is_default_ignorable(c)),
+    )
+}
+
+/// A name being looked up in a directory
+///
+/// In casefolded directories both matching and the htree hash use the
+/// folded form; elsewhere, and for names that are not valid UTF-8 or use
+/// code points newer than Unicode 12.1, names are compared byte for byte
+/// as the kernel does. Folding needs the Unicode
+/// tables of the `std` build; without them casefolded directories cannot
+/// be searched.
+pub(crate) struct LookupName<'a> {
+    name: &'a [u8],
+    folded: Option<String>,
+}
+
+impl<'a> LookupName<'a> {
+    /// # Arguments
+    /// * `name` - Name to find
+    /// * `casefolded` - Whether the directory has `EXT4_CASEFOLD_FL`
+    ///
+    /// # Errors
+    /// Returns `Unsupported` for a casefolded directory without `std`,
+    /// rather than silently missing names that differ only in case
+    pub(crate) fn new(name: &'a [u8], casefolded: bool) -> io::Result<Self> {
+        #[cfg(feature = "std")]
+        let folded = match core::str::from_utf8(name) {
+            Ok(name) if casefolded => casefold(name),
+            _ => None,
+        };
+        #[cfg(not(feature = "std"))]
+        let folded = match casefolded {
+            true => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Casefolded directories need the std feature",
+                ));
+            }
+            false => None,
+        };
+        Ok(Self { name, folded })
+    }
+
+    /// The bytes the htree hash is computed over
+    pub(crate) fn hash_input(&self) -> &[u8] {
+        self.folded
+            .as_ref()
+            .map_or(self.name, |folded| folded.as_bytes())
+    }
+
+    /// Whether a directory entry's name refers to this name
+    ///
+    /// The looked-up name was folded once up front; entry names are folded
+    /// lazily and compared as they go, so mismatches stop at the first
+    /// differing character without allocating.
+    pub(crate) fn matches(&self, entry_name: &[u8]) -> bool {
+        if entry_name == self.name {
+            return true;
+        }
+        #[cfg(feature = "std")]
+        match (&self.folded, core::str::from_utf8(entry_name)) {
+            (Some(folded), Ok(entry_name)) => {
+                fold_chars(entry_name).is_some_and(|chars| chars.eq(folded.chars()))
+            }
+            _ => false,
+        }
+        #[cfg(not(feature = "std"))]
+        false
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_casefold() {
+    let fold = |name| casefold(name).unwrap();
+    assert_eq!(fold("README.md"), "readme.md");
+    // Full folding expands ß, and precomposed and decomposed é agree
+    assert_eq!(fold("Straße"), fold("STRASSE"));
+    assert_eq!(fold("Caf\u{E9}"), fold("CAFE\u{301}"));
+    assert_eq!(fold("Caf\u{E9}"), "cafe\u{301}");
+    // Zero-width characters are ignored
+    assert_eq!(fold("a\u{200B}b"), "ab");
+    // Letters added after Unicode 12.1 are left to byte comparison, even
+    // where newer tables would fold them
+    assert_eq!(casefold("\u{A7C7}.txt"), None);
+
+    let lookup = LookupName::new("STRASSE".as_bytes(), true).unwrap();
+    assert!(lookup.matches("stra\u{DF}e".as_bytes()));
+    assert!(!lookup.matches(b"strasser"));
+    assert!(
+        !LookupName::new(b"README", false)
+            .unwrap()
+            .matches(b"readme")
+    );
+
+    let name = "A\u{1FAD0}";
+    let lookup = LookupName::new(name.as_bytes(), true).unwrap();
+    assert_eq!(lookup.hash_input(), name.as_bytes());
+    assert!(lookup.matches(name.as_bytes()));
+    assert!(!lookup.matches("a\u{1FAD0}".as_bytes()));
+    assert!(
+        !LookupName::new(b"a", true)
+            .unwrap()
+            .matches("A\u{1FAD0}".as_bytes())
+    );
+}
@@ -0,0 +1,547 @@
+use crate::io;
+#[cfg(feature = "std")]
+use crate::qcow2::Qcow2Image;
+#[cfg(feature = "std")]
+use crate::sparse::SparseImage;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use core::ops::Range;
+#[cfg(feature = "std")]
+use memmap2::Mmap;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+/// Longest chain of qcow2 backing files followed, which also stops loops
+#[cfg(feature = "std")]
+const MAX_BACKING_CHAIN: usize = 16;
+
+/// Device of filesystems opened by path, and the default device type
+#[cfg(feature = "std")]
+pub(crate) type DefaultDevice = DeviceWindow<ImageFile>;
+
+/// Without `std` there are no files, so the default is an in-memory image
+#[cfg(not(feature = "std"))]
+pub(crate) type DefaultDevice = Vec<u8>;
+
+/// Bytes read from a device: borrowed from a memory-mapped image, or a
+/// copy for devices that cannot lend out their storage
+pub enum BlockBuf {
+    /// Data copied out of the device
+    Owned(Vec<u8>),
+    /// A range of a shared mapping; cloning the mapping handle is cheap
+    #[cfg(feature = "std")]
+    Mapped(Arc<Mmap>, Range<usize>),
+}
+
+impl Deref for BlockBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            BlockBuf::Owned(data) => data,
+            #[cfg(feature = "std")]
+            BlockBuf::Mapped(map, range) => &map[range.clone()],
+        }
+    }
+}
+
+/// Storage that a filesystem is read from
+///
+/// Implemented for image files and block devices (`File`, `ImageFile`),
+/// Android sparse images (`SparseImage`), qcow2 disk images
+/// (`Qcow2Image`), in-memory images (`&[u8]`, `Vec<u8>`) and for a
+/// window into another device, such as one partition of a whole-disk
+/// image. Writing is optional and unsupported by default.
+///
+/// Reads take `&self` and must not depend on a shared file position, so a
+/// device that is `Sync` can serve several threads at once.
+pub trait BlockDevice {
+    /// Fill `buf` with the bytes starting at `offset`
+    ///
+    /// # Errors
+    /// Returns `UnexpectedEof` if the range extends past the end of the device
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Size of the device in bytes
+    fn size(&self) -> io::Result<u64>;
+
+    /// Read `len` bytes starting at `offset`
+    ///
+    /// Memory-backed devices return a view of their storage without
+    /// copying; the default reads into a new buffer.
+    fn read_buf(&self, offset: u64, len: usize) -> io::Result<BlockBuf> {
+        let mut buf = vec![0u8; len];
+        self.read_at(offset, &mut buf)?;
+        Ok(BlockBuf::Owned(buf))
+    }
+
+    /// Whether `read_buf` hands out views instead of copies, so caching
+    /// blocks in memory again would gain nothing
+    fn is_zero_copy(&self) -> bool {
+        false
+    }
+
+    /// Write all of `buf` starting at `offset`
+    fn write_at(&mut self, _offset: u64, _buf: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Device is read-only",
+        ))
+    }
+
+    /// Make previous writes durable
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads use positional I/O (`pread` on Unix) and leave the file position
+/// alone, so one `File` can be read from several threads
+#[cfg(feature = "std")]
+impl BlockDevice for File {
+    #[cfg(unix)]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        let mut offset = offset;
+        while !buf.is_empty() {
+            match std::os::windows::fs::FileExt::seek_read(self, buf, offset) {
+                Ok(0) => return Err(past_end()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Seeks to the end rather than using the metadata length, which is 0
+    /// for block devices; reads do not use the file position
+    fn size(&self) -> io::Result<u64> {
+        let mut file: &File = self;
+        file.seek(SeekFrom::End(0))
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sync_data()
+    }
+}
+
+impl BlockDevice for &[u8] {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        buf.copy_from_slice(slice_range(self, offset, buf.len())?);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+/// An in-memory image; writes must stay within its current size
+impl BlockDevice for Vec<u8> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        buf.copy_from_slice(slice_range(self, offset, buf.len())?);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        slice_range(self, offset, buf.len())?;
+        let start = offset as usize;
+        self[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for Box<D> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_at(offset, buf)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn read_buf(&self, offset: u64, len: usize) -> io::Result<BlockBuf> {
+        (**self).read_buf(offset, len)
+    }
+
+    fn is_zero_copy(&self) -> bool {
+        (**self).is_zero_copy()
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        (**self).write_at(offset, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// A read-only, memory-mapped image file or a byte range of one
+///
+/// Reads through [`BlockDevice::read_buf`] borrow from the mapping rather
+/// than allocating, which makes scanning a whole filesystem much cheaper.
+/// Clones share the mapping.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct MappedImage {
+    map: Arc<Mmap>,
+    range: Range<usize>,
+}
+
+#[cfg(feature = "std")]
+impl MappedImage {
+    /// Map an image file into memory
+    ///
+    /// # Arguments
+    /// * `path` - Path to the image file
+    ///
+    /// # Safety
+    /// The file must not be truncated or modified, by this or any other
+    /// process, for as long as the mapping or a view of it is alive. The
+    /// mapped bytes are handed out as `&[u8]`, so a change would break
+    /// their immutability, and a truncation makes reads fault with
+    /// `SIGBUS`. Use [`ImageFile`] for images that may change underneath.
+    pub unsafe fn open(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only, and the caller guarantees the
+        // file stays unchanged while it is mapped
+        let map = unsafe { Mmap::map(&file)? };
+        let range = 0..map.len();
+        Ok(Self {
+            map: Arc::new(map),
+            range,
+        })
+    }
+
+    /// A view of a byte range of this image sharing the same mapping
+    ///
+    /// # Errors
+    /// Returns `UnexpectedEof` if the range extends past the end of the image
+    pub fn slice(&self, offset: u64, len: u64) -> io::Result<Self> {
+        let range = self.range(offset, len as usize)?;
+        Ok(Self {
+            map: self.map.clone(),
+            range,
+        })
+    }
+
+    /// The mapped bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map[self.range.clone()]
+    }
+
+    /// Translate a range of this view to the mapping
+    fn range(&self, offset: u64, len: usize) -> io::Result<Range<usize>> {
+        let start = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.range.start.checked_add(offset))
+            .ok_or_else(past_end)?;
+        let end = start.checked_add(len).ok_or_else(past_end)?;
+        if end > self.range.end {
+            return Err(past_end());
+        }
+        Ok(start..end)
+    }
+}
+
+#[cfg(feature = "std")]
+impl BlockDevice for MappedImage {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let range = self.range(offset, buf.len())?;
+        buf.copy_from_slice(&self.map[range]);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.range.len() as u64)
+    }
+
+    fn read_buf(&self, offset: u64, len: usize) -> io::Result<BlockBuf> {
+        let range = self.range(offset, len)?;
+        Ok(BlockBuf::Mapped(self.map.clone(), range))
+    }
+
+    fn is_zero_copy(&self) -> bool {
+        true
+    }
+}
+
+/// What to do with the backing files named by qcow2 images
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackingFiles {
+    /// Open the backing file chain, as QEMU does
+    #[default]
+    Follow,
+    /// Leave backing files closed, so that reads of clusters not stored in
+    /// the image itself fail with `NotFound`
+    ///
+    /// For images from untrusted sources: a backing file name may be any
+    /// absolute path, so following it reads whatever file the image names.
+    Ignore,
+}
+
+/// An image file opened by path, in whichever container format it uses
+///
+/// Raw images and block devices are read directly; Android sparse images
+/// and qcow2 disk images are recognised by their header and read through
+/// [`SparseImage`] and [`Qcow2Image`].
+#[cfg(feature = "std")]
+pub enum ImageFile {
+    /// A raw image or block device
+    Raw(File),
+    /// An Android sparse image
+    Sparse(SparseImage<File>),
+    /// A qcow2 image, with its backing files opened
+    Qcow2(Qcow2Image<File>),
+}
+
+#[cfg(feature = "std")]
+impl ImageFile {
+    /// Open an image file and detect its format, following the backing
+    /// files of qcow2 images
+    ///
+    /// # Arguments
+    /// * `path` - Path to the image file or device
+    ///
+    /// # Errors
+    /// Returns `InvalidData` if the file looks like a container format but
+    /// its headers cannot be parsed
+    pub fn open(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        Self::open_with(path, BackingFiles::Follow)
+    }
+
+    /// Open an image file and detect its format, choosing whether the
+    /// backing files of qcow2 images are opened
+    ///
+    /// # Arguments
+    /// * `path` - Path to the image file or device
+    /// * `backing` - Whether to open backing files
+    ///
+    /// # Errors
+    /// As for [`open`](Self::open), and `Unsupported` if a backing file is
+    /// declared to be in a format other than raw or qcow2
+    pub fn open_with(path: impl AsRef<std::path::Path>, backing: BackingFiles) -> io::Result<Self> {
+        Self::open_chained(path.as_ref(), None, backing, 0)
+    }
+
+    /// Open an image that is the `depth`th backing file of another
+    ///
+    /// Backing files are opened as the format their overlay declares; only
+    /// the top image and backing files of undeclared format are probed.
+    fn open_chained(
+        path: &std::path::Path,
+        format: Option<&str>,
+        backing: BackingFiles,
+        depth: usize,
+    ) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let qcow2 = match format {
+            None if SparseImage::detect(&file)? => {
+                return Ok(ImageFile::Sparse(SparseImage::new(file)?));
+            }
+            None => Qcow2Image::detect(&file)?,
+            Some("raw") => false,
+            Some("qcow2") => true,
+            Some(format) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "Backing file '{}' has unsupported format '{}'",
+                        path.display(),
+                        format
+                    ),
+                ));
+            }
+        };
+        if !qcow2 {
+            return Ok(ImageFile::Raw(file));
+        }
+
+        let mut image = Qcow2Image::new(file)?;
+        if let (Some(name), BackingFiles::Follow) = (image.backing_file(), backing) {
+            if depth >= MAX_BACKING_CHAIN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Backing file chain of '{}' is too long", path.display()),
+                ));
+            }
+            // Relative names are relative to the image, as in QEMU
+            let backing_path = path.parent().unwrap_or(std::path::Path::new("")).join(name);
+            let backing_image =
+                Self::open_chained(&backing_path, image.backing_format(), backing, depth + 1)?;
+            image.set_backing(Box::new(backing_image));
+        }
+        Ok(ImageFile::Qcow2(image))
+    }
+}
+
+/// Writes are only supported on raw images
+#[cfg(feature = "std")]
+impl BlockDevice for ImageFile {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match self {
+            ImageFile::Raw(file) => file.read_at(offset, buf),
+            ImageFile::Sparse(image) => image.read_at(offset, buf),
+            ImageFile::Qcow2(image) => image.read_at(offset, buf),
+        }
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        match self {
+            ImageFile::Raw(file) => file.size(),
+            ImageFile::Sparse(image) => image.size(),
+            ImageFile::Qcow2(image) => image.size(),
+        }
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        match self {
+            ImageFile::Raw(file) => file.write_at(offset, buf),
+            ImageFile::Sparse(image) => image.write_at(offset, buf),
+            ImageFile::Qcow2(image) => image.write_at(offset, buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ImageFile::Raw(file) => BlockDevice::flush(file),
+            ImageFile::Sparse(image) => image.flush(),
+            ImageFile::Qcow2(image) => image.flush(),
+        }
+    }
+}
+
+/// Bounds-check a range of an in-memory image
+fn slice_range(data: &[u8], offset: u64, len: usize) -> io::Result<&[u8]> {
+    usize::try_from(offset)
+        .ok()
+        .and_then(|start| data.get(start..start.checked_add(len)?))
+        .ok_or_else(past_end)
+}
+
+pub(crate) fn past_end() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Read past the end of the device",
+    )
+}
+
+/// A byte range of another device, addressed from 0
+///
+/// Used to open a filesystem that lives in one partition of a larger disk
+/// image. Accesses beyond `len` fail instead of reaching the rest of the
+/// underlying device.
+pub struct DeviceWindow<D> {
+    inner: D,
+    offset: u64,
+    len: u64,
+}
+
+impl<D: BlockDevice> DeviceWindow<D> {
+    /// # Arguments
+    /// * `inner` - Underlying device
+    /// * `offset` - Byte offset of the window within `inner`
+    /// * `len` - Size of the window in bytes
+    pub fn new(inner: D, offset: u64, len: u64) -> Self {
+        Self { inner, offset, len }
+    }
+
+    /// Consume the window and return the underlying device
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Translate a range of the window to the underlying device
+    ///
+    /// # Errors
+    /// Returns `UnexpectedEof` past the window's end, or `InvalidData` if
+    /// the window itself lies beyond the largest offset
+    fn map(&self, offset: u64, len: usize) -> io::Result<u64> {
+        offset
+            .checked_add(len as u64)
+            .filter(|&end| end <= self.len)
+            .ok_or_else(past_end)?;
+        self.offset.checked_add(offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Device window lies past the largest offset",
+            )
+        })
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for DeviceWindow<D> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let offset = self.map(offset, buf.len())?;
+        self.inner.read_at(offset, buf)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+
+    fn read_buf(&self, offset: u64, len: usize) -> io::Result<BlockBuf> {
+        let offset = self.map(offset, len)?;
+        self.inner.read_buf(offset, len)
+    }
+
+    fn is_zero_copy(&self) -> bool {
+        self.inner.is_zero_copy()
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let offset = self.map(offset, buf.len())?;
+        self.inner.write_at(offset, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_device_window() {
+    let data: Vec<u8> = (0..=255).collect();
+    let mut window = DeviceWindow::new(data.as_slice(), 16, 32);
+    assert_eq!(window.size().unwrap(), 32);
+
+    let mut buf = [0u8; 4];
+    window.read_at(28, &mut buf).unwrap();
+    assert_eq!(buf, [44, 45, 46, 47]);
+
+    // Reads may not leave the window, even though the device is larger
+    let err = window.read_at(30, &mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(window.write_at(0, &buf).is_err());
+
+    let mut window = DeviceWindow::new(data.clone(), 16, 32);
+    window.write_at(0, &[1, 2]).unwrap();
+    assert_eq!(window.into_inner()[15..19], [15, 1, 2, 18]);
+}
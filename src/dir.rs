@@ -1,25 +1,148 @@
+use crate::inode::EXT4_MIN_INLINE_DATA_SIZE;
+use crate::metadata::FileType;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
 const DIR_OFFSET_INODE: usize = 0; // Inode Number (4 bytes)
 const DIR_OFFSET_REC_LEN: usize = 4; // Record Length (2 bytes)
 const DIR_OFFSET_NAME_LEN: usize = 6; // Name Length (1 byte)
 const DIR_OFFSET_FILE_TYPE: usize = 7; // File Type (1 byte)
 const DIR_OFFSET_NAME: usize = 8; // File Name (variable length)
 
+/// Size of the `ext4_dir_entry_tail` holding a leaf block's checksum
+pub(crate) const EXT4_DIR_ENTRY_TAIL_SIZE: usize = 12;
+
+/// File type byte that marks a directory entry tail
+const EXT4_DIR_TAIL_FT: u8 = 0xDE;
+
+/// Size of the parent inode header at the start of an inline directory
+const INLINE_DIR_OFFSET_ENTRIES: usize = 4;
+
 // EXT4 file type constants
 const EXT4_FT_REG_FILE: u8 = 1; // Regular file
-const EXT4_FT_DIR: u8 = 2; // Directory
+pub(crate) const EXT4_FT_DIR: u8 = 2; // Directory
 const EXT4_FT_CHRDEV: u8 = 3; // Character device
 const EXT4_FT_BLKDEV: u8 = 4; // Block device
 const EXT4_FT_FIFO: u8 = 5; // FIFO
 const EXT4_FT_SOCK: u8 = 6; // Socket
 const EXT4_FT_SYMLINK: u8 = 7; // Symbolic link
 
+/// Smallest valid record: the header plus a name padded to 4 bytes
+const EXT4_DIR_REC_MIN_LEN: usize = 12;
+
+/// `rec_len` values that stand for 65536 in 64KiB blocks
+const EXT4_MAX_REC_LEN: u16 = 0xFFFF;
+
+/// Largest file type value defined for directory entries
+const EXT4_FT_MAX: u8 = EXT4_FT_SYMLINK;
+
+/// Space a record with a name of `name_len` bytes actually needs
+fn record_size(name_len: usize) -> usize {
+    (DIR_OFFSET_NAME + name_len + 3) & !3
+}
+
+/// Header of one on-disk directory record, used or not
+#[derive(Debug, Clone, Copy)]
+struct DirRecord {
+    /// Inode number, 0 for padding and deleted slots
+    inode: u32,
+    /// Distance to the next record
+    rec_len: usize,
+    /// Length of the name following the header
+    name_len: usize,
+    /// File type
+    file_type: u8,
+}
+
+impl DirRecord {
+    /// Parse and validate the record at the start of `buf`
+    ///
+    /// Applies the kernel's `ext4_check_dir_entry()` rules: `rec_len` is a
+    /// multiple of 4, holds at least the header and name, and does not run
+    /// past the end of `buf`.
+    fn parse(buf: &[u8]) -> Option<Self> {
+        Self::read_header(buf).filter(|record| record.rec_len <= buf.len())
+    }
+
+    /// Parse an old record found in slack space
+    ///
+    /// Its `rec_len` may reach past the slack, but the name must fit and
+    /// look like a real file name rather than leftover bytes.
+    fn parse_slack(buf: &[u8]) -> Option<Self> {
+        let record = Self::read_header(buf)?;
+        let name = buf.get(DIR_OFFSET_NAME..DIR_OFFSET_NAME + record.name_len)?;
+        let plausible = record.inode != 0
+            && record.name_len > 0
+            && record.file_type <= EXT4_FT_MAX
+            && !name.iter().any(|&b| b == 0 || b == b'/');
+        plausible.then_some(record)
+    }
+
+    /// Read a record header, checking `rec_len` against the name length
+    fn read_header(buf: &[u8]) -> Option<Self> {
+        let header = buf.get(..DIR_OFFSET_NAME)?;
+
+        // Read inode number (4 bytes)
+        let inode = u32::from_le_bytes(
+            header[DIR_OFFSET_INODE..DIR_OFFSET_REC_LEN]
+                .try_into()
+                .ok()?,
+        );
+
+        // Read record length (2 bytes), where 64KiB blocks encode a full
+        // block as 0 or 0xFFFF
+        let rec_len = match u16::from_le_bytes(
+            header[DIR_OFFSET_REC_LEN..DIR_OFFSET_NAME_LEN]
+                .try_into()
+                .ok()?,
+        ) {
+            0 | EXT4_MAX_REC_LEN => 1 << 16,
+            rec_len => rec_len as usize,
+        };
+
+        // Read name length and file type (1 byte each)
+        let name_len = header[DIR_OFFSET_NAME_LEN] as usize;
+        let file_type = header[DIR_OFFSET_FILE_TYPE];
+
+        if rec_len < EXT4_DIR_REC_MIN_LEN || rec_len % 4 != 0 || rec_len < record_size(name_len) {
+            return None;
+        }
+
+        Some(Self {
+            inode,
+            rec_len,
+            name_len,
+            file_type,
+        })
+    }
+
+    /// Build the directory entry described by this record
+    ///
+    /// # Arguments
+    /// * `buf` - Buffer starting at the record
+    fn entry(&self, buf: &[u8]) -> DirectoryEntry {
+        // Extract filename (not null-terminated in ext4)
+        DirectoryEntry {
+            inode: self.inode,
+            name: buf[DIR_OFFSET_NAME..DIR_OFFSET_NAME + self.name_len].to_vec(),
+            file_type: self.file_type,
+        }
+    }
+}
+
 /// Represents a single directory entry in an EXT4 filesystem
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DirectoryEntry {
     /// Inode number
     pub inode: u32,
-    /// File or directory name
-    pub name: String,
+    /// File or directory name, as stored on disk
+    ///
+    /// ext4 names are arbitrary bytes other than `/` and NUL and need not
+    /// be valid UTF-8; use `name_lossy` or `escape_name` to display them.
+    pub name: Vec<u8>,
     /// File type
     pub file_type: u8,
 }
@@ -32,87 +155,67 @@ impl DirectoryEntry {
     ///
     /// # Returns
     /// `Some((entry, size))` if successful, where size is the entry length in bytes.
-    /// `None` if the data is invalid or insufficient.
+    /// `None` if the data is invalid or insufficient, or the record is unused
+    /// (inode 0).
     pub fn parse(buf: &[u8]) -> Option<(Self, usize)> {
-        // Check minimum size for header fields
-        if buf.len() < DIR_OFFSET_NAME {
+        let record = DirRecord::parse(buf)?;
+        if record.inode == 0 {
             return None;
         }
+        Some((record.entry(buf), record.rec_len))
+    }
 
-        // Read inode number (4 bytes)
-        let inode = u32::from_le_bytes(buf[DIR_OFFSET_INODE..DIR_OFFSET_REC_LEN].try_into().ok()?);
+    /// The name as text, with invalid UTF-8 replaced by U+FFFD
+    pub fn name_lossy(&self) -> alloc::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
 
-        // Read record length (2 bytes)
-        let rec_len = u16::from_le_bytes(
-            buf[DIR_OFFSET_REC_LEN..DIR_OFFSET_NAME_LEN]
-                .try_into()
-                .ok()?,
-        ) as usize;
-
-        // Read name length (1 byte)
-        let name_len = buf[DIR_OFFSET_NAME_LEN] as usize;
-
-        // Read file type (1 byte)
-        let file_type = buf[DIR_OFFSET_FILE_TYPE];
-
-        // Validate entry integrity
-        // - inode must be non-zero
-        // - rec_len must be non-zero
-        // - buffer must be large enough to contain both the name and full entry
-        if inode == 0
-            || rec_len == 0
-            || buf.len() < DIR_OFFSET_NAME + name_len
-            || buf.len() < rec_len
-        {
-            return None;
+    /// The file type recorded in the directory entry
+    pub fn file_type(&self) -> FileType {
+        match self.file_type {
+            EXT4_FT_REG_FILE => FileType::RegularFile,
+            EXT4_FT_DIR => FileType::Directory,
+            EXT4_FT_CHRDEV => FileType::CharDevice,
+            EXT4_FT_BLKDEV => FileType::BlockDevice,
+            EXT4_FT_FIFO => FileType::Fifo,
+            EXT4_FT_SOCK => FileType::Socket,
+            EXT4_FT_SYMLINK => FileType::Symlink,
+            _ => FileType::Unknown,
         }
-
-        // Extract filename (not null-terminated in ext4)
-        let name =
-            String::from_utf8_lossy(&buf[DIR_OFFSET_NAME..DIR_OFFSET_NAME + name_len]).to_string();
-
-        Some((
-            DirectoryEntry {
-                inode,
-                name,
-                file_type,
-            },
-            rec_len,
-        ))
     }
 
     /// Check if this entry represents a directory
-    pub(crate) fn is_directory(&self) -> bool {
+    pub fn is_directory(&self) -> bool {
         self.file_type == EXT4_FT_DIR
     }
 
     /// Check if this entry represents a regular file
-    pub(crate) fn is_file(&self) -> bool {
+    pub fn is_file(&self) -> bool {
         self.file_type == EXT4_FT_REG_FILE
     }
 
     /// Check if this entry represents a symbolic link
-    pub(crate) fn is_symlink(&self) -> bool {
+    pub fn is_symlink(&self) -> bool {
         self.file_type == EXT4_FT_SYMLINK
     }
 
     /// Check if this entry represents a character device
-    pub(crate) fn is_char_device(&self) -> bool {
+    pub fn is_char_device(&self) -> bool {
         self.file_type == EXT4_FT_CHRDEV
     }
 
     /// Check if this entry represents a block device
-    pub(crate) fn is_block_device(&self) -> bool {
+    pub fn is_block_device(&self) -> bool {
         self.file_type == EXT4_FT_BLKDEV
     }
 
     /// Check if this entry represents a FIFO
-    pub(crate) fn is_fifo(&self) -> bool {
+    pub fn is_fifo(&self) -> bool {
         self.file_type == EXT4_FT_FIFO
     }
 
     /// Check if this entry represents a socket
-    pub(crate) fn is_socket(&self) -> bool {
+    pub fn is_socket(&self) -> bool {
         self.file_type == EXT4_FT_SOCK
     }
 
@@ -131,14 +234,277 @@ impl DirectoryEntry {
     }
 }
 
-impl std::fmt::Display for DirectoryEntry {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// Parse all directory entries packed into a buffer
+///
+/// Every record is visited by following `rec_len` to the end of the buffer;
+/// records with inode 0 (padding and deleted slots) are skipped.
+///
+/// # Arguments
+/// * `buf` - A directory data block, or one region of an inline directory
+///
+/// # Returns
+/// Live entries in on-disk order, or `None` if a record is malformed.
+/// A trailing checksum tail is never returned as an entry.
+pub(crate) fn parse_dir_block(buf: &[u8]) -> Option<Vec<DirectoryEntry>> {
+    let mut entries = Vec::new();
+    for_each_record(buf, |record, rest| {
+        if record.inode != 0 {
+            entries.push(record.entry(rest));
+        }
+    })?;
+    Some(entries)
+}
+
+/// Recover deleted entries whose names are still visible in a buffer
+///
+/// When the kernel deletes an entry it either zeroes the inode of the
+/// first record in the block, or folds the record into the `rec_len` of
+/// the one before it. Both leave the old name on disk: the first as an
+/// inode-0 record, the second in the slack between the end of a record's
+/// name and the start of the next record. Slack is searched at 4-byte
+/// alignment for records that look intact.
+///
+/// # Arguments
+/// * `buf` - A directory leaf block, or one region of an inline directory
+///
+/// # Returns
+/// Recovered entries in on-disk order, or `None` if a live record is
+/// malformed. Entries recovered from an inode-0 record have inode 0.
+pub(crate) fn parse_deleted_entries(buf: &[u8]) -> Option<Vec<DirectoryEntry>> {
+    let mut entries = Vec::new();
+    for_each_record(buf, |record, rest| {
+        if record.inode == 0 && record.name_len > 0 {
+            entries.push(record.entry(rest));
+        }
+
+        let mut pos = record_size(record.name_len);
+        while pos + EXT4_DIR_REC_MIN_LEN <= record.rec_len {
+            let slack = &rest[pos..record.rec_len];
+            match DirRecord::parse_slack(slack) {
+                Some(old) => {
+                    entries.push(old.entry(slack));
+                    pos += record_size(old.name_len);
+                }
+                None => pos += 4,
+            }
+        }
+    })?;
+    Some(entries)
+}
+
+/// Visit every record of a buffer in order, excluding a checksum tail
+///
+/// # Returns
+/// `None` if a record fails validation
+fn for_each_record(buf: &[u8], mut visit: impl FnMut(&DirRecord, &[u8])) -> Option<()> {
+    let buf = match dir_entry_tail(buf) {
+        Some(_) => &buf[..buf.len() - EXT4_DIR_ENTRY_TAIL_SIZE],
+        None => buf,
+    };
+
+    let mut offset = 0;
+    while offset < buf.len() {
+        let rest = &buf[offset..];
+        let record = DirRecord::parse(rest)?;
+        visit(&record, rest);
+        offset += record.rec_len;
+    }
+    Some(())
+}
+
+/// Find the checksum tail at the end of a directory leaf block
+///
+/// With `metadata_csum`, the last 12 bytes of each leaf block form a fake
+/// entry with inode 0, `rec_len` 12, name length 0 and file type 0xDE,
+/// followed by the block's crc32c.
+///
+/// # Arguments
+/// * `buf` - A full directory block
+///
+/// # Returns
+/// The stored checksum, or `None` if the block does not end in a tail
+pub(crate) fn dir_entry_tail(buf: &[u8]) -> Option<u32> {
+    let tail = buf.get(buf.len().checked_sub(EXT4_DIR_ENTRY_TAIL_SIZE)?..)?;
+    let inode = u32::from_le_bytes(tail[DIR_OFFSET_INODE..DIR_OFFSET_REC_LEN].try_into().ok()?);
+    let rec_len = u16::from_le_bytes(
+        tail[DIR_OFFSET_REC_LEN..DIR_OFFSET_NAME_LEN]
+            .try_into()
+            .ok()?,
+    ) as usize;
+
+    if inode != 0
+        || rec_len != EXT4_DIR_ENTRY_TAIL_SIZE
+        || tail[DIR_OFFSET_NAME_LEN] != 0
+        || tail[DIR_OFFSET_FILE_TYPE] != EXT4_DIR_TAIL_FT
+    {
+        return None;
+    }
+    Some(u32::from_le_bytes(tail[DIR_OFFSET_NAME..].try_into().ok()?))
+}
+
+/// Parse an inline directory stored in `i_block` and the `system.data` xattr
+///
+/// Inline directories have no `.` and `..` records; instead `i_block` starts
+/// with the parent's inode number. Both entries are synthesized here so
+/// callers see the same listing as for block-based directories.
+///
+/// # Arguments
+/// * `inode_num` - Inode number of the directory itself
+/// * `data` - Inline data: the 60-byte `i_block` followed by the xattr value
+///
+/// # Returns
+/// All entries, or `None` if a record is malformed
+pub(crate) fn parse_inline_dir(inode_num: u32, data: &[u8]) -> Option<Vec<DirectoryEntry>> {
+    if data.len() < INLINE_DIR_OFFSET_ENTRIES {
+        return Some(Vec::new());
+    }
+
+    let parent = u32::from_le_bytes(data[..INLINE_DIR_OFFSET_ENTRIES].try_into().unwrap());
+    let mut entries = vec![
+        DirectoryEntry {
+            inode: inode_num,
+            name: b".".to_vec(),
+            file_type: EXT4_FT_DIR,
+        },
+        DirectoryEntry {
+            inode: parent,
+            name: b"..".to_vec(),
+            file_type: EXT4_FT_DIR,
+        },
+    ];
+    for region in inline_dir_regions(data) {
+        entries.extend(parse_dir_block(region)?);
+    }
+    Some(entries)
+}
+
+/// Split inline directory data into its separately packed record regions
+///
+/// Entries in `i_block` (after the parent header) and in the xattr
+/// continuation each have records spanning to the end of their own region.
+pub(crate) fn inline_dir_regions(data: &[u8]) -> [&[u8]; 2] {
+    let start = data.len().min(INLINE_DIR_OFFSET_ENTRIES);
+    let split = data.len().min(EXT4_MIN_INLINE_DATA_SIZE);
+    [&data[start..split], &data[split..]]
+}
+
+/// Render a name as text that `unescape_name` turns back into the same bytes
+///
+/// Valid UTF-8 is kept as is, with `\` doubled; every byte of an invalid
+/// sequence is written as `\xNN`.
+pub fn escape_name(name: &[u8]) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for chunk in name.utf8_chunks() {
+        escaped.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+/// Decode a name or path written with the escapes of `escape_name`
+///
+/// `\\` becomes a single backslash and `\xNN` the byte `NN`; any other
+/// backslash is kept literally.
+pub fn unescape_name(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut name = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 2..i + 4)
+            .and_then(|hex| core::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], bytes.get(i + 1), hex) {
+            (b'\\', Some(b'\\'), _) => {
+                name.push(b'\\');
+                i += 2;
+            }
+            (b'\\', Some(b'x'), Some(byte)) => {
+                name.push(byte);
+                i += 4;
+            }
+            (byte, _, _) => {
+                name.push(byte);
+                i += 1;
+            }
+        }
+    }
+    name
+}
+
+impl core::fmt::Display for DirectoryEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
             "{:<12} {:<6} {}",
             self.file_type_str(),
             self.inode,
-            self.name
+            escape_name(&self.name)
         )
     }
 }
+
+#[test]
+fn test_parse_dir_block_records() {
+    // Builds a record; `rec_len` 0 means "just large enough"
+    fn record(inode: u32, rec_len: usize, name: &str) -> Vec<u8> {
+        let rec_len = if rec_len == 0 {
+            record_size(name.len())
+        } else {
+            rec_len
+        };
+        let mut buf = vec![0u8; rec_len];
+        buf[..4].copy_from_slice(&inode.to_le_bytes());
+        buf[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+        buf[6] = name.len() as u8;
+        buf[7] = EXT4_FT_REG_FILE;
+        buf[8..8 + name.len()].copy_from_slice(name.as_bytes());
+        buf
+    }
+
+    // A cleared first record, a live one, and one whose slack still holds
+    // an entry folded into it
+    let mut block = record(0, 0, "first");
+    block.extend(record(12, 0, "live"));
+    let mut last = record(13, 64, "last");
+    let old = record(14, 0, "old");
+    last[record_size(4)..record_size(4) + old.len()].copy_from_slice(&old);
+    block.extend(last);
+
+    let names = |entries: Vec<DirectoryEntry>| -> Vec<String> {
+        entries
+            .iter()
+            .map(|e| e.name_lossy().into_owned())
+            .collect()
+    };
+    assert_eq!(names(parse_dir_block(&block).unwrap()), ["live", "last"]);
+    assert_eq!(
+        names(parse_deleted_entries(&block).unwrap()),
+        ["first", "old"]
+    );
+
+    // Misaligned and overlong rec_len are rejected
+    let mut bad = block.clone();
+    bad[4] = 18;
+    assert!(parse_dir_block(&bad).is_none());
+    let mut bad = block;
+    let last_offset = record_size(5) + record_size(4);
+    bad[last_offset + 4] = 68;
+    assert!(parse_dir_block(&bad).is_none());
+}
+
+#[test]
+fn test_escape_name() {
+    let cases: [&[u8]; 4] = [
+        b"plain.txt",
+        "caf\u{e9}".as_bytes(),
+        b"back\\slash",
+        b"bad\xff\xfe",
+    ];
+    for name in cases {
+        assert_eq!(unescape_name(&escape_name(name)), name);
+    }
+    assert_eq!(escape_name(b"bad\xff\\x"), "bad\\xff\\\\x");
+}
@@ -0,0 +1,187 @@
+use crate::FileSystem;
+use crate::device::{BlockDevice, DefaultDevice};
+use crate::inode::{Extent, Inode};
+use crate::io;
+
+use alloc::format;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom};
+
+/// An open file whose content is read on demand
+///
+/// Created by [`FileSystem::open_file`]. Reads only fetch the blocks they
+/// cover, and the extent tree is descended lazily, one leaf at a time, as
+/// the reads move through the file.
+pub struct Ext4File<'a, D: BlockDevice = DefaultDevice> {
+    fs: &'a FileSystem<D>,
+    inode_num: u32,
+    inode: Inode,
+    /// Extents of the most recently visited leaf of the extent tree
+    leaf: Vec<Extent>,
+    /// First logical block past the cached leaf's subtree, or `None` if no
+    /// extent follows it
+    leaf_end: Option<u32>,
+    /// Position used by `Read` and `Seek`, which need `std`
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pos: u64,
+}
+
+impl<'a, D: BlockDevice> Ext4File<'a, D> {
+    pub(crate) fn new(fs: &'a FileSystem<D>, inode_num: u32, inode: Inode) -> Self {
+        Self {
+            fs,
+            inode_num,
+            inode,
+            leaf: Vec::new(),
+            leaf_end: None,
+            pos: 0,
+        }
+    }
+
+    /// Size of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.inode.inode_size
+    }
+
+    /// Whether the file is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read from an offset without moving the position used by `Read`
+    ///
+    /// Holes and unwritten extents read as zeros.
+    ///
+    /// # Arguments
+    /// * `buf` - Buffer to fill
+    /// * `offset` - Byte offset within the file
+    ///
+    /// # Returns
+    /// Number of bytes read: `buf.len()` unless the end of the file is
+    /// reached first, and 0 at or past the end
+    ///
+    /// # Errors
+    /// Returns error if an extent tree block is invalid or the device read fails
+    pub fn read_at(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let size = self.len();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = (size - offset).min(buf.len() as u64) as usize;
+        let buf = &mut buf[..len];
+
+        // Inline data is already in memory
+        if let Some(data) = &self.inode.inline_data {
+            let data = data.get(offset as usize..).unwrap_or_default();
+            let copied = data.len().min(len);
+            buf[..copied].copy_from_slice(&data[..copied]);
+            buf[copied..].fill(0);
+            return Ok(len);
+        }
+
+        let block_size = self.fs.superblock.block_size() as u64;
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let within = pos % block_size;
+            let Ok(logical_block) = u32::try_from(pos / block_size) else {
+                // Past the largest logical block, so nothing can be mapped
+                buf[done..].fill(0);
+                break;
+            };
+
+            // Read up to the end of the extent, or zero-fill a hole up to
+            // the next extent of the leaf or the subtree after it
+            let (physical, run_blocks) = match self.extent_for(logical_block)? {
+                Some(extent) => (
+                    extent.map(logical_block).filter(|_| !extent.is_unwritten()),
+                    extent.logical_block as u64 + extent.len() as u64 - logical_block as u64,
+                ),
+                None => {
+                    let next = self
+                        .leaf
+                        .iter()
+                        .map(|extent| extent.logical_block)
+                        .filter(|&start| start > logical_block)
+                        .min()
+                        .or(self.leaf_end);
+                    let run_blocks = match next {
+                        Some(next) => (next - logical_block) as u64,
+                        // Nothing is mapped past here, so zero the rest
+                        None => (len - done) as u64 / block_size + 1,
+                    };
+                    (None, run_blocks)
+                }
+            };
+
+            let run = (run_blocks * block_size - within).min((len - done) as u64) as usize;
+            let chunk = &mut buf[done..done + run];
+            match physical {
+                Some(block) => {
+                    let byte_offset = block
+                        .checked_mul(block_size)
+                        .and_then(|start| start.checked_add(within))
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("Extent block {} lies past the largest offset", block),
+                            )
+                        })?;
+                    self.fs.device.read_uncached(byte_offset, chunk)?
+                }
+                None => chunk.fill(0),
+            }
+            done += run;
+        }
+        Ok(len)
+    }
+
+    /// The extent mapping a logical block, or `None` for a hole
+    ///
+    /// The cached leaf is reused when it covers the block; otherwise the
+    /// tree is descended again and the leaf reached is cached instead.
+    fn extent_for(&mut self, logical_block: u32) -> io::Result<Option<Extent>> {
+        let find = |leaf: &[Extent]| {
+            leaf.iter()
+                .find(|extent| extent.map(logical_block).is_some())
+                .cloned()
+        };
+
+        if let Some(extent) = find(&self.leaf) {
+            return Ok(Some(extent));
+        }
+        (self.leaf, self.leaf_end) =
+            self.fs
+                .find_extent_leaf(self.inode_num, &self.inode, logical_block)?;
+        Ok(find(&self.leaf))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: BlockDevice> Read for Ext4File<'_, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_at(buf, self.pos)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: BlockDevice> Seek for Ext4File<'_, D> {
+    /// Seeking past the end is allowed; reads there return 0 bytes
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::End(delta) => (self.len(), delta),
+            SeekFrom::Current(delta) => (self.pos, delta),
+        };
+        self.pos = base.checked_add_signed(delta).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
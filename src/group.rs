@@ -1,29 +1,75 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::Cursor;
+use crate::bytes::Cursor;
 
-/// Offset of the inode table block field in a 32-byte group descriptor.
+use byteorder::LittleEndian;
+
+/// Offset of the block bitmap block field in a group descriptor.
+const GROUP_DESC_OFFSET_BLOCK_BITMAP: u64 = 0x00;
+
+/// Offset of the inode bitmap block field in a group descriptor.
+const GROUP_DESC_OFFSET_INODE_BITMAP: u64 = 0x04;
+
+/// Offset of the inode table block field in a group descriptor.
 const GROUP_DESC_OFFSET_INODE_TABLE_BLOCK: u64 = 0x08;
 
+/// Offsets of the high 32 bits of the fields above (64-byte descriptors only).
+const GROUP_DESC_OFFSET_BLOCK_BITMAP_HI: u64 = 0x20;
+const GROUP_DESC_OFFSET_INODE_BITMAP_HI: u64 = 0x24;
+const GROUP_DESC_OFFSET_INODE_TABLE_BLOCK_HI: u64 = 0x28;
+
+/// Size of a descriptor without the `64bit` feature
+pub(crate) const EXT4_MIN_DESC_SIZE: usize = 32;
+
+/// Smallest descriptor size that carries the `_hi` fields
+pub(crate) const EXT4_MIN_DESC_SIZE_64BIT: usize = 64;
+
 /// Represents a single ext4 block group descriptor.
 /// Each block group has its own inode table.
 #[derive(Debug)]
 pub(crate) struct GroupDescriptor {
+    /// Block number of this group's block bitmap
+    pub(crate) block_bitmap: u64,
+    /// Block number of this group's inode bitmap
+    pub(crate) inode_bitmap: u64,
     /// Block number where this group's inode table starts
-    pub(crate) inode_table_block: u32,
+    pub(crate) inode_table_block: u64,
 }
 
 impl GroupDescriptor {
-    /// Parses a 32-byte ext4 group descriptor
+    /// Parses an ext4 group descriptor
     ///
     /// # Arguments
-    /// * `buf` - A byte slice containing one group descriptor (must be at least 12 bytes)
+    /// * `buf` - A byte slice containing one group descriptor: 32 bytes, or
+    ///   the superblock's `s_desc_size` with the `64bit` feature
     ///
     /// # Returns
-    /// Parsed `GroupDescriptor` with the inode table block number
+    /// Parsed `GroupDescriptor` with the bitmap and inode table block numbers
     pub(crate) fn parse(buf: &[u8]) -> Self {
         let mut rdr = Cursor::new(buf);
-        rdr.set_position(GROUP_DESC_OFFSET_INODE_TABLE_BLOCK);
-        let inode_table_block = rdr.read_u32::<LittleEndian>().unwrap();
-        Self { inode_table_block }
+        let mut read_block = |lo: u64, hi: u64| {
+            rdr.set_position(lo);
+            let lo = rdr.read_u32::<LittleEndian>().unwrap();
+            let hi = if buf.len() >= EXT4_MIN_DESC_SIZE_64BIT {
+                rdr.set_position(hi);
+                rdr.read_u32::<LittleEndian>().unwrap()
+            } else {
+                0
+            };
+            ((hi as u64) << 32) | lo as u64
+        };
+
+        Self {
+            block_bitmap: read_block(
+                GROUP_DESC_OFFSET_BLOCK_BITMAP,
+                GROUP_DESC_OFFSET_BLOCK_BITMAP_HI,
+            ),
+            inode_bitmap: read_block(
+                GROUP_DESC_OFFSET_INODE_BITMAP,
+                GROUP_DESC_OFFSET_INODE_BITMAP_HI,
+            ),
+            inode_table_block: read_block(
+                GROUP_DESC_OFFSET_INODE_TABLE_BLOCK,
+                GROUP_DESC_OFFSET_INODE_TABLE_BLOCK_HI,
+            ),
+        }
     }
 }
@@ -0,0 +1,282 @@
+/// Directory hash algorithms as stored in `dx_root_info.hash_version`
+const DX_HASH_LEGACY: u8 = 0;
+const DX_HASH_HALF_MD4: u8 = 1;
+const DX_HASH_TEA: u8 = 2;
+const DX_HASH_LEGACY_UNSIGNED: u8 = 3;
+const DX_HASH_HALF_MD4_UNSIGNED: u8 = 4;
+const DX_HASH_TEA_UNSIGNED: u8 = 5;
+
+/// Offset added to a signed hash version when chars are hashed as unsigned
+const DX_HASH_UNSIGNED_DELTA: u8 = 3;
+
+/// Largest 32-bit hash, reserved as the end-of-directory marker
+const EXT4_HTREE_EOF_32BIT: u32 = 0x7FFFFFFF;
+
+/// MD4 initial state, used when `s_hash_seed` is all zeros
+const DX_HASH_DEFAULT_SEED: [u32; 4] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476];
+
+/// Half-MD4 round constants
+const K2: u32 = 0o13240474631;
+const K3: u32 = 0o15666365641;
+
+/// TEA key schedule constant
+const TEA_DELTA: u32 = 0x9E3779B9;
+
+/// Hash function used to index a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DxHashVersion {
+    Legacy,
+    HalfMd4,
+    Tea,
+    LegacyUnsigned,
+    HalfMd4Unsigned,
+    TeaUnsigned,
+}
+
+impl DxHashVersion {
+    /// Decode the hash version of a directory index
+    ///
+    /// # Arguments
+    /// * `version` - `dx_root_info.hash_version`
+    /// * `unsigned` - Whether the filesystem hashes chars as unsigned
+    ///   (`EXT2_FLAGS_UNSIGNED_HASH`); only applies to the signed versions
+    ///
+    /// # Returns
+    /// The hash function, or `None` for unsupported ones such as SipHash
+    pub(crate) fn from_raw(version: u8, unsigned: bool) -> Option<Self> {
+        let version = if unsigned && version <= DX_HASH_TEA {
+            version + DX_HASH_UNSIGNED_DELTA
+        } else {
+            version
+        };
+        match version {
+            DX_HASH_LEGACY => Some(Self::Legacy),
+            DX_HASH_HALF_MD4 => Some(Self::HalfMd4),
+            DX_HASH_TEA => Some(Self::Tea),
+            DX_HASH_LEGACY_UNSIGNED => Some(Self::LegacyUnsigned),
+            DX_HASH_HALF_MD4_UNSIGNED => Some(Self::HalfMd4Unsigned),
+            DX_HASH_TEA_UNSIGNED => Some(Self::TeaUnsigned),
+            _ => None,
+        }
+    }
+
+    fn is_unsigned(self) -> bool {
+        matches!(
+            self,
+            Self::LegacyUnsigned | Self::HalfMd4Unsigned | Self::TeaUnsigned
+        )
+    }
+}
+
+/// Compute the directory hash of a file name, as the kernel's `ext4fs_dirhash()`
+///
+/// # Arguments
+/// * `name` - File name bytes
+/// * `version` - Hash function of the directory index
+/// * `seed` - `s_hash_seed` from the superblock; all zeros selects the default
+///
+/// # Returns
+/// `(major, minor)` hashes; only the major hash is stored in index entries
+pub(crate) fn dx_hash(name: &[u8], version: DxHashVersion, seed: &[u32; 4]) -> (u32, u32) {
+    let mut buf = if seed.iter().any(|&s| s != 0) {
+        *seed
+    } else {
+        DX_HASH_DEFAULT_SEED
+    };
+    let unsigned = version.is_unsigned();
+
+    let (hash, minor) = match version {
+        DxHashVersion::Legacy | DxHashVersion::LegacyUnsigned => (dx_hack_hash(name, unsigned), 0),
+        DxHashVersion::HalfMd4 | DxHashVersion::HalfMd4Unsigned => {
+            let mut input = [0u32; 8];
+            for chunk in chunks(name, 32) {
+                str2hashbuf(chunk, &mut input, unsigned);
+                half_md4_transform(&mut buf, &input);
+            }
+            (buf[1], buf[2])
+        }
+        DxHashVersion::Tea | DxHashVersion::TeaUnsigned => {
+            let mut input = [0u32; 4];
+            for chunk in chunks(name, 16) {
+                str2hashbuf(chunk, &mut input, unsigned);
+                tea_transform(&mut buf, &input);
+            }
+            (buf[0], buf[1])
+        }
+    };
+
+    // The low bit flags hash collisions in index entries
+    let hash = hash & !1;
+    if hash == EXT4_HTREE_EOF_32BIT << 1 {
+        ((EXT4_HTREE_EOF_32BIT - 1) << 1, minor)
+    } else {
+        (hash, minor)
+    }
+}
+
+/// Successive tails of `name`, advancing `step` bytes each time
+///
+/// Each tail is passed whole to `str2hashbuf`, whose padding depends on the
+/// number of bytes remaining rather than the size of the current block.
+fn chunks(name: &[u8], step: usize) -> impl Iterator<Item = &[u8]> {
+    (0..name.len())
+        .step_by(step)
+        .map(move |start| &name[start..])
+}
+
+/// Sign- or zero-extend a name byte the way C's `char` would be
+fn char_value(byte: u8, unsigned: bool) -> u32 {
+    if unsigned {
+        byte as u32
+    } else {
+        byte as i8 as i32 as u32
+    }
+}
+
+/// The original ext3 htree hash
+fn dx_hack_hash(name: &[u8], unsigned: bool) -> u32 {
+    let (mut hash0, mut hash1): (u32, u32) = (0x12A3FE2D, 0x37ABE8F9);
+    for &byte in name {
+        let mut hash = hash1.wrapping_add(hash0 ^ char_value(byte, unsigned).wrapping_mul(7152373));
+        if hash & 0x80000000 != 0 {
+            hash = hash.wrapping_sub(0x7FFFFFFF);
+        }
+        hash1 = hash0;
+        hash0 = hash;
+    }
+    hash0 << 1
+}
+
+/// Pack the start of `msg` into `buf` as big-endian-ish words, padded with
+/// its remaining length
+fn str2hashbuf(msg: &[u8], buf: &mut [u32], unsigned: bool) {
+    let len = msg.len() as u32;
+    let mut pad = len | (len << 8);
+    pad |= pad << 16;
+
+    let mut words = 0;
+    let mut val = pad;
+    for (i, &byte) in msg.iter().take(buf.len() * 4).enumerate() {
+        val = char_value(byte, unsigned).wrapping_add(val << 8);
+        if i % 4 == 3 {
+            buf[words] = val;
+            words += 1;
+            val = pad;
+        }
+    }
+    if words < buf.len() {
+        buf[words] = val;
+        words += 1;
+    }
+    buf[words..].fill(pad);
+}
+
+/// The MD4 compression function reduced to three rounds of eight steps
+fn half_md4_transform(buf: &mut [u32; 4], input: &[u32; 8]) {
+    let f = |x: u32, y: u32, z: u32| z ^ (x & (y ^ z));
+    let g = |x: u32, y: u32, z: u32| (x & y).wrapping_add((x ^ y) & z);
+    let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+    let round = |func: &dyn Fn(u32, u32, u32) -> u32, a: u32, b, c, d, x: u32, s| {
+        a.wrapping_add(func(b, c, d)).wrapping_add(x).rotate_left(s)
+    };
+
+    let [mut a, mut b, mut c, mut d] = *buf;
+
+    // Round 1
+    a = round(&f, a, b, c, d, input[0], 3);
+    d = round(&f, d, a, b, c, input[1], 7);
+    c = round(&f, c, d, a, b, input[2], 11);
+    b = round(&f, b, c, d, a, input[3], 19);
+    a = round(&f, a, b, c, d, input[4], 3);
+    d = round(&f, d, a, b, c, input[5], 7);
+    c = round(&f, c, d, a, b, input[6], 11);
+    b = round(&f, b, c, d, a, input[7], 19);
+
+    // Round 2
+    a = round(&g, a, b, c, d, input[1].wrapping_add(K2), 3);
+    d = round(&g, d, a, b, c, input[3].wrapping_add(K2), 5);
+    c = round(&g, c, d, a, b, input[5].wrapping_add(K2), 9);
+    b = round(&g, b, c, d, a, input[7].wrapping_add(K2), 13);
+    a = round(&g, a, b, c, d, input[0].wrapping_add(K2), 3);
+    d = round(&g, d, a, b, c, input[2].wrapping_add(K2), 5);
+    c = round(&g, c, d, a, b, input[4].wrapping_add(K2), 9);
+    b = round(&g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+    // Round 3
+    a = round(&h, a, b, c, d, input[3].wrapping_add(K3), 3);
+    d = round(&h, d, a, b, c, input[7].wrapping_add(K3), 9);
+    c = round(&h, c, d, a, b, input[2].wrapping_add(K3), 11);
+    b = round(&h, b, c, d, a, input[6].wrapping_add(K3), 15);
+    a = round(&h, a, b, c, d, input[1].wrapping_add(K3), 3);
+    d = round(&h, d, a, b, c, input[5].wrapping_add(K3), 9);
+    c = round(&h, c, d, a, b, input[0].wrapping_add(K3), 11);
+    b = round(&h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
+
+/// Sixteen rounds of the Tiny Encryption Algorithm over the first two words
+fn tea_transform(buf: &mut [u32; 4], input: &[u32; 4]) {
+    let [a, b, c, d] = *input;
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let mut sum: u32 = 0;
+
+    for _ in 0..16 {
+        sum = sum.wrapping_add(TEA_DELTA);
+        b0 = b0.wrapping_add(
+            ((b1 << 4).wrapping_add(a)) ^ b1.wrapping_add(sum) ^ ((b1 >> 5).wrapping_add(b)),
+        );
+        b1 = b1.wrapping_add(
+            ((b0 << 4).wrapping_add(c)) ^ b0.wrapping_add(sum) ^ ((b0 >> 5).wrapping_add(d)),
+        );
+    }
+
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
+
+#[test]
+fn test_dx_hash() {
+    // Expected values from debugfs `dx_hash -h <n> -s 0a1b2c3d-4e5f-6071-8293-a4b5c6d7e8f9`
+    let seed = [0x3D2C1B0A, 0x71605F4E, 0xB5A49382, 0xF9E8D7C6];
+    let long = b"a_rather_long_file_name_that_spans_more_than_thirty_two_bytes.txt";
+    let cafe = "café".as_bytes();
+    let cases: [(DxHashVersion, &[u8], (u32, u32)); 12] = [
+        (DxHashVersion::Legacy, b"test_file.txt", (0xc551321c, 0)),
+        (DxHashVersion::Legacy, cafe, (0x96ca5a2c, 0)),
+        (DxHashVersion::Legacy, long, (0xd5e726fe, 0)),
+        (DxHashVersion::LegacyUnsigned, cafe, (0x6dde4230, 0)),
+        (
+            DxHashVersion::HalfMd4,
+            b"test_file.txt",
+            (0xa4f822f8, 0x7b37d750),
+        ),
+        (DxHashVersion::HalfMd4, cafe, (0xbb694f2e, 0x76b906ab)),
+        (DxHashVersion::HalfMd4, long, (0x8db0b716, 0xe4f85d48)),
+        (
+            DxHashVersion::HalfMd4Unsigned,
+            cafe,
+            (0x48c0eaf6, 0x64d3667b),
+        ),
+        (
+            DxHashVersion::Tea,
+            b"test_file.txt",
+            (0x92f89806, 0x86c32be8),
+        ),
+        (DxHashVersion::Tea, cafe, (0x8edf5c24, 0xa2a8fa68)),
+        (DxHashVersion::Tea, long, (0x5a37f26c, 0xbcd51152)),
+        (DxHashVersion::TeaUnsigned, cafe, (0x4c77003a, 0x6ed5c7ab)),
+    ];
+    for (version, name, expected) in cases {
+        assert_eq!(dx_hash(name, version, &seed), expected, "{:?}", version);
+    }
+
+    // An all-zero seed falls back to the MD4 initial state
+    assert_eq!(
+        dx_hash(b"test_file.txt", DxHashVersion::HalfMd4, &[0; 4]),
+        (0xdd45d518, 0x408dc13b)
+    );
+}
@@ -0,0 +1,215 @@
+use crate::bytes::Cursor;
+use crate::checksum::crc32c;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::LittleEndian;
+
+/// Offset of `dx_root_info`, after the fixed-size `.` and `..` entries
+const DX_ROOT_OFFSET_INFO: u64 = 0x18;
+
+/// Offset of the count/limit header in an interior node, after an empty
+/// directory entry spanning the whole block
+const DX_NODE_OFFSET_COUNT_LIMIT: usize = 0x08;
+
+/// Offset of the count/limit header in the root, after `dx_root_info`
+const DX_ROOT_OFFSET_COUNT_LIMIT: usize = 0x20;
+
+/// Size of `dx_root_info`, the only `info_length` the kernel accepts
+const DX_ROOT_INFO_SIZE: u8 = 8;
+
+/// Size of the fixed `.` entry in front of the root's `..` entry
+const DX_ROOT_DOT_REC_LEN: usize = 12;
+
+/// Size of the `dx_tail` (reserved word, checksum) after the entry table
+const DX_TAIL_SIZE: usize = 8;
+
+/// Size of one `dx_entry` (hash, block)
+const DX_ENTRY_SIZE: usize = 8;
+
+/// Limit on indirect levels without the `largedir` feature
+const DX_MAX_INDIRECT_LEVELS: u8 = 2;
+
+/// Limit on indirect levels with the `largedir` feature
+const DX_MAX_INDIRECT_LEVELS_LARGEDIR: u8 = 3;
+
+/// One index entry: leaf or interior node holding hashes from `hash` upwards
+///
+/// The first entry of each node overlays the count/limit header and has an
+/// implicit hash of 0.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DxEntry {
+    /// Lowest hash in the child; bit 0 marks a collision continuation
+    pub(crate) hash: u32,
+    /// Logical block of the child within the directory
+    pub(crate) block: u32,
+}
+
+/// The root of an htree index, stored in the directory's first block
+#[derive(Debug, Clone)]
+pub(crate) struct DxRoot {
+    /// Hash function (`dx_root_info.hash_version`)
+    pub(crate) hash_version: u8,
+    /// Number of interior node levels below the root
+    pub(crate) indirect_levels: u8,
+    /// Index entries of the root
+    pub(crate) entries: Vec<DxEntry>,
+}
+
+impl DxRoot {
+    /// Parse the index root from a directory's first block
+    ///
+    /// # Arguments
+    /// * `buf` - The directory's first block
+    /// * `largedir` - Whether the `largedir` feature allows a third level
+    ///
+    /// # Returns
+    /// Parsed root, or `None` if the header or entry table is malformed
+    pub(crate) fn parse(buf: &[u8], largedir: bool) -> Option<Self> {
+        let mut cursor = Cursor::new(buf);
+        cursor.set_position(DX_ROOT_OFFSET_INFO);
+        let _reserved = cursor.read_u32::<LittleEndian>().ok()?;
+        let hash_version = cursor.read_u8().ok()?;
+        let info_length = cursor.read_u8().ok()? as usize;
+        let indirect_levels = cursor.read_u8().ok()?;
+
+        let max_levels = if largedir {
+            DX_MAX_INDIRECT_LEVELS_LARGEDIR
+        } else {
+            DX_MAX_INDIRECT_LEVELS
+        };
+        if indirect_levels >= max_levels {
+            return None;
+        }
+
+        let entries = parse_entries(buf, DX_ROOT_OFFSET_INFO as usize + info_length)?;
+        Some(Self {
+            hash_version,
+            indirect_levels,
+            entries,
+        })
+    }
+}
+
+/// Parse the index entries of an interior node block
+pub(crate) fn parse_dx_node(buf: &[u8]) -> Option<Vec<DxEntry>> {
+    parse_entries(buf, DX_NODE_OFFSET_COUNT_LIMIT)
+}
+
+/// Parse a count/limit header and the entries that follow it
+fn parse_entries(buf: &[u8], offset: usize) -> Option<Vec<DxEntry>> {
+    let mut cursor = Cursor::new(buf.get(offset..)?);
+    let limit = cursor.read_u16::<LittleEndian>().ok()? as usize;
+    let count = cursor.read_u16::<LittleEndian>().ok()? as usize;
+    if count == 0 || count > limit || offset + limit * DX_ENTRY_SIZE > buf.len() {
+        return None;
+    }
+
+    let mut entries = vec![DxEntry {
+        hash: 0,
+        block: cursor.read_u32::<LittleEndian>().ok()?,
+    }];
+    for _ in 1..count {
+        let hash = cursor.read_u32::<LittleEndian>().ok()?;
+        let block = cursor.read_u32::<LittleEndian>().ok()?;
+        entries.push(DxEntry { hash, block });
+    }
+    Some(entries)
+}
+
+/// Whether a block of an indexed directory is an htree root or interior node
+///
+/// # Arguments
+/// * `buf` - A full directory block
+/// * `logical_block` - Its logical block number; the root is always block 0
+pub(crate) fn is_index_block(buf: &[u8], logical_block: u32) -> bool {
+    logical_block == 0
+        || buf.len() >= 6 && u16::from_le_bytes([buf[4], buf[5]]) as usize == buf.len()
+}
+
+/// Locate the count/limit header of an htree root or interior node
+///
+/// Mirrors the kernel's `get_dx_countlimit()`: interior nodes start with an
+/// empty entry spanning the whole block, while the root has `.` followed
+/// by a `..` entry spanning the rest of the block and a `dx_root_info`.
+///
+/// # Returns
+/// Offset of the count/limit header, or `None` if `buf` is not an index block
+fn count_limit_offset(buf: &[u8]) -> Option<usize> {
+    let rec_len_at = |offset: usize| -> Option<usize> {
+        Some(u16::from_le_bytes(buf.get(offset + 4..offset + 6)?.try_into().ok()?) as usize)
+    };
+
+    let rec_len = rec_len_at(0)?;
+    if rec_len == buf.len() {
+        return Some(DX_NODE_OFFSET_COUNT_LIMIT);
+    }
+    if rec_len != DX_ROOT_DOT_REC_LEN
+        || rec_len_at(DX_ROOT_DOT_REC_LEN)? != buf.len() - DX_ROOT_DOT_REC_LEN
+    {
+        return None;
+    }
+
+    let info = buf.get(DX_ROOT_OFFSET_INFO as usize..DX_ROOT_OFFSET_COUNT_LIMIT)?;
+    if info[..4] != [0; 4] || info[5] != DX_ROOT_INFO_SIZE {
+        return None;
+    }
+    Some(DX_ROOT_OFFSET_COUNT_LIMIT)
+}
+
+/// Compute and read back the checksum of an index block
+///
+/// The `dx_tail` sits right after `limit` entries. Its checksum covers the
+/// block up to the last used entry, followed by the tail with the checksum
+/// field itself zeroed.
+///
+/// # Arguments
+/// * `buf` - A full htree root or interior node block
+/// * `seed` - Per-inode checksum seed of the directory
+///
+/// # Returns
+/// `(stored, computed)`, or `None` if the block is not an index block or
+/// has no room for a tail
+pub(crate) fn dx_checksum(buf: &[u8], seed: u32) -> Option<(u32, u32)> {
+    let offset = count_limit_offset(buf)?;
+    let limit = u16::from_le_bytes(buf.get(offset..offset + 2)?.try_into().ok()?) as usize;
+    let count = u16::from_le_bytes(buf.get(offset + 2..offset + 4)?.try_into().ok()?) as usize;
+
+    let tail = offset + limit * DX_ENTRY_SIZE;
+    if count > limit || tail + DX_TAIL_SIZE > buf.len() {
+        return None;
+    }
+
+    let stored = u32::from_le_bytes(buf[tail + 4..tail + DX_TAIL_SIZE].try_into().ok()?);
+    let computed = crc32c(seed, &buf[..offset + count * DX_ENTRY_SIZE]);
+    let computed = crc32c(computed, &buf[tail..tail + 4]);
+    let computed = crc32c(computed, &[0; 4]);
+    Some((stored, computed))
+}
+
+/// Position of the entry whose range contains `hash`: the last one whose
+/// starting hash is not greater
+pub(crate) fn search(entries: &[DxEntry], hash: u32) -> usize {
+    entries[1..].partition_point(|e| e.hash <= hash)
+}
+
+#[test]
+fn test_dx_root_levels() {
+    // `.`, `..` spanning the block, dx_root_info and one entry
+    let mut buf = vec![0u8; 1024];
+    buf[4..6].copy_from_slice(&12u16.to_le_bytes());
+    buf[12 + 4..12 + 6].copy_from_slice(&1012u16.to_le_bytes());
+    buf[DX_ROOT_OFFSET_INFO as usize + 5] = DX_ROOT_INFO_SIZE;
+    buf[DX_ROOT_OFFSET_COUNT_LIMIT..DX_ROOT_OFFSET_COUNT_LIMIT + 4]
+        .copy_from_slice(&[100, 0, 1, 0]);
+
+    buf[DX_ROOT_OFFSET_INFO as usize + 6] = 1;
+    assert_eq!(DxRoot::parse(&buf, false).unwrap().indirect_levels, 1);
+
+    // A third level needs largedir
+    buf[DX_ROOT_OFFSET_INFO as usize + 6] = 2;
+    assert!(DxRoot::parse(&buf, false).is_none());
+    assert_eq!(DxRoot::parse(&buf, true).unwrap().indirect_levels, 2);
+    buf[DX_ROOT_OFFSET_INFO as usize + 6] = 3;
+    assert!(DxRoot::parse(&buf, true).is_none());
+}
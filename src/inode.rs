@@ -1,48 +1,94 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::{Cursor, Read};
+use crate::bytes::Cursor;
+use crate::checksum::crc32c;
+use crate::xattr::{EXT4_XATTR_INDEX_SYSTEM, XattrEntry, parse_inode_xattrs};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::LittleEndian;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Offsets within the ext4 inode structure
 const INODE_OFFSET_MODE: u64 = 0x00;
-const INODE_OFFSET_SIZE: u64 = 0x04;
 const INODE_OFFSET_FLAGS: u64 = 0x20;
 const INODE_OFFSET_BLOCK: u64 = 0x28;
+const INODE_OFFSET_GENERATION: u64 = 0x64;
+const INODE_OFFSET_OSD2: u64 = 0x74;
+const INODE_OFFSET_EXTRA_ISIZE: u64 = 0x80;
+const INODE_OFFSET_CTIME_EXTRA: u64 = 0x84;
+const INODE_OFFSET_MTIME_EXTRA: u64 = 0x88;
+const INODE_OFFSET_ATIME_EXTRA: u64 = 0x8C;
+const INODE_OFFSET_CRTIME: u64 = 0x90;
+const INODE_OFFSET_CRTIME_EXTRA: u64 = 0x94;
+const INODE_OFFSET_VERSION_HI: u64 = 0x98;
+const INODE_OFFSET_PROJID: u64 = 0x9C;
+
+/// Size of the original ext2 inode; larger inodes carry extra fields
+pub(crate) const EXT4_GOOD_OLD_INODE_SIZE: usize = 128;
+
+const EXT4_INDEX_FLAG: u32 = 0x00001000;
+const EXT4_HUGE_FILE_FLAG: u32 = 0x00040000;
 const EXT4_EXTENTS_FLAG: u32 = 0x00080000;
+const EXT4_INLINE_DATA_FLAG: u32 = 0x10000000;
+const EXT4_CASEFOLD_FLAG: u32 = 0x40000000;
+
+/// Offsets of the checksum halves, zeroed while computing the checksum
+const INODE_OFFSET_CHECKSUM_LO: usize = 0x7C;
+const INODE_OFFSET_CHECKSUM_HI: usize = 0x82;
+
+/// Extent tree layout
+const EXT4_EXTENT_MAGIC: u16 = 0xF30A;
+const EXTENT_HEADER_SIZE: usize = 12;
+const EXTENT_ENTRY_SIZE: usize = 12;
+
+/// Deepest extent tree the kernel creates or accepts
+pub(crate) const EXT4_MAX_EXTENT_DEPTH: u16 = 5;
+
+/// Extents longer than this are unwritten; the excess is the real length
+const EXT_INIT_MAX_LEN: u16 = 32768;
+
+/// Low bits of a `_extra` timestamp field that extend the seconds
+const EXT4_EPOCH_BITS: u32 = 2;
+const EXT4_EPOCH_MASK: u32 = (1 << EXT4_EPOCH_BITS) - 1;
+
+/// Size of `i_block`, which also holds the first part of inline data
+pub(crate) const EXT4_MIN_INLINE_DATA_SIZE: usize = 60;
 
 /// Parsed extent header
-/// 12 bytes at start of i_block
-#[derive(Debug)]
+/// 12 bytes at start of i_block and of every extent tree block
+#[derive(Debug, Clone)]
 pub(crate) struct ExtentHeader {
-    pub magic: u16,
     pub entry_count: u16,
     pub max_entry_count: u16,
     pub tree_depth: u16,
-    pub generation: u32,
 }
 
 impl ExtentHeader {
-    pub fn parse(buf: &[u8]) -> Self {
+    /// Parse an extent header, returning `None` if the magic does not match
+    pub fn parse(buf: &[u8]) -> Option<Self> {
         let mut cursor = Cursor::new(buf);
-        let magic = cursor.read_u16::<LittleEndian>().unwrap();
-        assert_eq!(magic, 0xf30a, "Invalid extent header magic");
+        let magic = cursor.read_u16::<LittleEndian>().ok()?;
+        if magic != EXT4_EXTENT_MAGIC {
+            return None;
+        }
 
-        let entry_count = cursor.read_u16::<LittleEndian>().unwrap();
-        let max_entry_count = cursor.read_u16::<LittleEndian>().unwrap();
-        let tree_depth = cursor.read_u16::<LittleEndian>().unwrap();
-        let generation = cursor.read_u32::<LittleEndian>().unwrap();
+        let entry_count = cursor.read_u16::<LittleEndian>().ok()?;
+        let max_entry_count = cursor.read_u16::<LittleEndian>().ok()?;
+        let tree_depth = cursor.read_u16::<LittleEndian>().ok()?;
 
-        Self {
-            magic,
+        Some(Self {
             entry_count,
             max_entry_count,
             tree_depth,
-            generation,
-        }
+        })
     }
 }
 
 /// Leaf extent entry
 /// 12 bytes per extent if depth == 0
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Extent {
     pub logical_block: u32,  // Logical block index in file
     pub block_count: u16,    // Number of blocks this extent covers
@@ -70,16 +116,177 @@ impl Extent {
     pub fn physical_block_start(&self) -> u64 {
         ((self.start_block_hi as u64) << 32) | (self.start_block_lo as u64)
     }
+
+    /// Whether the extent is preallocated but not yet written (reads as zeros)
+    pub fn is_unwritten(&self) -> bool {
+        self.block_count > EXT_INIT_MAX_LEN
+    }
+
+    /// Number of blocks covered, with the unwritten marker removed
+    pub fn len(&self) -> u32 {
+        if self.is_unwritten() {
+            (self.block_count - EXT_INIT_MAX_LEN) as u32
+        } else {
+            self.block_count as u32
+        }
+    }
+
+    /// Physical block backing a logical block, if this extent covers it
+    pub fn map(&self, logical_block: u32) -> Option<u64> {
+        let offset = logical_block.checked_sub(self.logical_block)?;
+        (offset < self.len()).then(|| self.physical_block_start() + offset as u64)
+    }
+}
+
+/// Index entry of an interior extent tree node
+/// 12 bytes per entry if depth > 0
+#[derive(Debug, Clone)]
+pub(crate) struct ExtentIndex {
+    pub first_block: u32, // First logical block covered by the subtree
+    pub leaf_lo: u32,     // Lower 32 bits of the child node block
+    pub leaf_hi: u16,     // Upper 16 bits of the child node block
+}
+
+impl ExtentIndex {
+    pub fn parse(buf: &[u8]) -> Self {
+        let mut cursor = Cursor::new(buf);
+        let first_block = cursor.read_u32::<LittleEndian>().unwrap();
+        let leaf_lo = cursor.read_u32::<LittleEndian>().unwrap();
+        let leaf_hi = cursor.read_u16::<LittleEndian>().unwrap();
+
+        Self {
+            first_block,
+            leaf_lo,
+            leaf_hi,
+        }
+    }
+
+    /// Returns the physical block holding the child node
+    pub fn leaf_block(&self) -> u64 {
+        ((self.leaf_hi as u64) << 32) | (self.leaf_lo as u64)
+    }
+}
+
+/// Entries of an extent tree node: extents in leaves, indexes above them
+#[derive(Debug, Clone)]
+pub(crate) enum ExtentEntries {
+    Leaf(Vec<Extent>),
+    Index(Vec<ExtentIndex>),
 }
 
-/// Represents a parsed inode, assuming extent-based layout
-#[derive(Debug)]
+/// One node of an extent tree, either the root in `i_block` or a tree block
+#[derive(Debug, Clone)]
+pub(crate) struct ExtentNode {
+    pub header: ExtentHeader,
+    pub entries: ExtentEntries,
+}
+
+impl ExtentNode {
+    /// Parse an extent tree node
+    ///
+    /// # Arguments
+    /// * `buf` - The 60-byte `i_block` or a full extent tree block
+    ///
+    /// # Returns
+    /// `None` if the magic is wrong or the entries do not fit in `buf`
+    pub(crate) fn parse(buf: &[u8]) -> Option<Self> {
+        let header = ExtentHeader::parse(buf)?;
+        let count = header.entry_count as usize;
+        if header.entry_count > header.max_entry_count
+            || EXTENT_HEADER_SIZE + count * EXTENT_ENTRY_SIZE > buf.len()
+        {
+            return None;
+        }
+
+        let entry = |i: usize| {
+            let offset = EXTENT_HEADER_SIZE + i * EXTENT_ENTRY_SIZE;
+            &buf[offset..offset + EXTENT_ENTRY_SIZE]
+        };
+        let entries = if header.tree_depth == 0 {
+            ExtentEntries::Leaf((0..count).map(|i| Extent::parse(entry(i))).collect())
+        } else {
+            ExtentEntries::Index((0..count).map(|i| ExtentIndex::parse(entry(i))).collect())
+        };
+
+        Some(Self { header, entries })
+    }
+
+    /// Offset of the `ext4_extent_tail` checksum in a tree block
+    pub(crate) fn tail_offset(&self) -> usize {
+        EXTENT_HEADER_SIZE + self.header.max_entry_count as usize * EXTENT_ENTRY_SIZE
+    }
+}
+
+/// An inode timestamp: 32-bit seconds plus the optional `_extra` field
+///
+/// The extra field holds nanoseconds in its upper 30 bits and two epoch bits
+/// that extend the signed 32-bit seconds past 2038.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timestamp {
+    /// Raw on-disk seconds field
+    pub(crate) seconds_raw: u32,
+    /// Raw `_extra` field, if the inode is large enough to hold it
+    pub(crate) extra: Option<u32>,
+}
+
+impl Timestamp {
+    /// Seconds since the Unix epoch, including the epoch extension bits
+    pub(crate) fn seconds(&self) -> i64 {
+        let base = self.seconds_raw as i32 as i64;
+        let epoch = self.extra.unwrap_or(0) & EXT4_EPOCH_MASK;
+        base + ((epoch as i64) << 32)
+    }
+
+    /// Nanoseconds within the second (zero for small inodes)
+    #[cfg(feature = "std")]
+    pub(crate) fn nanoseconds(&self) -> u32 {
+        self.extra.unwrap_or(0) >> EXT4_EPOCH_BITS
+    }
+
+    /// Convert to a `SystemTime`, handling times before the epoch
+    #[cfg(feature = "std")]
+    pub(crate) fn to_system_time(self) -> SystemTime {
+        let seconds = self.seconds();
+        let nanos = Duration::from_nanos(self.nanoseconds() as u64);
+        if seconds >= 0 {
+            UNIX_EPOCH + Duration::from_secs(seconds as u64) + nanos
+        } else {
+            UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()) + nanos
+        }
+    }
+}
+
+/// Represents a parsed inode, either extent-based or with inline data
+#[derive(Debug, Clone)]
 pub(crate) struct Inode {
     pub inode_mode: u16,
-    pub inode_size: u32,
-    pub extent_blocks: Vec<u64>, // All resolved physical block numbers
-    pub extent_header: ExtentHeader,
-    pub extents: Vec<Extent>, // All parsed extent entries
+    pub uid: u32,
+    pub gid: u32,
+    pub inode_size: u64,
+    pub links_count: u16,
+    pub atime: Timestamp,
+    pub ctime: Timestamp,
+    pub mtime: Timestamp,
+    pub dtime: u32,
+    pub crtime: Option<Timestamp>,
+    /// Raw `i_blocks`, including the high 16 bits
+    pub blocks: u64,
+    pub flags: u32,
+    pub generation: u32,
+    pub version: u64,
+    pub file_acl: u64,
+    pub extra_isize: u16,
+    pub checksum: u32,
+    pub projid: Option<u32>,
+    /// Raw `i_block`: extent root, inline data or a fast symlink target
+    pub i_block: [u8; EXT4_MIN_INLINE_DATA_SIZE],
+    /// Root of the extent tree, stored in i_block
+    pub extent_root: Option<ExtentNode>,
+    /// Extended attributes stored in the inode body, or `None` if one of
+    /// their values lies outside the inode
+    pub xattrs: Option<Vec<XattrEntry>>,
+    /// Inline contents: `i_block` followed by the `system.data` xattr value
+    pub inline_data: Option<Vec<u8>>,
 }
 
 impl Inode {
@@ -88,53 +295,342 @@ impl Inode {
 
         cursor.set_position(INODE_OFFSET_MODE);
         let inode_mode = cursor.read_u16::<LittleEndian>().unwrap();
-
-        cursor.set_position(INODE_OFFSET_SIZE);
-        let inode_size = cursor.read_u32::<LittleEndian>().unwrap();
+        let uid_lo = cursor.read_u16::<LittleEndian>().unwrap();
+        let size_lo = cursor.read_u32::<LittleEndian>().unwrap();
+        let atime = cursor.read_u32::<LittleEndian>().unwrap();
+        let ctime = cursor.read_u32::<LittleEndian>().unwrap();
+        let mtime = cursor.read_u32::<LittleEndian>().unwrap();
+        let dtime = cursor.read_u32::<LittleEndian>().unwrap();
+        let gid_lo = cursor.read_u16::<LittleEndian>().unwrap();
+        let links_count = cursor.read_u16::<LittleEndian>().unwrap();
+        let blocks_lo = cursor.read_u32::<LittleEndian>().unwrap();
 
         cursor.set_position(INODE_OFFSET_FLAGS);
         let inode_flags = cursor.read_u32::<LittleEndian>().unwrap();
-        assert!(
-            inode_flags & EXT4_EXTENTS_FLAG != 0,
-            "Expected inode with extents enabled"
-        );
+        let version_lo = cursor.read_u32::<LittleEndian>().unwrap();
 
         cursor.set_position(INODE_OFFSET_BLOCK);
-        let mut i_block_raw = [0u8; 60];
+        let mut i_block_raw = [0u8; EXT4_MIN_INLINE_DATA_SIZE];
         cursor.read_exact(&mut i_block_raw).unwrap();
 
-        // Parse extent header and assert depth = 0
-        let extent_header = ExtentHeader::parse(&i_block_raw[..12]);
-        assert_eq!(
-            extent_header.tree_depth, 0,
-            "Extent trees with depth > 0 are not supported"
-        );
-
-        // Parse extent entries
-        let mut extents = Vec::new();
-        let mut extent_blocks = Vec::new();
-        for i in 0..extent_header.entry_count {
-            let offset = 12 + (i as usize) * 12;
-            let extent = Extent::parse(&i_block_raw[offset..offset + 12]);
-
-            let physical_start = extent.physical_block_start();
-            for j in 0..extent.block_count as u64 {
-                extent_blocks.push(physical_start + j);
+        cursor.set_position(INODE_OFFSET_GENERATION);
+        let generation = cursor.read_u32::<LittleEndian>().unwrap();
+        let file_acl_lo = cursor.read_u32::<LittleEndian>().unwrap();
+        let size_high = cursor.read_u32::<LittleEndian>().unwrap();
+
+        // Linux-specific osd2 fields hold the high halves
+        cursor.set_position(INODE_OFFSET_OSD2);
+        let blocks_high = cursor.read_u16::<LittleEndian>().unwrap();
+        let file_acl_high = cursor.read_u16::<LittleEndian>().unwrap();
+        let uid_high = cursor.read_u16::<LittleEndian>().unwrap();
+        let gid_high = cursor.read_u16::<LittleEndian>().unwrap();
+        let checksum_lo = cursor.read_u16::<LittleEndian>().unwrap();
+
+        // Fields past the original 128 bytes exist only up to i_extra_isize
+        let extra_isize = if inode_bytes.len() > EXT4_GOOD_OLD_INODE_SIZE {
+            cursor.set_position(INODE_OFFSET_EXTRA_ISIZE);
+            cursor.read_u16::<LittleEndian>().unwrap()
+        } else {
+            0
+        };
+        let extra_end = EXT4_GOOD_OLD_INODE_SIZE as u64 + extra_isize as u64;
+        let mut read_extra = |offset: u64| -> Option<u32> {
+            if offset + 4 > extra_end || offset + 4 > inode_bytes.len() as u64 {
+                return None;
             }
+            cursor.set_position(offset);
+            cursor.read_u32::<LittleEndian>().ok()
+        };
 
-            extents.push(extent);
-        }
+        // i_checksum_hi shares a 4-byte slot with i_extra_isize
+        let checksum_hi = read_extra(INODE_OFFSET_EXTRA_ISIZE).map(|v| v >> 16);
+        let ctime_extra = read_extra(INODE_OFFSET_CTIME_EXTRA);
+        let mtime_extra = read_extra(INODE_OFFSET_MTIME_EXTRA);
+        let atime_extra = read_extra(INODE_OFFSET_ATIME_EXTRA);
+        let crtime = read_extra(INODE_OFFSET_CRTIME).map(|seconds_raw| Timestamp {
+            seconds_raw,
+            extra: read_extra(INODE_OFFSET_CRTIME_EXTRA),
+        });
+        let version_hi = read_extra(INODE_OFFSET_VERSION_HI).unwrap_or(0);
+        let projid = read_extra(INODE_OFFSET_PROJID);
+
+        let xattrs = parse_inode_xattrs(inode_bytes);
+
+        // Small files and directories keep their data in i_block, continued
+        // in the system.data xattr when it does not fit
+        let inline_data = if inode_flags & EXT4_INLINE_DATA_FLAG != 0 {
+            let mut inline_data = i_block_raw.to_vec();
+            if let Some(xattr) = xattrs
+                .iter()
+                .flatten()
+                .find(|e| e.name_index == EXT4_XATTR_INDEX_SYSTEM && e.name == b"data")
+            {
+                inline_data.extend_from_slice(&xattr.value);
+            }
+            Some(inline_data)
+        } else {
+            None
+        };
+
+        // Only extent-mapped inodes have a tree root in i_block
+        let extent_root = if inode_flags & EXT4_EXTENTS_FLAG != 0 {
+            ExtentNode::parse(&i_block_raw)
+        } else {
+            None
+        };
 
         Self {
             inode_mode,
-            inode_size,
-            extent_blocks,
-            extent_header,
-            extents,
+            uid: (uid_high as u32) << 16 | uid_lo as u32,
+            gid: (gid_high as u32) << 16 | gid_lo as u32,
+            inode_size: (size_high as u64) << 32 | size_lo as u64,
+            links_count,
+            atime: Timestamp {
+                seconds_raw: atime,
+                extra: atime_extra,
+            },
+            ctime: Timestamp {
+                seconds_raw: ctime,
+                extra: ctime_extra,
+            },
+            mtime: Timestamp {
+                seconds_raw: mtime,
+                extra: mtime_extra,
+            },
+            dtime,
+            crtime,
+            blocks: (blocks_high as u64) << 32 | blocks_lo as u64,
+            flags: inode_flags,
+            generation,
+            version: (version_hi as u64) << 32 | version_lo as u64,
+            file_acl: (file_acl_high as u64) << 32 | file_acl_lo as u64,
+            extra_isize,
+            checksum: checksum_hi.unwrap_or(0) << 16 | checksum_lo as u32,
+            projid,
+            i_block: i_block_raw,
+            extent_root,
+            xattrs,
+            inline_data,
         }
     }
 
     pub(crate) fn is_dir(&self) -> bool {
         self.inode_mode & 0xF000 == 0x4000 // EXT4_S_IFDIR
     }
+
+    pub(crate) fn is_symlink(&self) -> bool {
+        self.inode_mode & 0xF000 == 0xA000 // EXT4_S_IFLNK
+    }
+
+    /// Whether this directory has an htree index (`EXT4_INDEX_FL`)
+    pub(crate) fn is_indexed(&self) -> bool {
+        self.is_dir() && self.flags & EXT4_INDEX_FLAG != 0 && self.inline_data.is_none()
+    }
+
+    /// Whether this directory's names are case-insensitive (`EXT4_CASEFOLD_FL`)
+    pub(crate) fn is_casefolded(&self) -> bool {
+        self.is_dir() && self.flags & EXT4_CASEFOLD_FLAG != 0
+    }
+
+    /// Whether this is a symlink whose target is stored directly in i_block
+    pub(crate) fn is_fast_symlink(&self) -> bool {
+        self.is_symlink()
+            && self.extent_root.is_none()
+            && self.inline_data.is_none()
+            && self.inode_size < EXT4_MIN_INLINE_DATA_SIZE as u64
+    }
+
+    /// Whether the inode is large enough to hold `i_checksum_hi`
+    pub(crate) fn has_checksum_hi(&self) -> bool {
+        self.extra_isize >= 4
+    }
+
+    /// Number of 512-byte sectors allocated to this inode
+    ///
+    /// # Arguments
+    /// * `huge_file` - Whether the `huge_file` feature is enabled
+    /// * `block_size` - Filesystem block size in bytes
+    pub(crate) fn sector_count(&self, huge_file: bool, block_size: u32) -> u64 {
+        if !huge_file {
+            // Without huge_file the high half is unused
+            return self.blocks & 0xFFFF_FFFF;
+        }
+        if self.flags & EXT4_HUGE_FILE_FLAG != 0 {
+            // i_blocks is counted in filesystem blocks for this inode
+            self.blocks * (block_size as u64 / 512)
+        } else {
+            self.blocks
+        }
+    }
+
+    /// Human-readable file type as printed by debugfs
+    fn type_str(&self) -> &'static str {
+        match self.inode_mode & 0xF000 {
+            0x1000 => "FIFO",
+            0x2000 => "character special",
+            0x4000 => "directory",
+            0x6000 => "block special",
+            0x8000 => "regular",
+            0xA000 => "symlink",
+            0xC000 => "socket",
+            _ => "bad type",
+        }
+    }
+}
+
+/// Compute the checksum of a raw on-disk inode
+///
+/// The checksum fields themselves are treated as zero. Only the low half is
+/// zeroed when the inode is too small to hold `i_checksum_hi`.
+///
+/// # Arguments
+/// * `raw` - Raw on-disk inode (full `s_inode_size` bytes)
+/// * `seed` - Per-inode seed from the filesystem seed, inode number and generation
+/// * `has_hi` - Whether `i_checksum_hi` is present
+pub(crate) fn inode_checksum(raw: &[u8], seed: u32, has_hi: bool) -> u32 {
+    let mut buf = raw.to_vec();
+    buf[INODE_OFFSET_CHECKSUM_LO..INODE_OFFSET_CHECKSUM_LO + 2].fill(0);
+    if has_hi && buf.len() > EXT4_GOOD_OLD_INODE_SIZE {
+        buf[INODE_OFFSET_CHECKSUM_HI..INODE_OFFSET_CHECKSUM_HI + 2].fill(0);
+    }
+    crc32c(seed, &buf)
+}
+
+/// Inode metadata for the shell's `stat` command
+///
+/// Only meant to be printed; [`Metadata`](crate::Metadata) exposes the
+/// same information field by field.
+pub struct InodeStat {
+    pub(crate) inode_num: u32,
+    pub(crate) inode: Inode,
+    /// Leaf extents gathered from the whole extent tree
+    pub(crate) extents: Vec<Extent>,
+    /// Allocated 512-byte sectors, after huge_file adjustment
+    pub(crate) sectors: u64,
+}
+
+impl core::fmt::Display for InodeStat {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let inode = &self.inode;
+        writeln!(
+            f,
+            "Inode: {}   Type: {}    Mode:  {:04o}   Flags: 0x{:x}",
+            self.inode_num,
+            inode.type_str(),
+            inode.inode_mode & 0o7777,
+            inode.flags
+        )?;
+        writeln!(
+            f,
+            "Generation: {}    Version: 0x{:08x}:{:08x}",
+            inode.generation,
+            inode.version >> 32,
+            inode.version & 0xFFFF_FFFF
+        )?;
+        writeln!(
+            f,
+            "User: {:>5}   Group: {:>5}   Project: {:>5}   Size: {}",
+            inode.uid,
+            inode.gid,
+            inode.projid.unwrap_or(0),
+            inode.inode_size
+        )?;
+        writeln!(f, "File ACL: {}", inode.file_acl)?;
+        writeln!(
+            f,
+            "Links: {}   Blockcount: {}",
+            inode.links_count, self.sectors
+        )?;
+
+        let mut times = vec![
+            (" ctime", inode.ctime),
+            (" atime", inode.atime),
+            (" mtime", inode.mtime),
+        ];
+        if let Some(crtime) = inode.crtime {
+            times.push(("crtime", crtime));
+        }
+        for (label, time) in times {
+            match time.extra {
+                Some(extra) => write!(f, "{}: 0x{:08x}:{:08x}", label, time.seconds_raw, extra)?,
+                None => write!(f, "{}: 0x{:08x}", label, time.seconds_raw)?,
+            }
+            writeln!(f, " -- {}", format_time(time.seconds()))?;
+        }
+        if inode.dtime != 0 {
+            writeln!(
+                f,
+                " dtime: 0x{:08x} -- {}",
+                inode.dtime,
+                format_time(inode.dtime as i64)
+            )?;
+        }
+
+        if inode.extra_isize != 0 {
+            writeln!(f, "Size of extra inode fields: {}", inode.extra_isize)?;
+        }
+        writeln!(f, "Inode checksum: 0x{:08x}", inode.checksum)?;
+
+        if inode.is_fast_symlink() {
+            let target = &inode.i_block[..inode.inode_size as usize];
+            write!(f, "Fast link dest: \"{}\"", String::from_utf8_lossy(target))
+        } else if let Some(data) = &inode.inline_data {
+            write!(f, "Size of inline data: {}", data.len())
+        } else {
+            writeln!(f, "EXTENTS:")?;
+            let extents: Vec<String> = self
+                .extents
+                .iter()
+                .map(|e| {
+                    let start = e.physical_block_start();
+                    if e.len() <= 1 {
+                        format!("({}):{}", e.logical_block, start)
+                    } else {
+                        let count = e.len() as u64;
+                        format!(
+                            "({}-{}):{}-{}",
+                            e.logical_block,
+                            e.logical_block as u64 + count - 1,
+                            start,
+                            start + count - 1
+                        )
+                    }
+                })
+                .collect();
+            write!(f, "{}", extents.join(", "))
+        }
+    }
+}
+
+/// Format seconds since the epoch like `ctime(3)`, in UTC
+fn format_time(seconds: i64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let days = seconds.div_euclid(86400);
+    let secs_of_day = seconds.rem_euclid(86400);
+
+    // Civil-from-days conversion on the proleptic Gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{} {} {:2} {:02}:{:02}:{:02} {}",
+        DAYS[days.rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize],
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        year
+    )
 }
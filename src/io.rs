@@ -0,0 +1,76 @@
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use alloc::boxed::Box;
+    use core::fmt;
+
+    /// The kinds of error reported by this crate, named as in `std::io`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        NotFound,
+        InvalidInput,
+        InvalidData,
+        UnexpectedEof,
+        Unsupported,
+        NotADirectory,
+        IsADirectory,
+        Interrupted,
+        Other,
+    }
+
+    /// An error with a kind and a payload, like `std::io::Error`
+    ///
+    /// As with `std`, the payload is usually a message, but a checksum
+    /// failure carries a [`ChecksumError`](crate::ChecksumError) that
+    /// `get_ref()` and `downcast_ref()` recover.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        error: Box<dyn core::error::Error + Send + Sync>,
+    }
+
+    impl Error {
+        /// # Arguments
+        /// * `kind` - Category of the error
+        /// * `error` - Description of what went wrong, or the error itself
+        pub fn new(
+            kind: ErrorKind,
+            error: impl Into<Box<dyn core::error::Error + Send + Sync>>,
+        ) -> Self {
+            Self {
+                kind,
+                error: error.into(),
+            }
+        }
+
+        /// An error of kind `Other`
+        pub fn other(error: impl Into<Box<dyn core::error::Error + Send + Sync>>) -> Self {
+            Self::new(ErrorKind::Other, error)
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+
+        /// The payload the error was created with
+        pub fn get_ref(&self) -> Option<&(dyn core::error::Error + Send + Sync + 'static)> {
+            Some(&*self.error)
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Display::fmt(&self.error, f)
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    pub type Result<T> = core::result::Result<T, Error>;
+}
@@ -1,250 +1,2943 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+mod acl;
+#[cfg(feature = "async")]
+mod async_fs;
+mod bytes;
+mod cache;
+mod casefold;
+mod checksum;
+mod device;
 mod dir;
+mod file;
 mod group;
+mod hash;
+mod htree;
 mod image;
 mod inode;
+/// I/O result and error types
+///
+/// With the `std` feature these are the `std::io` types. Without it, a
+/// minimal replacement with the same names is used, so [`BlockDevice`]
+/// implementations are written the same way in both builds.
+pub mod io;
+mod metadata;
+mod partition;
+mod qcow2;
+mod sparse;
 mod superblock;
+#[cfg(feature = "std")]
+mod unicode_12_1;
+mod xattr;
 
-use crate::dir::DirectoryEntry;
+pub use crate::acl::{Acl, AclEntry, AclTag};
+#[cfg(feature = "async")]
+pub use crate::async_fs::{AsyncBlockDevice, AsyncFileSystem, AsyncImage};
+pub use crate::cache::CacheStats;
+pub use crate::checksum::{ChecksumError, ChecksumKind};
+#[cfg(feature = "std")]
+pub use crate::device::{BackingFiles, ImageFile, MappedImage};
+pub use crate::device::{BlockBuf, BlockDevice, DeviceWindow};
+pub use crate::dir::{DirectoryEntry, escape_name, unescape_name};
+pub use crate::file::Ext4File;
+pub use crate::inode::InodeStat;
+pub use crate::metadata::{FileType, Metadata, Permissions};
+pub use crate::partition::{Guid, Partition, PartitionType, read_partitions};
+pub use crate::qcow2::Qcow2Image;
+pub use crate::sparse::SparseImage;
+pub use crate::xattr::Xattr;
+
+use crate::cache::{
+    CachedDevice, DEFAULT_CACHE_CAPACITY, DENTRY_CACHE_ENTRIES, INODE_CACHE_ENTRIES, Lock,
+    LruCache, lock,
+};
+use crate::casefold::{EXT4_ENC_UTF8_12_1, LookupName};
+use crate::checksum::{crc32c, inode_seed};
+use crate::device::DefaultDevice;
+use crate::dir::{
+    EXT4_DIR_ENTRY_TAIL_SIZE, dir_entry_tail, inline_dir_regions, parse_deleted_entries,
+    parse_dir_block, parse_inline_dir,
+};
 use crate::group::GroupDescriptor;
+use crate::hash::{DxHashVersion, dx_hash};
+use crate::htree::{DxEntry, DxRoot, dx_checksum, is_index_block, parse_dx_node};
 use crate::image::read_block;
-use crate::inode::Inode;
-use crate::superblock::Superblock;
+use crate::inode::{
+    EXT4_GOOD_OLD_INODE_SIZE, EXT4_MAX_EXTENT_DEPTH, Extent, ExtentEntries, ExtentIndex,
+    ExtentNode, Inode, inode_checksum,
+};
+use crate::superblock::{SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE, Superblock};
+use crate::xattr::{XattrEntry, parse_block_xattrs};
+
+use alloc::vec;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::vec::Vec;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Inode number of the root directory
+const EXT4_ROOT_INO: u32 = 2;
+
+/// Maximum number of symlinks followed while resolving one path, as in Linux
+const MAX_SYMLINK_FOLLOWS: u32 = 40;
+
+/// Largest extended attribute value, as in Linux
+const XATTR_SIZE_MAX: u32 = 65536;
 
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+/// Whether a symlink in the last component of a path is followed
+///
+/// Symlinks in earlier components are always followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FollowSymlinks {
+    /// Resolve a final symlink to its target
+    #[default]
+    Follow,
+    /// Like `O_NOFOLLOW`: metadata describes a final symlink itself, and
+    /// reading or listing one fails with `InvalidInput`
+    NoFollow,
+}
+
+/// Results of looking up (directory inode, name), including misses
+type DentryCache = LruCache<(u32, Vec<u8>), Option<DirectoryEntry>>;
 
 /// Represents an ext4 filesystem with read access
-pub struct FileSystem {
-    /// File handle to the filesystem image or device
-    device: File,
+///
+/// Generic over where the filesystem is stored; [`FileSystem::open`] reads
+/// an image file or device, [`FileSystem::from_device`] anything else.
+///
+/// Names in casefolded directories are folded as by the kernel's Unicode
+/// 12.1 tables; names with code points assigned later are compared byte
+/// for byte, as Linux does. Without the `std` feature there are no folding
+/// tables at all, and looking up a name in a casefolded directory fails
+/// with `Unsupported`; listing it still works.
+pub struct FileSystem<D: BlockDevice = DefaultDevice> {
+    /// The filesystem image or device, behind the block cache
+    device: CachedDevice<D>,
     /// Parsed superblock containing filesystem metadata
     superblock: Superblock,
+    /// Current directory as (name, inode) pairs below the root
+    cwd: Vec<(Vec<u8>, u32)>,
+    /// Parsed inodes with their byte offset on the device
+    inodes: Lock<LruCache<u32, (u64, Inode)>>,
+    /// Results of looking up (directory inode, name), including misses
+    dentries: Lock<DentryCache>,
+    /// Block ranges of every group's bitmaps and inode table, sorted;
+    /// read on the first directory block check
+    group_metadata: Lock<Option<Vec<Range<u64>>>>,
 }
 
+#[cfg(feature = "std")]
 impl FileSystem {
     /// Open and initialize an ext4 filesystem
     ///
+    /// `path` may be a bare filesystem image or device, or a whole disk
+    /// with a partition table. A `#pN` suffix selects partition N, as in
+    /// `disk.img#p2`; without one, a disk that does not itself start with
+    /// a filesystem is opened at its first ext2/3/4 partition.
+    ///
+    /// Container formats are detected as by [`ImageFile::open`], and the
+    /// backing files of a qcow2 image are opened too; use
+    /// [`open_with`](Self::open_with) for images that cannot be trusted to
+    /// name only their own backing files.
+    ///
     /// # Arguments
     /// * `path` - Path to filesystem image or device file
     ///
     /// # Returns
     /// Initialized FileSystem instance with parsed superblock
-    pub fn open(path: &str) -> std::io::Result<Self> {
-        let mut device = File::open(path)?;
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::open_with(path, BackingFiles::Follow)
+    }
+
+    /// Open and initialize an ext4 filesystem, choosing whether the backing
+    /// files of a qcow2 image are opened
+    ///
+    /// # Arguments
+    /// * `path` - Path to filesystem image or device file, as for
+    ///   [`open`](Self::open)
+    /// * `backing` - Whether to open backing files
+    pub fn open_with(path: &str, backing: BackingFiles) -> io::Result<Self> {
+        let (disk, index) = split_partition_suffix(path);
+        let file = ImageFile::open_with(disk, backing)?;
+        let region = select_region(&file, disk, index)?;
+        Self::from_device(DeviceWindow::new(
+            file,
+            region.start,
+            region.end - region.start,
+        ))
+    }
+
+    /// Open the filesystem in one partition of a whole-disk image
+    ///
+    /// # Arguments
+    /// * `path` - Path to the disk image or device
+    /// * `index` - Partition number as listed by [`read_partitions`]
+    ///
+    /// # Errors
+    /// Returns `NotFound` if the disk has no such partition
+    pub fn open_partition(path: &str, index: u32) -> io::Result<Self> {
+        let file = ImageFile::open(path)?;
+        let region = select_region(&file, path, Some(index))?;
+        Self::from_device(DeviceWindow::new(
+            file,
+            region.start,
+            region.end - region.start,
+        ))
+    }
+}
 
+#[cfg(feature = "std")]
+impl FileSystem<MappedImage> {
+    /// Memory-map an image and open the filesystem in it
+    ///
+    /// Metadata blocks are parsed straight from the mapping instead of
+    /// being copied into buffers, so no block cache is needed. Accepts the
+    /// same paths as [`FileSystem::open`], but only regular files can be
+    /// mapped.
+    ///
+    /// # Arguments
+    /// * `path` - Path to filesystem or disk image, optionally with `#pN`
+    ///
+    /// # Safety
+    /// The image file must not be truncated or modified while the returned
+    /// filesystem is alive, as for [`MappedImage::open`].
+    pub unsafe fn open_mmap(path: &str) -> io::Result<Self> {
+        let (disk, index) = split_partition_suffix(path);
+        // SAFETY: forwarded to the caller
+        let image = unsafe { MappedImage::open(disk)? };
+        let region = select_region(&image, disk, index)?;
+        Self::from_device(image.slice(region.start, region.end - region.start)?)
+    }
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// Initialize an ext4 filesystem stored on an arbitrary device
+    ///
+    /// # Arguments
+    /// * `device` - Device holding the filesystem, starting at offset 0
+    ///
+    /// # Returns
+    /// Initialized FileSystem instance with parsed superblock
+    ///
+    /// # Errors
+    /// Returns `InvalidData` if the device does not hold an ext2/3/4 superblock
+    pub fn from_device(device: D) -> io::Result<Self> {
         // Read superblock at standard location (offset 1024, size 1024)
-        let buf = read_block(&mut device, 1024, 1024)?;
+        let buf = read_block(&device, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE)?;
+        if !Superblock::has_magic(&buf) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "No ext2/3/4 superblock found",
+            ));
+        }
         let sb = Superblock::parse(&buf);
+        sb.validate()?;
 
+        let mut device = CachedDevice::new(device, DEFAULT_CACHE_CAPACITY)?;
+        device.set_block_size(sb.block_size());
         Ok(FileSystem {
             device,
             superblock: sb,
+            cwd: Vec::new(),
+            inodes: Lock::new(LruCache::new(INODE_CACHE_ENTRIES)),
+            dentries: Lock::new(LruCache::new(DENTRY_CACHE_ENTRIES)),
+            group_metadata: Lock::new(None),
         })
     }
 
-    pub fn read_file(&mut self, path: &str) -> std::io::Result<Vec<u8>> {
-        let inode_num = self.resolve_path(path)?;
-        let inode = self.read_inode(inode_num)?;
-        self.read_file_from_inode(&inode)
+    /// Consume the filesystem and return the device it was read from
+    pub fn into_device(self) -> D {
+        self.device.into_inner()
     }
 
-    pub fn list_dir(&mut self, path: &str) -> std::io::Result<Vec<DirectoryEntry>> {
-        let inode_num = self.resolve_path(path)?;
-        let inode = self.read_inode(inode_num)?;
-        if !inode.is_dir() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Not a directory",
-            ));
-        }
-        self.read_dir(inode_num)
+    /// Set how many bytes of metadata blocks may be kept in memory
+    ///
+    /// Inode tables, group descriptors, directory and extent tree blocks
+    /// are cached so repeated lookups do not re-read the device; file
+    /// content is not cached. A budget smaller than one block disables
+    /// the cache.
+    ///
+    /// # Arguments
+    /// * `bytes` - Byte budget; the default is 8 MiB
+    pub fn set_cache_capacity(&self, bytes: usize) {
+        self.device.set_capacity(bytes);
     }
-}
 
-impl std::fmt::Display for FileSystem {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.superblock)
+    /// Hit and miss counts of the block cache so far
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.stats()
     }
-}
 
-impl FileSystem {
-    /// Read and parse an inode by its number
+    /// Forget cached state derived from a byte range of the device
     ///
-    /// # Arguments
-    /// * `inode_num` - Inode number (1-indexed)
+    /// Call this when blocks change underneath the filesystem, e.g. after
+    /// writing to the device or replaying the journal. Cached blocks and
+    /// inodes in the range are dropped, as are all directory lookups,
+    /// since the blocks of a directory cannot be told apart cheaply.
     ///
-    /// # Returns
-    /// Parsed Inode structure
-    fn read_inode(&mut self, inode_num: u32) -> std::io::Result<Inode> {
-        let block_size = self.superblock.block_size() as u64;
+    /// # Arguments
+    /// * `offset` - Byte offset of the changed range
+    /// * `len` - Length of the changed range in bytes
+    pub fn invalidate_range(&self, offset: u64, len: u64) {
+        self.device.invalidate(offset, len);
         let inode_size = self.superblock.inode_size as u64;
-        let inodes_per_group = self.superblock.inodes_per_group;
-
-        // Convert to 0-indexed
-        let inode_index = inode_num - 1;
-
-        // Determine which block group contains this inode
-        let group_index = inode_index / inodes_per_group;
-        let local_index = inode_index % inodes_per_group;
-
-        // Get group descriptor to find inode table location
-        let group = self.read_group_desc(group_index)?;
-        let inode_table_block = group.inode_table_block;
-
-        // Calculate byte offset of the specific inode
-        let inode_table_offset = inode_table_block as u64 * block_size;
-        let inode_offset = inode_table_offset + (local_index as u64 * inode_size);
+        let end = offset.saturating_add(len);
+        lock(&self.inodes).retain(|_, &(at, _)| at + inode_size <= offset || end <= at);
+        lock(&self.dentries).clear();
+    }
 
-        // Read and parse the inode data
-        let mut buf = vec![0u8; inode_size as usize];
-        self.device.seek(SeekFrom::Start(inode_offset))?;
-        self.device.read_exact(&mut buf)?;
+    /// Forget a cached inode along with lookups in it and of it
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode whose metadata or directory content changed
+    pub fn invalidate_inode(&self, inode_num: u32) {
+        lock(&self.inodes).remove(&inode_num);
+        lock(&self.dentries).retain(|(parent, _), entry| {
+            *parent != inode_num && entry.as_ref().is_none_or(|e| e.inode != inode_num)
+        });
+    }
 
-        Ok(Inode::parse(&buf))
+    /// Drop all cached blocks, inodes and directory lookups
+    pub fn clear_caches(&self) {
+        self.device.clear();
+        lock(&self.inodes).clear();
+        lock(&self.dentries).clear();
+        *lock(&self.group_metadata) = None;
     }
 
-    /// Read a block group descriptor by index
+    /// Change the directory that relative paths are resolved against
     ///
     /// # Arguments
-    /// * `group_index` - 0-indexed block group number
+    /// * `path` - Path of the new current directory, absolute or relative
     ///
-    /// # Returns
-    /// Parsed GroupDescriptor for the specified group
-    fn read_group_desc(&mut self, group_index: u32) -> std::io::Result<GroupDescriptor> {
-        let block_size = self.superblock.block_size();
+    /// # Errors
+    /// Returns error if the path does not exist or is not a directory
+    pub fn set_current_dir(&mut self, path: impl AsRef<[u8]>) -> io::Result<()> {
+        let path = path.as_ref();
+        let stack = self.walk_path(path, true)?;
+        let inode_num = stack.last().map_or(EXT4_ROOT_INO, |&(_, inode)| inode);
+        if !self.read_inode(inode_num)?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("'{}' is not a directory", escape_name(path)),
+            ));
+        }
+        self.cwd = stack;
+        Ok(())
+    }
 
-        // Group descriptor table location depends on block size
-        let desc_table_offset = if block_size == 1024 {
-            2048 // Block 2 for 1KB blocks (superblock takes blocks 0-1)
-        } else {
-            block_size // Block 1 for larger blocks (superblock is block 0)
-        };
+    /// The current directory as a normalised absolute path
+    ///
+    /// `.`, `..` and symlinks are resolved, so this is the canonical
+    /// location rather than the path that was passed to `set_current_dir`.
+    /// Names are returned as stored and may not be valid UTF-8.
+    pub fn current_dir(&self) -> Vec<u8> {
+        let mut path = Vec::new();
+        for (name, _) in &self.cwd {
+            path.push(b'/');
+            path.extend_from_slice(name);
+        }
+        if path.is_empty() {
+            path.push(b'/');
+        }
+        path
+    }
 
-        // Each group descriptor is 32 bytes
-        let offset = desc_table_offset as u64 + group_index as u64 * 32;
+    pub fn read_file(&self, path: impl AsRef<[u8]>) -> io::Result<Vec<u8>> {
+        self.read_file_with(path, FollowSymlinks::Follow)
+    }
 
-        // Read and parse group descriptor
-        let mut buf = [0u8; 32];
-        self.device.seek(SeekFrom::Start(offset))?;
-        self.device.read_exact(&mut buf)?;
+    /// Read the whole content of a file, choosing whether a symlink in the
+    /// last component is followed
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file
+    /// * `follow` - What to do if `path` names a symlink
+    ///
+    /// # Errors
+    /// Returns `InvalidInput` if `path` names a symlink and `follow` is
+    /// [`FollowSymlinks::NoFollow`]
+    pub fn read_file_with(
+        &self,
+        path: impl AsRef<[u8]>,
+        follow: FollowSymlinks,
+    ) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        let inode_num = self.resolve_path_with(path, follow == FollowSymlinks::Follow)?;
+        let inode = self.read_inode(inode_num)?;
+        refuse_symlink(path, &inode)?;
+        self.read_file_from_inode(inode_num, &inode)
+    }
 
-        Ok(GroupDescriptor::parse(&buf))
+    /// Open a file for reading without loading its content
+    ///
+    /// Unlike [`read_file`](Self::read_file), only the blocks that are
+    /// actually read are fetched, so this is suitable for large files.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file; symlinks are followed
+    ///
+    /// # Errors
+    /// Returns error if the path does not exist, is a directory, or the
+    /// inode is neither extent-mapped nor inline
+    pub fn open_file(&self, path: impl AsRef<[u8]>) -> io::Result<Ext4File<'_, D>> {
+        let inode_num = self.resolve_path(path)?;
+        self.open_inode(inode_num)
     }
 
-    /// Read and parse all directory entries from a directory inode
+    /// Copy a directory tree out of the filesystem, several files at a time
+    ///
+    /// Directories are recreated first, then regular files are streamed by
+    /// worker threads that share this filesystem, and symlinks are created
+    /// last. Device nodes, FIFOs and sockets are skipped, and file modes,
+    /// owners and timestamps are not restored.
+    ///
+    /// The image is not trusted: nothing is written outside `dest`. Names
+    /// that are not a single host path component are rejected, files are
+    /// only ever created, never opened through an existing file or
+    /// symlink, and no path is resolved through a symlink, whether it was
+    /// already in `dest` or extracted from the image.
     ///
     /// # Arguments
-    /// * `inode_num` - Inode number of the directory to read
+    /// * `path` - Directory to extract; symlinks are followed
+    /// * `dest` - Host directory to extract into, created if missing
+    /// * `threads` - Files copied at once; 0 picks the available parallelism
     ///
     /// # Returns
-    /// Vector of all directory entries found in the directory
+    /// Number of regular files extracted
     ///
     /// # Errors
-    /// Returns error if:
-    /// - Inode cannot be read
-    /// - Inode is not a directory
-    /// - Block reading fails
-    fn read_dir(&mut self, inode_num: u32) -> std::io::Result<Vec<DirectoryEntry>> {
-        // Read the inode to get block pointers and verify it's a directory
-        let inode = self.read_inode(inode_num)?;
-
-        // Check if inode is a directory (mode & 0xF000 == 0x4000)
-        if (inode.inode_mode & 0xF000) != 0x4000 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("Inode {} is not a directory", inode_num),
+    /// Returns the first error hit; files not yet started are then skipped.
+    /// Returns `InvalidData` for a name that cannot be a host file name or a
+    /// directory reached twice, and `AlreadyExists` if a file or symlink to
+    /// be created is already in `dest`
+    #[cfg(feature = "std")]
+    pub fn extract_tree(
+        &self,
+        path: impl AsRef<[u8]>,
+        dest: impl AsRef<Path>,
+        threads: usize,
+    ) -> io::Result<usize>
+    where
+        D: Sync,
+    {
+        let root = self.resolve_path(path)?;
+        if !self.read_inode(root)?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("Inode {} is not a directory", root),
             ));
         }
 
-        let block_size = self.superblock.block_size() as usize;
-        let mut entries = Vec::new();
-
-        // Process each data block pointed to by the inode
-        for &block in &inode.extent_blocks {
-            // Skip unallocated blocks
-            if block == 0 || block > 8192 {
-                continue;
-            }
-
-            // Read the entire block containing directory entries
-            let offset = block as u64 * block_size as u64;
-            self.device.seek(SeekFrom::Start(offset))?;
-            let mut buf = vec![0u8; block_size];
-            self.device.read_exact(&mut buf)?;
-
-            // Parse directory entries sequentially within the block
-            let mut cursor = 0;
-            while cursor < block_size {
-                let remaining_buf = &buf[cursor..];
-
-                match DirectoryEntry::parse(remaining_buf) {
-                    Some((entry, rec_len)) => {
-                        entries.push(entry);
-                        cursor += rec_len;
+        // Create the directories while collecting the files and symlinks.
+        // Each directory is entered once, so hard-linked directories cannot
+        // make the walk loop
+        std::fs::create_dir_all(&dest)?;
+        let mut files = Vec::new();
+        let mut symlinks = Vec::new();
+        let mut visited = std::collections::BTreeSet::from([root]);
+        let mut pending = vec![(root, dest.as_ref().to_path_buf())];
+        while let Some((inode_num, dir)) = pending.pop() {
+            for entry in self.read_dir(inode_num)? {
+                if entry.name == b"." || entry.name == b".." {
+                    continue;
+                }
+                let target = dir.join(host_name(&entry.name)?);
+                if entry.is_directory() {
+                    if !visited.insert(entry.inode) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Directory inode {} is linked more than once", entry.inode),
+                        ));
                     }
-                    None => break, // Invalid or end of entries
+                    create_host_dir(&target)?;
+                    pending.push((entry.inode, target));
+                } else if entry.is_file() {
+                    files.push((entry.inode, target));
+                } else if entry.is_symlink() {
+                    symlinks.push((entry.inode, target));
                 }
             }
         }
 
-        Ok(entries)
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let next = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.min(files.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        while let Some((inode_num, target)) =
+                            files.get(next.fetch_add(1, Ordering::Relaxed))
+                        {
+                            if let Err(e) = self.extract_file(*inode_num, target) {
+                                // Leave nothing for the other workers
+                                next.store(files.len(), Ordering::Relaxed);
+                                return Err(e);
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers.into_iter().try_for_each(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+        })?;
+
+        // Only now that nothing else is written can symlinks appear
+        for (inode_num, target) in symlinks {
+            let inode = self.read_inode(inode_num)?;
+            let link = self.read_link_target(inode_num, &inode)?;
+            create_symlink(&link, &target)?;
+        }
+        Ok(files.len())
     }
 
-    fn resolve_path(&mut self, path: &str) -> std::io::Result<u32> {
-        // Start at root inode (inode number 2)
-        let mut current_inode_num = 2;
+    /// Stream one regular file to a new host file
+    ///
+    /// The file is created exclusively and without following a symlink in
+    /// its place, so nothing already on the host is overwritten.
+    #[cfg(feature = "std")]
+    fn extract_file(&self, inode_num: u32, target: &Path) -> io::Result<()> {
+        let mut file = self.open_inode(inode_num)?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NOFOLLOW);
+        }
+        let mut out = options.open(target)?;
+        std::io::copy(&mut file, &mut out)?;
+        Ok(())
+    }
 
-        // Handle root path directly
-        if path == "/" {
-            return Ok(current_inode_num);
+    /// Open an inode for reading, as [`open_file`](Self::open_file) does
+    fn open_inode(&self, inode_num: u32) -> io::Result<Ext4File<'_, D>> {
+        let inode = self.read_inode(inode_num)?;
+        if inode.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("Inode {} is a directory", inode_num),
+            ));
+        }
+        if inode.extent_root.is_none() && inode.inline_data.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Inode {} is not extent-mapped", inode_num),
+            ));
         }
+        Ok(Ext4File::new(self, inode_num, inode))
+    }
 
-        for component in path.split('/').filter(|s| !s.is_empty()) {
-            let entries = self.read_dir(current_inode_num)?;
+    pub fn list_dir(&self, path: impl AsRef<[u8]>) -> io::Result<Vec<DirectoryEntry>> {
+        let inode_num = self.dir_inode(path)?;
+        self.read_dir(inode_num)
+    }
 
-            let next_entry = entries
-                .into_iter()
-                .find(|e| e.name == component)
-                .ok_or_else(|| {
-                    std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        format!("Component '{}' not found", component),
-                    )
-                })?;
+    /// List a directory, choosing whether a symlink in the last component
+    /// is followed
+    ///
+    /// # Arguments
+    /// * `path` - Path of the directory
+    /// * `follow` - What to do if `path` names a symlink
+    ///
+    /// # Errors
+    /// Returns `InvalidInput` if `path` names a symlink and `follow` is
+    /// [`FollowSymlinks::NoFollow`]
+    pub fn list_dir_with(
+        &self,
+        path: impl AsRef<[u8]>,
+        follow: FollowSymlinks,
+    ) -> io::Result<Vec<DirectoryEntry>> {
+        let path = path.as_ref();
+        if follow == FollowSymlinks::NoFollow {
+            let inode_num = self.resolve_path_with(path, false)?;
+            refuse_symlink(path, &self.read_inode(inode_num)?)?;
+        }
+        self.list_dir(path)
+    }
 
-            current_inode_num = next_entry.inode;
+    /// Resolve a path that must name a directory
+    fn dir_inode(&self, path: impl AsRef<[u8]>) -> io::Result<u32> {
+        let inode_num = self.resolve_path(path)?;
+        let inode = self.read_inode(inode_num)?;
+        if !inode.is_dir() {
+            return Err(io::Error::other("Not a directory"));
         }
+        Ok(inode_num)
+    }
 
-        Ok(current_inode_num)
+    /// List deleted entries whose names are still present in a directory
+    ///
+    /// Intended for forensic use: recovered entries may point at inodes
+    /// that have since been freed or reused, and entries whose record was
+    /// cleared in place report inode 0.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the directory
+    ///
+    /// # Returns
+    /// Recovered entries in on-disk order
+    pub fn list_deleted(&self, path: impl AsRef<[u8]>) -> io::Result<Vec<DirectoryEntry>> {
+        let inode_num = self.resolve_path(path)?;
+        self.scan_dir(inode_num, true)
     }
 
-    fn read_file_from_inode(&mut self, inode: &Inode) -> std::io::Result<Vec<u8>> {
-        let mut content = Vec::new();
-        let block_size = self.superblock.block_size();
+    /// Query metadata about a file or directory
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file or directory
+    ///
+    /// # Returns
+    /// Type, permissions, size, ownership and timestamps of the inode
+    pub fn metadata(&self, path: impl AsRef<[u8]>) -> io::Result<Metadata> {
+        self.metadata_with(path, FollowSymlinks::Follow)
+    }
+
+    /// Query metadata, choosing whether a symlink in the last component is
+    /// followed
+    ///
+    /// With [`FollowSymlinks::NoFollow`] this is
+    /// [`symlink_metadata`](Self::symlink_metadata).
+    pub fn metadata_with(
+        &self,
+        path: impl AsRef<[u8]>,
+        follow: FollowSymlinks,
+    ) -> io::Result<Metadata> {
+        let inode_num = self.resolve_path_with(path.as_ref(), follow == FollowSymlinks::Follow)?;
+        let inode = self.read_inode(inode_num)?;
+        Ok(Metadata::from_inode(inode_num, &inode))
+    }
+
+    /// Query metadata without following a symlink in the last component
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file, directory or symlink
+    ///
+    /// # Returns
+    /// Metadata of the symlink itself if `path` names one
+    pub fn symlink_metadata(&self, path: impl AsRef<[u8]>) -> io::Result<Metadata> {
+        self.metadata_with(path, FollowSymlinks::NoFollow)
+    }
+
+    /// Read the target of a symbolic link
+    ///
+    /// # Arguments
+    /// * `path` - Path of the symlink; earlier components may be symlinks too
+    ///
+    /// # Returns
+    /// The link target exactly as stored, which may be relative
+    pub fn read_link(&self, path: impl AsRef<[u8]>) -> io::Result<Vec<u8>> {
+        let inode_num = self.resolve_path_with(path.as_ref(), false)?;
+        let inode = self.read_inode(inode_num)?;
+        self.read_link_target(inode_num, &inode)
+    }
+
+    /// Read the full inode metadata for a path
+    ///
+    /// Like debugfs, a symlink in the last component is not followed.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file or directory
+    ///
+    /// # Returns
+    /// Inode number and parsed fields, printable in debugfs `stat` format
+    pub fn stat(&self, path: impl AsRef<[u8]>) -> io::Result<InodeStat> {
+        let inode_num = self.resolve_path_with(path.as_ref(), false)?;
+        let inode = self.read_inode(inode_num)?;
+        let sectors = inode.sector_count(
+            self.superblock.has_huge_file(),
+            self.superblock.block_size(),
+        );
+        let extents = if inode.extent_root.is_some() {
+            self.read_extents(inode_num, &inode)?
+        } else {
+            Vec::new()
+        };
+        Ok(InodeStat {
+            inode_num,
+            inode,
+            extents,
+            sectors,
+        })
+    }
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// List the extended attributes of a file or directory
+    ///
+    /// Attributes stored in the inode body come first, followed by those in
+    /// the external xattr block referenced by `i_file_acl`. The inline-data
+    /// `system.data` attribute is included like any other.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file or directory
+    ///
+    /// # Returns
+    /// Attributes with their full names (e.g. `user.comment`) and values
+    pub fn xattrs(&self, path: impl AsRef<[u8]>) -> io::Result<Vec<Xattr>> {
+        self.xattr_entries(path)?
+            .into_iter()
+            .map(|entry| {
+                let value = self.read_xattr_value(&entry)?;
+                Ok(Xattr {
+                    name: entry.full_name(),
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    /// Read a single extended attribute by its full name
+    ///
+    /// Only the value of the matching attribute is read.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file or directory
+    /// * `name` - Full attribute name including namespace, e.g. `user.comment`
+    ///
+    /// # Returns
+    /// The attribute value, or `None` if the attribute does not exist
+    pub fn get_xattr(&self, path: impl AsRef<[u8]>, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.xattr_entries(path)?
+            .into_iter()
+            .find(|entry| entry.full_name() == name)
+            .map(|entry| self.read_xattr_value(&entry))
+            .transpose()
+    }
+
+    /// The xattr entries of a file, in-inode first, without values stored
+    /// in separate inodes
+    ///
+    /// # Errors
+    /// Returns `InvalidData` if the in-inode area or the xattr block is corrupt
+    fn xattr_entries(&self, path: impl AsRef<[u8]>) -> io::Result<Vec<XattrEntry>> {
+        let inode_num = self.resolve_path(path)?;
+        let inode = self.read_inode(inode_num)?;
 
-        for block in &inode.extent_blocks {
-            let offset = block * block_size as u64;
-            let block_data = read_block(&mut self.device, offset, block_size)?;
-            content.extend_from_slice(&block_data);
+        let mut entries = inode.xattrs.clone().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid in-inode xattrs in inode {}", inode_num),
+            )
+        })?;
+        if inode.file_acl != 0 {
+            let block_size = self.superblock.block_size();
+            let buf = read_block(&self.device, inode.file_acl * block_size as u64, block_size)?;
+            let block_entries = parse_block_xattrs(&buf).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid xattr block {} in inode {}",
+                        inode.file_acl, inode_num
+                    ),
+                )
+            })?;
+            entries.extend(block_entries);
         }
+        Ok(entries)
+    }
 
-        content.truncate(inode.inode_size as usize);
-        Ok(content)
+    /// Read the access ACL of a file or directory
+    ///
+    /// Falls back to the minimal ACL equivalent to the permission bits when
+    /// there is no `system.posix_acl_access` attribute.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file or directory
+    pub fn acl(&self, path: impl AsRef<[u8]>) -> io::Result<Acl> {
+        let path = path.as_ref();
+        match self.read_acl_xattr(path, "system.posix_acl_access")? {
+            Some(acl) => Ok(acl),
+            None => Ok(Acl::from_mode(self.metadata(path)?.mode())),
+        }
+    }
+
+    /// Read the default ACL that a directory passes on to new entries
+    ///
+    /// # Arguments
+    /// * `path` - Path of the directory
+    ///
+    /// # Returns
+    /// The decoded `system.posix_acl_default` attribute, or `None` if unset
+    pub fn default_acl(&self, path: impl AsRef<[u8]>) -> io::Result<Option<Acl>> {
+        self.read_acl_xattr(path.as_ref(), "system.posix_acl_default")
     }
 }
 
-#[test]
-fn test_resolve_path() {
-    let mut fs = FileSystem::open("ext4.img").expect("Failed to open image");
-    let inode_num = fs
-        .resolve_path("/home/zyu379/test_file.txt")
-        .expect("Path not resolved");
-    let _ = fs.read_inode(inode_num).expect("Failed to read inode");
+impl<D: BlockDevice> core::fmt::Display for FileSystem<D> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.superblock)
+    }
 }
 
-#[test]
-fn test_read_test_file() {
-    let mut fs = FileSystem::open("ext4.img").unwrap();
-    let content = fs.read_file("/home/zyu379/test_file.txt").unwrap();
-    assert_eq!(String::from_utf8_lossy(&content), "hello from ext4 test\n");
+impl<D: BlockDevice> FileSystem<D> {
+    /// Read and parse an inode by its number
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number (1-indexed)
+    ///
+    /// # Returns
+    /// Parsed Inode structure
+    fn read_inode(&self, inode_num: u32) -> io::Result<Inode> {
+        if let Some((_, inode)) = lock(&self.inodes).get(&inode_num) {
+            return Ok(inode.clone());
+        }
+
+        let inode_size = self.superblock.inode_size as u64;
+        let inode_offset = self.inode_offset(inode_num)?;
+
+        // Read and parse the inode data
+        let buf = read_block(&self.device, inode_offset, inode_size as u32)?;
+
+        let inode = Inode::parse(&buf);
+        self.verify_inode_checksum(inode_num, &inode, &buf)?;
+        // Children are one level shallower, so this bounds the whole tree
+        if let Some(root) = &inode.extent_root
+            && root.header.tree_depth > EXT4_MAX_EXTENT_DEPTH
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Extent tree of inode {} is too deep", inode_num),
+            ));
+        }
+        // Inline content continues in an xattr that could not be located
+        if inode.inline_data.is_some() && inode.xattrs.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid inline data xattr in inode {}", inode_num),
+            ));
+        }
+        lock(&self.inodes).insert(inode_num, (inode_offset, inode.clone()));
+        Ok(inode)
+    }
+
+    /// Locate an inode on disk
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number (1-indexed)
+    ///
+    /// # Returns
+    /// Byte offset of the inode within the device
+    ///
+    /// # Errors
+    /// Returns `InvalidData` for inode 0 and numbers past `s_inodes_count`
+    fn inode_offset(&self, inode_num: u32) -> io::Result<u64> {
+        let block_size = self.superblock.block_size() as u64;
+        let inode_size = self.superblock.inode_size as u64;
+        let inodes_per_group = self.superblock.inodes_per_group;
+
+        if inode_num == 0 || inode_num > self.superblock.inodes_count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Inode number {} is out of range", inode_num),
+            ));
+        }
+
+        // Convert to 0-indexed
+        let inode_index = inode_num - 1;
+
+        // Determine which block group contains this inode
+        let group_index = inode_index / inodes_per_group;
+        let local_index = inode_index % inodes_per_group;
+
+        // Get group descriptor to find inode table location
+        let group = self.read_group_desc(group_index)?;
+        let inode_table_block = group.inode_table_block;
+
+        // Calculate byte offset of the specific inode
+        let inode_table_offset = inode_table_block * block_size;
+        Ok(inode_table_offset + (local_index as u64 * inode_size))
+    }
+
+    /// Verify an inode's checksum when `metadata_csum` is enabled
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number, part of the checksum seed
+    /// * `inode` - Parsed inode, providing the generation and stored checksum
+    /// * `raw` - Raw on-disk inode bytes the checksum covers
+    fn verify_inode_checksum(&self, inode_num: u32, inode: &Inode, raw: &[u8]) -> io::Result<()> {
+        // Never-used inodes are all zeros and carry no checksum
+        if !self.superblock.has_metadata_csum() || raw.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+
+        let seed = inode_seed(self.superblock.csum_seed(), inode_num, inode.generation);
+        let has_hi = raw.len() > EXT4_GOOD_OLD_INODE_SIZE && inode.has_checksum_hi();
+        let mut computed = inode_checksum(raw, seed, has_hi);
+        let mut expected = inode.checksum;
+        if !has_hi {
+            // Only i_checksum_lo is stored
+            computed &= 0xFFFF;
+            expected &= 0xFFFF;
+        }
+
+        if computed != expected {
+            return Err(ChecksumError {
+                kind: ChecksumKind::Inode { inode: inode_num },
+                expected,
+                computed,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Collect the leaf extents of an inode, walking index blocks as needed
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number, used to seed extent block checksums
+    /// * `inode` - Parsed inode whose extent tree to traverse
+    ///
+    /// # Returns
+    /// All leaf extents in logical order; empty for inline-data inodes
+    ///
+    /// # Errors
+    /// Returns error if:
+    /// - The inode is neither extent-mapped nor inline
+    /// - A tree block is malformed or fails checksum verification
+    fn read_extents(&self, inode_num: u32, inode: &Inode) -> io::Result<Vec<Extent>> {
+        let root = match &inode.extent_root {
+            Some(root) => root.clone(),
+            None if inode.inline_data.is_some() => return Ok(Vec::new()),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("Inode {} is not extent-mapped", inode_num),
+                ));
+            }
+        };
+
+        let mut extents = Vec::new();
+        let mut last_starts = [None; EXT4_MAX_EXTENT_DEPTH as usize + 1];
+        self.walk_extent_node(
+            inode_num,
+            inode.generation,
+            &root,
+            &mut last_starts,
+            &mut extents,
+        )?;
+        Ok(extents)
+    }
+
+    /// Recursively gather leaf extents below an extent tree node
+    ///
+    /// `last_starts` holds the last index `first_block` seen at each depth.
+    /// Subtrees cover increasing ranges across a whole level, so a block
+    /// referenced twice shows up as an index out of order instead of being
+    /// walked again.
+    fn walk_extent_node(
+        &self,
+        inode_num: u32,
+        generation: u32,
+        node: &ExtentNode,
+        last_starts: &mut [Option<u32>],
+        extents: &mut Vec<Extent>,
+    ) -> io::Result<()> {
+        let indexes = match &node.entries {
+            ExtentEntries::Leaf(leaves) => {
+                extents.extend(leaves.iter().cloned());
+                return Ok(());
+            }
+            ExtentEntries::Index(indexes) => indexes,
+        };
+
+        let last = &mut last_starts[node.header.tree_depth as usize];
+        for index in indexes {
+            if last.is_some_and(|last| index.first_block <= last) {
+                return Err(unordered_extent_index(inode_num));
+            }
+            *last = Some(index.first_block);
+        }
+        for index in indexes {
+            let child = self.read_extent_child(inode_num, generation, node, index)?;
+            self.walk_extent_node(inode_num, generation, &child, last_starts, extents)?;
+        }
+        Ok(())
+    }
+
+    /// Find the leaf holding a logical block, descending only the one path
+    /// through the extent tree that can contain it
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number, used to seed extent block checksums
+    /// * `inode` - Parsed extent-mapped inode
+    /// * `logical_block` - Logical block of the file to look up
+    ///
+    /// # Returns
+    /// The extents of the leaf whose range includes `logical_block`, and
+    /// the first logical block of the subtree that follows it, or `None` if
+    /// no extent lies beyond the leaf. The block is a hole if none of the
+    /// extents covers it
+    fn find_extent_leaf(
+        &self,
+        inode_num: u32,
+        inode: &Inode,
+        logical_block: u32,
+    ) -> io::Result<(Vec<Extent>, Option<u32>)> {
+        let mut node = match &inode.extent_root {
+            Some(root) => root.clone(),
+            None => return Ok((Vec::new(), None)),
+        };
+
+        // Deeper subtrees are narrower, so the innermost next sibling is
+        // the closest
+        let mut next = None;
+        loop {
+            let indexes = match &node.entries {
+                ExtentEntries::Leaf(leaves) => return Ok((leaves.clone(), next)),
+                ExtentEntries::Index(indexes) => indexes,
+            };
+            if indexes
+                .windows(2)
+                .any(|pair| pair[0].first_block >= pair[1].first_block)
+            {
+                return Err(unordered_extent_index(inode_num));
+            }
+            // The last subtree starting at or before the block
+            let i = indexes.partition_point(|index| index.first_block <= logical_block);
+            if let Some(sibling) = indexes.get(i) {
+                next = Some(sibling.first_block);
+            }
+            let Some(index) = i.checked_sub(1).map(|i| &indexes[i]) else {
+                return Ok((Vec::new(), next));
+            };
+            node = self.read_extent_child(inode_num, inode.generation, &node, index)?;
+        }
+    }
+
+    /// Read and verify the extent tree block an index entry points to
+    ///
+    /// # Errors
+    /// Returns error if the block is malformed, not one level below
+    /// `parent`, or fails checksum verification
+    fn read_extent_child(
+        &self,
+        inode_num: u32,
+        generation: u32,
+        parent: &ExtentNode,
+        index: &ExtentIndex,
+    ) -> io::Result<ExtentNode> {
+        let block_size = self.superblock.block_size();
+        let block = index.leaf_block();
+        let buf = read_block(&self.device, block * block_size as u64, block_size)?;
+
+        let child = ExtentNode::parse(&buf)
+            .filter(|child| child.header.tree_depth + 1 == parent.header.tree_depth)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid extent block {} in inode {}", block, inode_num),
+                )
+            })?;
+
+        if self.superblock.has_metadata_csum() {
+            let tail = child.tail_offset();
+            if tail + 4 > buf.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Extent block {} has no room for a checksum", block),
+                ));
+            }
+            let expected = u32::from_le_bytes(buf[tail..tail + 4].try_into().unwrap());
+            let seed = inode_seed(self.superblock.csum_seed(), inode_num, generation);
+            let computed = crc32c(seed, &buf[..tail]);
+            if computed != expected {
+                return Err(ChecksumError {
+                    kind: ChecksumKind::ExtentBlock {
+                        inode: inode_num,
+                        block,
+                    },
+                    expected,
+                    computed,
+                }
+                .into());
+            }
+        }
+        Ok(child)
+    }
+
+    /// Read a block group descriptor by index
+    ///
+    /// # Arguments
+    /// * `group_index` - 0-indexed block group number
+    ///
+    /// # Returns
+    /// Parsed GroupDescriptor for the specified group
+    fn read_group_desc(&self, group_index: u32) -> io::Result<GroupDescriptor> {
+        let block_size = self.superblock.block_size();
+
+        // Group descriptor table location depends on block size
+        let desc_table_offset = if block_size == 1024 {
+            2048 // Block 2 for 1KB blocks (superblock takes blocks 0-1)
+        } else {
+            block_size // Block 1 for larger blocks (superblock is block 0)
+        };
+
+        // Each group descriptor is 32 bytes, or s_desc_size with 64bit
+        let desc_size = self.superblock.desc_size as u32;
+        let offset = desc_table_offset as u64 + group_index as u64 * desc_size as u64;
+
+        // Read and parse group descriptor
+        let buf = read_block(&self.device, offset, desc_size)?;
+
+        Ok(GroupDescriptor::parse(&buf))
+    }
+
+    /// Read and parse all directory entries from a directory inode
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number of the directory to read
+    ///
+    /// # Returns
+    /// Vector of all directory entries found in the directory
+    ///
+    /// # Errors
+    /// Returns error if:
+    /// - Inode cannot be read
+    /// - Inode is not a directory
+    /// - Block reading fails or a block holds a malformed record
+    fn read_dir(&self, inode_num: u32) -> io::Result<Vec<DirectoryEntry>> {
+        self.scan_dir(inode_num, false)
+    }
+
+    /// Walk every record of a directory's blocks
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number of the directory to read
+    /// * `deleted` - Return deleted entries recovered from unused records
+    ///   and slack space instead of live ones
+    fn scan_dir(&self, inode_num: u32, deleted: bool) -> io::Result<Vec<DirectoryEntry>> {
+        let mut entries = Vec::new();
+        self.visit_dir(inode_num, deleted, 0, |_, block| {
+            entries.extend(block);
+            true
+        })?;
+        Ok(entries)
+    }
+
+    /// Walk every record of a directory's blocks, one block at a time
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number of the directory to read
+    /// * `deleted` - As for [`scan_dir`](Self::scan_dir)
+    /// * `from` - Position of the first block to visit, to resume a walk
+    /// * `visit` - Called with the position and entries of each block in
+    ///   turn; returning `false` ends the walk early
+    fn visit_dir(
+        &self,
+        inode_num: u32,
+        deleted: bool,
+        from: usize,
+        mut visit: impl FnMut(usize, Vec<DirectoryEntry>) -> bool,
+    ) -> io::Result<()> {
+        // Read the inode to get block pointers and verify it's a directory
+        let inode = self.read_inode(inode_num)?;
+
+        // Check if inode is a directory (mode & 0xF000 == 0x4000)
+        if (inode.inode_mode & 0xF000) != 0x4000 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Inode {} is not a directory", inode_num),
+            ));
+        }
+
+        if let Some(data) = &inode.inline_data {
+            let size = (inode.inode_size as usize).min(data.len());
+            let data = &data[..size];
+            let entries = if deleted {
+                inline_dir_regions(data)
+                    .into_iter()
+                    .map(parse_deleted_entries)
+                    .collect::<Option<Vec<_>>>()
+                    .map(|regions| regions.concat())
+            } else {
+                parse_inline_dir(inode_num, data)
+            };
+            let entries = entries.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Corrupt inline directory in inode {}", inode_num),
+                )
+            })?;
+            if from == 0 {
+                visit(0, entries);
+            }
+            return Ok(());
+        }
+
+        let block_size = self.superblock.block_size();
+
+        // Process each data block pointed to by the inode, with its
+        // logical block number. Blocks past i_size and extents running
+        // past the largest logical block are corrupt and skipped.
+        let size_blocks = inode.inode_size / block_size as u64;
+        let extents = self.read_extents(inode_num, &inode)?;
+        let blocks = extents
+            .iter()
+            .filter(|e| !e.is_unwritten())
+            .filter_map(|e| {
+                let end = e.logical_block.checked_add(e.len())?;
+                let end = (end as u64).min(size_blocks) as u32;
+                Some((e.logical_block..end).map(|logical_block| {
+                    let i = logical_block - e.logical_block;
+                    (logical_block, e.physical_block_start() + i as u64)
+                }))
+            })
+            .flatten();
+        for (position, (logical_block, block)) in blocks.enumerate().skip(from) {
+            self.check_dir_block(inode_num, logical_block, block)?;
+
+            // Read the entire block containing directory entries
+            let buf = read_block(&self.device, block * block_size as u64, block_size)?;
+            self.verify_dir_block_checksum(inode_num, &inode, logical_block, block, &buf)?;
+
+            // Interior htree nodes hold no entries; the root only `.` and `..`
+            if inode.is_indexed()
+                && is_index_block(&buf, logical_block)
+                && (logical_block != 0 || deleted)
+            {
+                continue;
+            }
+
+            // Parse directory entries sequentially within the block
+            let parsed = if deleted {
+                parse_deleted_entries(&buf)
+            } else {
+                parse_dir_block(&buf)
+            };
+            if !visit(
+                position,
+                parsed.ok_or_else(|| corrupt_dir_block(inode_num, block))?,
+            ) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reject a directory block that points outside the filesystem or
+    /// into the group metadata
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number of the directory
+    /// * `logical_block` - Logical block number within the directory
+    /// * `block` - Physical block number the extent maps it to
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if the block is out of range or holds
+    /// a block group's bitmaps or inode table
+    fn check_dir_block(&self, inode_num: u32, logical_block: u32, block: u64) -> io::Result<()> {
+        if !self.superblock.contains_block(block) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Block {} of directory {} maps to block {}, outside the filesystem ({} blocks)",
+                    logical_block, inode_num, block, self.superblock.blocks_count
+                ),
+            ));
+        }
+        if self.is_group_metadata(block)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Block {} of directory {} maps to block {}, which holds group metadata",
+                    logical_block, inode_num, block
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether a block holds the bitmaps or inode table of any group
+    ///
+    /// With `flex_bg` a group's metadata may live in another group, so all
+    /// descriptors are read once and the ranges kept.
+    fn is_group_metadata(&self, block: u64) -> io::Result<bool> {
+        let mut ranges = lock(&self.group_metadata);
+        if ranges.is_none() {
+            let table_blocks = self.superblock.inode_table_blocks();
+            let mut all = Vec::new();
+            for group in 0..self.superblock.group_count() {
+                let desc = self.read_group_desc(group)?;
+                all.push(desc.block_bitmap..desc.block_bitmap + 1);
+                all.push(desc.inode_bitmap..desc.inode_bitmap + 1);
+                all.push(
+                    desc.inode_table_block..desc.inode_table_block.saturating_add(table_blocks),
+                );
+            }
+            all.sort_by_key(|range| range.start);
+            // Merge overlaps, which only corrupt descriptors produce, so the
+            // ends are sorted as well
+            let mut merged: Vec<Range<u64>> = Vec::new();
+            for range in all {
+                match merged.last_mut() {
+                    Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                    _ => merged.push(range),
+                }
+            }
+            *ranges = Some(merged);
+        }
+        let ranges = ranges.as_ref().unwrap();
+        let index = ranges.partition_point(|range| range.end <= block);
+        Ok(ranges.get(index).is_some_and(|range| range.start <= block))
+    }
+
+    /// Verify a directory block's checksum when `metadata_csum` is enabled
+    ///
+    /// Leaf blocks are covered by an `ext4_dir_entry_tail`, htree index
+    /// blocks by a `dx_tail`.
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number of the directory, part of the seed
+    /// * `inode` - The directory's inode
+    /// * `logical_block` - Logical block number within the directory
+    /// * `block` - Physical block number, reported on mismatch
+    /// * `buf` - Block contents
+    fn verify_dir_block_checksum(
+        &self,
+        inode_num: u32,
+        inode: &Inode,
+        logical_block: u32,
+        block: u64,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        if !self.superblock.has_metadata_csum() {
+            return Ok(());
+        }
+
+        let seed = inode_seed(self.superblock.csum_seed(), inode_num, inode.generation);
+        let (kind, checksums) = if inode.is_indexed() && is_index_block(buf, logical_block) {
+            let kind = ChecksumKind::DxNode {
+                inode: inode_num,
+                block,
+            };
+            (kind, dx_checksum(buf, seed))
+        } else {
+            let kind = ChecksumKind::DirBlock {
+                inode: inode_num,
+                block,
+            };
+            let checksums = dir_entry_tail(buf).map(|stored| {
+                let computed = crc32c(seed, &buf[..buf.len() - EXT4_DIR_ENTRY_TAIL_SIZE]);
+                (stored, computed)
+            });
+            (kind, checksums)
+        };
+
+        let (expected, computed) = checksums.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Directory block {} of inode {} has no room for a checksum",
+                    block, inode_num
+                ),
+            )
+        })?;
+        if computed != expected {
+            return Err(ChecksumError {
+                kind,
+                expected,
+                computed,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Look up a single name in a directory, consulting the dentry cache
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number of the directory
+    /// * `name` - Name to find
+    ///
+    /// # Returns
+    /// The matching entry, or `None` if the directory has no such name
+    fn find_entry(&self, inode_num: u32, name: &[u8]) -> io::Result<Option<DirectoryEntry>> {
+        let key = (inode_num, name.to_vec());
+        if let Some(entry) = lock(&self.dentries).get(&key) {
+            return Ok(entry.clone());
+        }
+        let entry = self.search_dir(inode_num, name)?;
+        lock(&self.dentries).insert(key, entry.clone());
+        Ok(entry)
+    }
+
+    /// Search a directory's blocks for a single name
+    ///
+    /// Indexed directories are searched through their htree, reading only
+    /// the index blocks and the leaf holding the name's hash. Directories
+    /// without an index, or with a hash function that is not supported,
+    /// are scanned linearly. Casefolded directories match names
+    /// case-insensitively.
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number of the directory
+    /// * `name` - Name to find
+    ///
+    /// # Returns
+    /// The matching entry, or `None` if the directory has no such name
+    fn search_dir(&self, inode_num: u32, name: &[u8]) -> io::Result<Option<DirectoryEntry>> {
+        let inode = self.read_inode(inode_num)?;
+        let casefolded = self.superblock.has_casefold() && inode.is_casefolded();
+        if casefolded && self.superblock.encoding != EXT4_ENC_UTF8_12_1 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported filename encoding {}", self.superblock.encoding),
+            ));
+        }
+        let lookup = LookupName::new(name, casefolded)?;
+
+        if inode.is_indexed() {
+            let extents = self.read_extents(inode_num, &inode)?;
+            let root_block = self.read_dir_block(inode_num, &inode, &extents, 0)?;
+
+            // `.` and `..` live in front of the index root
+            if name == b"." || name == b".." {
+                let entries = parse_dir_block(&root_block).ok_or_else(|| {
+                    corrupt_dir_block(inode_num, extents[0].physical_block_start())
+                })?;
+                return Ok(entries.into_iter().find(|e| e.name == name));
+            }
+
+            let unsigned = self.superblock.has_unsigned_hash();
+            if let Some(root) = DxRoot::parse(&root_block, self.superblock.has_largedir())
+                && let Some(version) = DxHashVersion::from_raw(root.hash_version, unsigned)
+            {
+                return self.dx_find_entry(inode_num, &inode, &extents, root, version, &lookup);
+            }
+        }
+
+        Ok(self
+            .read_dir(inode_num)?
+            .into_iter()
+            .find(|e| lookup.matches(&e.name)))
+    }
+
+    /// Look up a name through a directory's htree index
+    ///
+    /// Descends from the root to the leaf block covering the name's hash,
+    /// then moves on to following leaves while their index entries mark a
+    /// hash collision continuing from the previous one.
+    ///
+    /// # Arguments
+    /// * `inode_num` - Inode number of the directory
+    /// * `inode` - The directory's inode
+    /// * `extents` - The directory's extents, mapping logical blocks
+    /// * `root` - Parsed index root from the first block
+    /// * `version` - Hash function of the index
+    /// * `lookup` - Name to find
+    fn dx_find_entry(
+        &self,
+        inode_num: u32,
+        inode: &Inode,
+        extents: &[Extent],
+        root: DxRoot,
+        version: DxHashVersion,
+        lookup: &LookupName,
+    ) -> io::Result<Option<DirectoryEntry>> {
+        let (hash, _) = dx_hash(lookup.hash_input(), version, &self.superblock.hash_seed);
+
+        // The chosen entry at each level, from the root down
+        let mut path: Vec<(Vec<DxEntry>, usize)> = Vec::new();
+        let mut entries = root.entries;
+        for _ in 0..root.indirect_levels {
+            let at = htree::search(&entries, hash);
+            let block = self.read_dir_block(inode_num, inode, extents, entries[at].block)?;
+            path.push((entries, at));
+            entries = parse_dx_node(&block).ok_or_else(|| invalid_dx_node(inode_num))?;
+        }
+        let at = htree::search(&entries, hash);
+        path.push((entries, at));
+
+        loop {
+            let (entries, at) = path.last().unwrap();
+            let logical_block = entries[*at].block;
+            let leaf = self.read_dir_block(inode_num, inode, extents, logical_block)?;
+            let leaf_entries = parse_dir_block(&leaf).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Corrupt leaf {} in directory {}", logical_block, inode_num),
+                )
+            })?;
+            if let Some(entry) = leaf_entries.into_iter().find(|e| lookup.matches(&e.name)) {
+                return Ok(Some(entry));
+            }
+            if !self.dx_next_leaf(inode_num, inode, extents, &mut path, hash)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Advance an htree path to the next leaf if it continues `hash`
+    ///
+    /// # Returns
+    /// `false` once the index is exhausted or the next leaf starts a new hash
+    fn dx_next_leaf(
+        &self,
+        inode_num: u32,
+        inode: &Inode,
+        extents: &[Extent],
+        path: &mut [(Vec<DxEntry>, usize)],
+        hash: u32,
+    ) -> io::Result<bool> {
+        // Step the deepest level that still has entries to its right
+        let mut level = path.len() - 1;
+        loop {
+            let (entries, at) = &mut path[level];
+            *at += 1;
+            if *at < entries.len() {
+                break;
+            }
+            if level == 0 {
+                return Ok(false);
+            }
+            level -= 1;
+        }
+
+        let (entries, at) = &path[level];
+        if entries[*at].hash & !1 != hash {
+            return Ok(false);
+        }
+
+        // Reload the levels below from their new first entries
+        for level in level + 1..path.len() {
+            let (entries, at) = &path[level - 1];
+            let block = self.read_dir_block(inode_num, inode, extents, entries[*at].block)?;
+            let entries = parse_dx_node(&block).ok_or_else(|| invalid_dx_node(inode_num))?;
+            path[level] = (entries, 0);
+        }
+        Ok(true)
+    }
+
+    /// Read a directory block by its logical block number, verifying its checksum
+    fn read_dir_block(
+        &self,
+        inode_num: u32,
+        inode: &Inode,
+        extents: &[Extent],
+        logical_block: u32,
+    ) -> io::Result<BlockBuf> {
+        let physical = extents
+            .iter()
+            .filter(|e| !e.is_unwritten())
+            .find_map(|e| e.map(logical_block))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Block {} of directory {} is not mapped",
+                        logical_block, inode_num
+                    ),
+                )
+            })?;
+        self.check_dir_block(inode_num, logical_block, physical)?;
+
+        let block_size = self.superblock.block_size();
+        let buf = read_block(&self.device, physical * block_size as u64, block_size)?;
+        self.verify_dir_block_checksum(inode_num, inode, logical_block, physical, &buf)?;
+        Ok(buf)
+    }
+
+    /// Resolve a path to an inode number, following all symlinks
+    ///
+    /// Relative paths start at the current directory.
+    fn resolve_path(&self, path: impl AsRef<[u8]>) -> io::Result<u32> {
+        self.resolve_path_with(path.as_ref(), true)
+    }
+
+    /// Resolve a path to an inode number
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path, or relative to the current directory
+    /// * `follow_last` - Whether to follow a symlink in the final component
+    fn resolve_path_with(&self, path: &[u8], follow_last: bool) -> io::Result<u32> {
+        let stack = self.walk_path(path, follow_last)?;
+        Ok(stack.last().map_or(EXT4_ROOT_INO, |&(_, inode)| inode))
+    }
+
+    /// Walk a path component by component from the root or current directory
+    ///
+    /// `.` and `..` are looked up through the real directory entries; a
+    /// `..` that does not lead back to the parent the walk came from is
+    /// reported as corruption.
+    /// Symlinks in intermediate components are always followed; relative
+    /// targets are resolved against the directory containing the link.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path, or relative to the current directory
+    /// * `follow_last` - Whether to follow a symlink in the final component
+    ///
+    /// # Returns
+    /// The normalised location as (name, inode) pairs below the root
+    ///
+    /// # Errors
+    /// Returns error if a component is missing, and `InvalidData` if more
+    /// than `MAX_SYMLINK_FOLLOWS` symlinks are encountered
+    fn walk_path(&self, path: &[u8], follow_last: bool) -> io::Result<Vec<(Vec<u8>, u32)>> {
+        let mut stack = if path.starts_with(b"/") {
+            Vec::new()
+        } else {
+            self.cwd.clone()
+        };
+        let mut components: VecDeque<Vec<u8>> = path
+            .split(|&b| b == b'/')
+            .filter(|s| !s.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        let mut follows = 0;
+
+        while let Some(component) = components.pop_front() {
+            let current_inode_num = stack.last().map_or(EXT4_ROOT_INO, |&(_, inode)| inode);
+            let next_entry = self
+                .find_entry(current_inode_num, &component)?
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Component '{}' not found", escape_name(&component)),
+                    )
+                })?;
+
+            match component.as_slice() {
+                b"." => continue,
+                b".." => {
+                    // The root's ".." refers to itself
+                    let dir = stack.pop().map_or(EXT4_ROOT_INO, |(_, inode)| inode);
+                    let parent = stack.last().map_or(EXT4_ROOT_INO, |&(_, inode)| inode);
+                    if next_entry.inode != parent {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "'..' of directory {} points to inode {} instead of its parent {}",
+                                dir, next_entry.inode, parent
+                            ),
+                        ));
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let follow = !components.is_empty() || follow_last;
+            if !follow || !self.is_symlink_entry(&next_entry)? {
+                // Record the on-disk spelling, which may differ in case
+                stack.push((next_entry.name, next_entry.inode));
+                continue;
+            }
+
+            follows += 1;
+            if follows > MAX_SYMLINK_FOLLOWS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Too many levels of symbolic links in '{}'",
+                        escape_name(path)
+                    ),
+                ));
+            }
+
+            // Splice the target in front of the remaining components
+            let inode = self.read_inode(next_entry.inode)?;
+            let target = self.read_link_target(next_entry.inode, &inode)?;
+            if target.starts_with(b"/") {
+                stack.clear();
+            }
+            for part in target.split(|&b| b == b'/').filter(|s| !s.is_empty()).rev() {
+                components.push_front(part.to_vec());
+            }
+        }
+
+        Ok(stack)
+    }
+
+    /// Check whether a directory entry refers to a symlink
+    ///
+    /// Falls back to the inode mode when the entry has no file type.
+    fn is_symlink_entry(&self, entry: &DirectoryEntry) -> io::Result<bool> {
+        if entry.file_type != 0 {
+            return Ok(entry.is_symlink());
+        }
+        Ok(self.read_inode(entry.inode)?.is_symlink())
+    }
+
+    /// Read a symlink's target from i_block (fast) or its data (slow)
+    fn read_link_target(&self, inode_num: u32, inode: &Inode) -> io::Result<Vec<u8>> {
+        if !inode.is_symlink() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Inode {} is not a symbolic link", inode_num),
+            ));
+        }
+
+        if inode.is_fast_symlink() {
+            return Ok(inode.i_block[..inode.inode_size as usize].to_vec());
+        }
+        self.read_file_from_inode(inode_num, inode)
+    }
+
+    /// Read and decode one of the `system.posix_acl_*` attributes
+    fn read_acl_xattr(&self, path: &[u8], name: &str) -> io::Result<Option<Acl>> {
+        let Some(value) = self.get_xattr(path, name)? else {
+            return Ok(None);
+        };
+        Acl::parse(&value).map(Some).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid {} attribute on '{}'", name, escape_name(path)),
+            )
+        })
+    }
+
+    /// Fetch an xattr value, reading it from its own inode with `ea_inode`
+    fn read_xattr_value(&self, entry: &XattrEntry) -> io::Result<Vec<u8>> {
+        if entry.value_inum == 0 {
+            return Ok(entry.value.clone());
+        }
+
+        if entry.value_size > XATTR_SIZE_MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Xattr value in inode {} has size {}, more than the {} bytes allowed",
+                    entry.value_inum, entry.value_size, XATTR_SIZE_MAX
+                ),
+            ));
+        }
+        let inode = self.read_inode(entry.value_inum)?;
+        let len = inode.inode_size.min(entry.value_size as u64);
+        self.read_inode_data(entry.value_inum, &inode, len)
+    }
+
+    /// Read the whole content of a file into memory
+    ///
+    /// # Errors
+    /// Returns `Unsupported` for files claiming to be larger than the
+    /// filesystem; only sparse files can be, and those must be read in
+    /// parts through [`open_file`](Self::open_file)
+    fn read_file_from_inode(&self, inode_num: u32, inode: &Inode) -> io::Result<Vec<u8>> {
+        self.check_whole_read(inode_num, inode)?;
+        self.read_inode_data(inode_num, inode, inode.inode_size)
+    }
+
+    /// Refuse to read a file into memory in one piece if it claims to be
+    /// larger than the filesystem, as only a corrupt or sparse one can
+    fn check_whole_read(&self, inode_num: u32, inode: &Inode) -> io::Result<()> {
+        let fs_size = self.superblock.blocks_count * self.superblock.block_size() as u64;
+        if inode.inode_size > fs_size {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Inode {} has size {}, larger than the filesystem; read it with open_file",
+                    inode_num, inode.inode_size
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read the first `size` bytes of an inode's content
+    ///
+    /// `size` must already be bounded, as it is allocated up front.
+    fn read_inode_data(&self, inode_num: u32, inode: &Inode, size: u64) -> io::Result<Vec<u8>> {
+        // Inline data already holds the whole file
+        if let Some(data) = &inode.inline_data {
+            let mut content = data.clone();
+            content.truncate(size as usize);
+            return Ok(content);
+        }
+
+        let block_size = self.superblock.block_size();
+        let mut content = vec![0u8; size as usize];
+
+        // Place each block at its logical offset; holes and unwritten
+        // extents stay zero. File content bypasses the block cache.
+        for extent in self.read_extents(inode_num, inode)? {
+            if extent.is_unwritten() {
+                continue;
+            }
+            for i in 0..extent.len() as u64 {
+                let start = (extent.logical_block as u64 + i) * block_size as u64;
+                if start >= size {
+                    break;
+                }
+                let offset = (extent.physical_block_start() + i) * block_size as u64;
+                let end = (start + block_size as u64).min(size);
+                self.device
+                    .read_uncached(offset, &mut content[start as usize..end as usize])?;
+            }
+        }
+
+        Ok(content)
+    }
+}
+
+/// Error for a path whose final symlink may not be followed
+fn refuse_symlink(path: &[u8], inode: &Inode) -> io::Result<()> {
+    if inode.is_symlink() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is a symbolic link", escape_name(path)),
+        ));
+    }
+    Ok(())
+}
+
+/// The host file name for a name stored in the filesystem
+///
+/// Unix names are arbitrary bytes, so they are used unchanged; elsewhere
+/// names that are not valid UTF-8 are escaped as by [`escape_name`].
+///
+/// # Errors
+/// Returns `InvalidData` if the name is empty, `.` or `..`, or holds a
+/// separator or NUL, any of which would let it name another path
+#[cfg(feature = "std")]
+fn host_name(name: &[u8]) -> io::Result<std::path::PathBuf> {
+    let separator = |&byte: &u8| byte == b'/' || byte == 0 || cfg!(windows) && byte == b'\\';
+    if name.is_empty() || name == b"." || name == b".." || name.iter().any(separator) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{}' is not a valid file name", escape_name(name)),
+        ));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(std::ffi::OsStr::from_bytes(name).into())
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(escape_name(name).into())
+    }
+}
+
+/// Create a directory for extraction, or reuse one already there
+///
+/// An existing entry is only reused if it is a real directory; a symlink
+/// to one would let later writes escape the destination.
+#[cfg(feature = "std")]
+fn create_host_dir(path: &Path) -> io::Result<()> {
+    match std::fs::create_dir(path) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            if std::fs::symlink_metadata(path)?.is_dir() {
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("'{}' exists and is not a directory", path.display()),
+                ))
+            }
+        }
+        result => result,
+    }
+}
+
+/// Recreate a symlink on the host; other platforms skip symlinks
+///
+/// The link text is copied as is, so it may point anywhere; only its own
+/// location is checked.
+#[cfg(feature = "std")]
+fn create_symlink(link: &[u8], target: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(link), target)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (link, target);
+        Ok(())
+    }
+}
+
+/// Split a `#pN` partition suffix off a disk path
+///
+/// Paths that exist as given are never split, so image names containing
+/// `#` still open.
+#[cfg(feature = "std")]
+fn split_partition_suffix(path: &str) -> (&str, Option<u32>) {
+    let split = path
+        .rsplit_once("#p")
+        .and_then(|(disk, suffix)| Some((disk, suffix.parse().ok()?)))
+        .filter(|_| !std::path::Path::new(path).exists());
+    match split {
+        Some((disk, index)) => (disk, Some(index)),
+        None => (path, None),
+    }
+}
+
+/// Pick the byte range of a disk that holds the filesystem
+///
+/// # Arguments
+/// * `device` - Bare filesystem or whole disk
+/// * `path` - Path of the disk, for error messages
+/// * `index` - Partition to use; without one, the whole device if it
+///   starts with a filesystem, else its first ext2/3/4 partition
+#[cfg(feature = "std")]
+fn select_region<D: BlockDevice>(
+    device: &D,
+    path: &str,
+    index: Option<u32>,
+) -> io::Result<std::ops::Range<u64>> {
+    let partition = match index {
+        Some(index) => {
+            let partition = read_partitions(device)?
+                .into_iter()
+                .find(|p| p.index == index);
+            Some(partition.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No partition {} in '{}'", index, path),
+                )
+            })?)
+        }
+        None => {
+            // A bare filesystem may have anything in its boot sector, so
+            // its partition table is not even parsed
+            let mut buf = vec![0u8; SUPERBLOCK_SIZE as usize];
+            let bare =
+                device.read_at(SUPERBLOCK_OFFSET, &mut buf).is_ok() && Superblock::has_magic(&buf);
+            if bare {
+                None
+            } else {
+                read_partitions(device)?.into_iter().find(|p| p.is_ext)
+            }
+        }
+    };
+    match partition {
+        Some(partition) => {
+            let end = partition.start.checked_add(partition.size).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Partition {} in '{}' ends past 2^64", partition.index, path),
+                )
+            })?;
+            Ok(partition.start..end)
+        }
+        None => Ok(0..device.size()?),
+    }
+}
+
+/// Error for a directory block holding a malformed record
+fn corrupt_dir_block(inode_num: u32, block: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Corrupt directory block {} in inode {}", block, inode_num),
+    )
+}
+
+/// Error for extent index entries whose subtrees are not in logical order
+fn unordered_extent_index(inode_num: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Extent index of inode {} is out of order", inode_num),
+    )
+}
+
+/// Error for an htree interior node that cannot be parsed
+fn invalid_dx_node(inode_num: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid htree node in directory {}", inode_num),
+    )
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_resolve_path() {
+    let fs = FileSystem::open("ext4.img").expect("Failed to open image");
+    let inode_num = fs
+        .resolve_path("/home/zyu379/test_file.txt")
+        .expect("Path not resolved");
+    let _ = fs.read_inode(inode_num).expect("Failed to read inode");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_read_test_file() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let content = fs.read_file("/home/zyu379/test_file.txt").unwrap();
+    assert_eq!(String::from_utf8_lossy(&content), "hello from ext4 test\n");
+}
+
+#[test]
+fn test_corrupt_superblock() {
+    static IMAGE: &[u8] = include_bytes!("../ext4_inline.img");
+    let corrupt = |offset: usize, value: &[u8]| {
+        let mut image = IMAGE.to_vec();
+        let at = SUPERBLOCK_OFFSET as usize + offset;
+        image[at..at + value.len()].copy_from_slice(value);
+        FileSystem::from_device(image).err().map(|e| e.kind())
+    };
+    let invalid = Some(io::ErrorKind::InvalidData);
+    assert_eq!(corrupt(0x18, &40u32.to_le_bytes()), invalid);
+    assert_eq!(corrupt(0x20, &0u32.to_le_bytes()), invalid);
+    assert_eq!(corrupt(0x28, &0u32.to_le_bytes()), invalid);
+    for inode_size in [0u16, 64, 200, 8192] {
+        assert_eq!(corrupt(0x58, &inode_size.to_le_bytes()), invalid);
+    }
+
+    let fs = FileSystem::from_device(IMAGE).unwrap();
+    for inode_num in [0, fs.superblock.inodes_count + 1, u32::MAX] {
+        assert_eq!(
+            fs.inode_offset(inode_num).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+    assert!(fs.inode_offset(fs.superblock.inodes_count).is_ok());
+}
+
+#[test]
+fn test_from_slice() {
+    // Needs neither files nor threads, so it runs without `std` too
+    static IMAGE: &[u8] = include_bytes!("../ext4_inline.img");
+    let fs = FileSystem::from_device(IMAGE).unwrap();
+    assert_eq!(
+        fs.read_file("/small.txt").unwrap(),
+        b"hello from inline data\n"
+    );
+    assert!(
+        fs.list_dir("/")
+            .unwrap()
+            .iter()
+            .any(|entry| entry.name == b"medium.txt")
+    );
+
+    // Checksum failures can be told apart from other corruption
+    let mut fs = FileSystem::from_device(IMAGE.to_vec()).unwrap();
+    let inode_num = fs.resolve_path("/small.txt").unwrap();
+    let offset = fs.inode_offset(inode_num).unwrap() + 0x10;
+    fs.device.write_at(offset, &[0xFF]).unwrap();
+    fs.invalidate_range(offset, 1);
+    let err = fs.read_file("/small.txt").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let checksum = err.get_ref().unwrap().downcast_ref::<ChecksumError>();
+    assert_eq!(
+        checksum.unwrap().kind,
+        ChecksumKind::Inode { inode: inode_num }
+    );
+}
+
+#[cfg(not(feature = "std"))]
+#[test]
+fn test_casefold_without_std() {
+    static IMAGE: &[u8] = include_bytes!("../ext4_casefold.img");
+    let fs = FileSystem::from_device(IMAGE).unwrap();
+    let err = fs.read_file("/cf/readme.md").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    assert!(fs.list_dir("/cf").unwrap().len() > 100);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_read_inline_file() {
+    let fs = FileSystem::open("ext4_inline.img").unwrap();
+    let content = fs.read_file("/small.txt").unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&content),
+        "hello from inline data\n"
+    );
+
+    // Larger than i_block, so the tail lives in the system.data xattr
+    let content = fs.read_file("/medium.txt").unwrap();
+    assert_eq!(content, vec![b'x'; 100]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_list_inline_dir() {
+    let fs = FileSystem::open("ext4_inline.img").unwrap();
+    let names: Vec<String> = fs
+        .list_dir("/inline_dir")
+        .unwrap()
+        .iter()
+        .map(|e| e.name_lossy().into_owned())
+        .collect();
+    assert_eq!(names, [".", "..", "a", "b", "c"]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_list_deleted() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let names = |entries: Vec<DirectoryEntry>| -> Vec<String> {
+        entries
+            .iter()
+            .map(|e| e.name_lossy().into_owned())
+            .collect()
+    };
+
+    let live = names(fs.list_dir("/deleted").unwrap());
+    assert!(live.contains(&"keep1".to_string()) && live.contains(&"keep2".to_string()));
+    assert!(!live.contains(&"gone".to_string()));
+    assert_eq!(names(fs.list_deleted("/deleted").unwrap()), ["gone"]);
+
+    // Slack after the last entry of the htree root is not mistaken for names
+    assert!(fs.list_deleted("/bigdir").unwrap().is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_non_utf8_names() {
+    let mut fs = FileSystem::open("ext4.img").unwrap();
+    let latin1 = b"/raw/caf\xe9";
+    let replacement = "/raw/caf\u{FFFD}";
+
+    // Both names decode lossily to the same text but stay distinct
+    let mut names: Vec<Vec<u8>> = fs
+        .list_dir("/raw")
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    assert_eq!(names[2..], [b"caf\xe9".to_vec(), "caf\u{FFFD}".into()]);
+
+    assert_eq!(fs.read_file(latin1).unwrap(), b"latin1\n");
+    assert_eq!(fs.read_file(replacement).unwrap(), b"replacement\n");
+
+    // Escaped names round-trip to the bytes on disk
+    assert_eq!(escape_name(latin1), "/raw/caf\\xe9");
+    assert_eq!(unescape_name(&escape_name(latin1)), latin1);
+    fs.set_current_dir(latin1).unwrap_err();
+    fs.set_current_dir("/raw").unwrap();
+    assert_eq!(
+        fs.read_file(unescape_name("caf\\xe9")).unwrap(),
+        b"latin1\n"
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_stat() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let stat = fs.stat("/home/zyu379/test_file.txt").unwrap();
+    assert_eq!(stat.inode.inode_size, 21);
+    assert_eq!(stat.inode.links_count, 1);
+    assert_eq!(stat.inode.inode_mode & 0o7777, 0o644);
+    assert!(stat.inode.crtime.is_some());
+    assert!(stat.to_string().contains("Type: regular"));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_metadata() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let meta = fs.metadata("/home/zyu379/test_file.txt").unwrap();
+    assert!(meta.is_file());
+    assert_eq!(meta.len(), 21);
+    assert_eq!(meta.permissions().mode(), 0o644);
+    assert!(meta.modified() > std::time::UNIX_EPOCH);
+
+    let meta = fs.metadata("/home").unwrap();
+    assert_eq!(meta.file_type(), FileType::Directory);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_read_extent_tree_file() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let content = fs.read_file("/sparse.bin").unwrap();
+    assert_eq!(content.len(), 19 * 4096);
+
+    // Data chunks alternate with holes, which read back as zeros
+    for (i, chunk) in content.chunks(4096).enumerate() {
+        let expected = if i % 2 == 0 { b'a' + i as u8 / 2 } else { 0 };
+        assert!(chunk.iter().all(|&b| b == expected));
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_open_file() {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let expected = fs.read_file("/sparse.bin").unwrap();
+    let mut file = fs.open_file("/sparse.bin").unwrap();
+    assert_eq!(file.len(), expected.len() as u64);
+
+    // Reads spanning a data block, a hole and the next block
+    let mut buf = vec![0u8; 9000];
+    for offset in [0, 100, 4000, 4096 * 7 + 5, 4096 * 18] {
+        let read = file.read_at(&mut buf, offset).unwrap();
+        let end = (offset as usize + read).min(expected.len());
+        assert_eq!(buf[..read], expected[offset as usize..end]);
+    }
+    assert_eq!(file.read_at(&mut buf, file.len()).unwrap(), 0);
+
+    // Read and Seek agree with read_at
+    file.seek(SeekFrom::End(-10)).unwrap();
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, expected[expected.len() - 10..]);
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).unwrap();
+    assert_eq!(content, expected);
+    assert!(
+        file.seek(SeekFrom::Current(-(content.len() as i64) - 1))
+            .is_err()
+    );
+
+    let err = fs.open_file("/home").err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::IsADirectory);
+
+    let fs = FileSystem::open("ext4_inline.img").unwrap();
+    let mut content = String::new();
+    let mut file = fs.open_file("/small.txt").unwrap();
+    file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "hello from inline data\n");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_from_device() {
+    let image = std::fs::read("ext4.img").unwrap();
+    let fs = FileSystem::from_device(image.as_slice()).unwrap();
+    let expected = fs.read_file("/home/zyu379/test_file.txt").unwrap();
+
+    // The same filesystem one MiB into a larger disk
+    let mut disk = vec![0u8; 1 << 20];
+    disk.extend_from_slice(&image);
+    disk.extend_from_slice(&[0xFF; 4096]);
+    let window = DeviceWindow::new(disk, 1 << 20, image.len() as u64);
+    let fs = FileSystem::from_device(window).unwrap();
+    assert_eq!(
+        fs.read_file("/home/zyu379/test_file.txt").unwrap(),
+        expected
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_open_partition() {
+    // An MBR disk with ext4.img as its second primary partition
+    let image = std::fs::read("ext4.img").unwrap();
+    let mut disk = vec![0u8; 2048 * 512];
+    let mut entry = [0u8; 16];
+    entry[4] = 0x83;
+    entry[8..12].copy_from_slice(&2048u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&((image.len() / 512) as u32).to_le_bytes());
+    disk[0x1CE..0x1DE].copy_from_slice(&entry);
+    disk[0x1FE..0x200].copy_from_slice(&[0x55, 0xAA]);
+    disk.extend_from_slice(&image);
+
+    let temp = TempPath::new("disk.img");
+    std::fs::write(&temp, &disk).unwrap();
+    let path = temp.to_str().unwrap();
+
+    let partitions = read_partitions(&disk.as_slice()).unwrap();
+    assert_eq!(partitions.len(), 1);
+    assert!(partitions[0].index == 2 && partitions[0].is_ext);
+
+    let expected = b"hello from ext4 test\n";
+    for fs in [
+        FileSystem::open(&format!("{}#p2", path)).unwrap(),
+        FileSystem::open_partition(path, 2).unwrap(),
+        FileSystem::open(path).unwrap(),
+    ] {
+        assert_eq!(
+            fs.read_file("/home/zyu379/test_file.txt").unwrap(),
+            expected
+        );
+    }
+
+    // SAFETY: the image is private to this test and not modified
+    let fs = unsafe { FileSystem::open_mmap(&format!("{}#p2", path)).unwrap() };
+    assert_eq!(
+        fs.read_file("/home/zyu379/test_file.txt").unwrap(),
+        expected
+    );
+
+    let err = FileSystem::open(&format!("{}#p1", path)).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    // A bare filesystem opens even if its boot sector looks like a broken
+    // partition table
+    let mut bare = image.clone();
+    bare[..512].copy_from_slice(&disk[..512]);
+    bare[0x1C2] = 0xEE;
+    std::fs::write(&temp, &bare).unwrap();
+    assert!(read_partitions(&bare.as_slice()).is_err());
+    let fs = FileSystem::open(path).unwrap();
+    assert_eq!(
+        fs.read_file("/home/zyu379/test_file.txt").unwrap(),
+        expected
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_block_cache_lookups() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    fs.list_dir("/bigdir").unwrap();
+    let cold = fs.cache_stats();
+    assert!(cold.misses > 0);
+
+    // Reading the same directory again is served from memory
+    fs.list_dir("/bigdir").unwrap();
+    let warm = fs.cache_stats();
+    assert_eq!(warm.misses, cold.misses);
+    assert!(warm.hits > cold.hits);
+
+    // Without a budget every block goes to the device
+    fs.set_cache_capacity(0);
+    assert_eq!(fs.cache_stats().bytes, 0);
+    fs.list_dir("/bigdir").unwrap();
+    assert_eq!(fs.cache_stats().misses, warm.misses);
+    assert_eq!(fs.cache_stats().hits, warm.hits);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_lookup_caches() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let path = "/bigdir/file_250.txt";
+    let inode = fs.metadata(path).unwrap().ino();
+
+    // A repeated lookup needs neither directory blocks nor inode tables
+    let before = fs.cache_stats();
+    assert_eq!(fs.metadata(path).unwrap().ino(), inode);
+    assert_eq!(fs.cache_stats(), before);
+
+    // Invalidated lookups are resolved again from (cached) blocks
+    let bigdir = fs.resolve_path("/bigdir").unwrap();
+    fs.invalidate_inode(bigdir);
+    assert_eq!(fs.metadata(path).unwrap().ino(), inode);
+    let after = fs.cache_stats();
+    assert!(after.hits > before.hits);
+    assert_eq!(after.misses, before.misses);
+
+    fs.clear_caches();
+    assert_eq!(fs.metadata(path).unwrap().ino(), inode);
+    assert!(fs.cache_stats().misses > after.misses);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_open_mmap() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    // SAFETY: the test images are never written
+    let mapped = unsafe { FileSystem::open_mmap("ext4.img").unwrap() };
+    for path in ["/bigdir", "/home/zyu379", "/deleted"] {
+        assert_eq!(mapped.list_dir(path).unwrap(), fs.list_dir(path).unwrap());
+    }
+    assert_eq!(
+        mapped.read_file("/sparse.bin").unwrap(),
+        fs.read_file("/sparse.bin").unwrap()
+    );
+
+    // Blocks come straight from the mapping, never through the cache
+    let stats = mapped.cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.bytes), (0, 0, 0));
+    let buf = read_block(&mapped.device, 1024, 1024).unwrap();
+    assert!(matches!(buf, BlockBuf::Mapped(..)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_htree_lookup() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let bigdir = fs.resolve_path("/bigdir").unwrap();
+    assert!(fs.read_inode(bigdir).unwrap().is_indexed());
+
+    for n in [1, 137, 250, 500] {
+        let content = fs.read_file(format!("/bigdir/file_{:03}.txt", n)).unwrap();
+        assert_eq!(content, format!("file {:03}\n", n).as_bytes());
+    }
+    assert_eq!(fs.resolve_path("/bigdir/.").unwrap(), bigdir);
+    assert_eq!(fs.resolve_path("/bigdir/..").unwrap(), EXT4_ROOT_INO);
+
+    let err = fs.read_file("/bigdir/file_501.txt").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_dir_block_bounds() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let inode_num = fs.resolve_path("/late").unwrap();
+    let inode = fs.read_inode(inode_num).unwrap();
+    assert!(fs.read_extents(inode_num, &inode).unwrap()[0].physical_block_start() > 8192);
+    assert_eq!(
+        fs.read_file("/late/hello.txt").unwrap(),
+        b"hello from a high block\n"
+    );
+
+    // Point the directory's extent past the end of the filesystem, then
+    // into an inode table, fixing up the inode checksum so only the bounds
+    // check can fail
+    let inode_table = fs.read_group_desc(1).unwrap().inode_table_block + 1;
+    for (target, message) in [
+        (u32::MAX, "outside the filesystem"),
+        (inode_table as u32, "holds group metadata"),
+    ] {
+        let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+        // i_block + extent header + ee_start_lo of the first extent
+        patch_inode(&mut fs, inode_num, |raw| {
+            raw[0x28 + 12 + 8..0x28 + 12 + 12].copy_from_slice(&target.to_le_bytes());
+        });
+
+        let err = fs.list_dir("/late").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(message));
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_group_descriptors() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    assert_eq!(fs.superblock.desc_size, 64);
+
+    // Every group has its own inode table inside the filesystem
+    let groups = fs.superblock.inodes_count / fs.superblock.inodes_per_group;
+    assert_eq!(fs.superblock.group_count(), groups);
+    let tables: Vec<u64> = (0..groups)
+        .map(|group| fs.read_group_desc(group).unwrap().inode_table_block)
+        .collect();
+    assert!(
+        tables
+            .iter()
+            .all(|&block| fs.superblock.contains_block(block))
+    );
+    assert!(tables.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_casefold_lookup() {
+    let mut fs = FileSystem::open("ext4_casefold.img").unwrap();
+    let cf = fs.resolve_path("/cf").unwrap();
+    let inode = fs.read_inode(cf).unwrap();
+    assert!(inode.is_casefolded() && inode.is_indexed());
+
+    // Lookups go through the htree, so these only succeed if the hash is
+    // computed over the folded name
+    assert_eq!(fs.read_file("/cf/entry_150.TXT").unwrap(), b"entry 150\n");
+    assert_eq!(fs.read_file("/cf/readme.MD").unwrap(), b"readme\n");
+    assert_eq!(fs.read_file("/cf/CAFE\u{301}.txt").unwrap(), b"cafe\n");
+    assert_eq!(fs.read_file("/cf/STRASSE").unwrap(), b"strasse\n");
+
+    // The current directory keeps the on-disk spelling
+    fs.set_current_dir("/cf/../cf").unwrap();
+    assert_eq!(fs.current_dir(), b"/cf");
+
+    // Directories without the casefold flag stay case-sensitive
+    let err = fs.resolve_path("/CF").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+/// A uniquely named path in the temp directory, removed when dropped
+///
+/// Tests run in parallel and may fail halfway, so each gets its own name
+/// and cleans up whatever it left there.
+#[cfg(all(test, feature = "std"))]
+pub(crate) struct TempPath(std::path::PathBuf);
+
+#[cfg(all(test, feature = "std"))]
+impl TempPath {
+    pub(crate) fn new(name: &str) -> Self {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let unique = NEXT.fetch_add(1, Ordering::Relaxed);
+        let file = format!("ext4fs_{}_{}_{}", std::process::id(), unique, name);
+        Self(std::env::temp_dir().join(file))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+impl core::ops::Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0).or_else(|_| std::fs::remove_file(&self.0));
+    }
+}
+
+/// Modify the first block of a linear directory on an in-memory image and
+/// fix up its checksum
+#[cfg(all(test, feature = "std"))]
+fn patch_dir_block(fs: &mut FileSystem<Vec<u8>>, inode_num: u32, patch: impl FnOnce(&mut [u8])) {
+    let inode = fs.read_inode(inode_num).unwrap();
+    let block = fs.read_extents(inode_num, &inode).unwrap()[0].physical_block_start();
+    let block_size = fs.superblock.block_size();
+    let mut buf = read_block(&fs.device, block * block_size as u64, block_size)
+        .unwrap()
+        .to_vec();
+    patch(&mut buf);
+    let seed = inode_seed(fs.superblock.csum_seed(), inode_num, inode.generation);
+    let checksum = crc32c(seed, &buf[..buf.len() - EXT4_DIR_ENTRY_TAIL_SIZE]);
+    let len = buf.len();
+    buf[len - 4..].copy_from_slice(&checksum.to_le_bytes());
+    fs.device.write_at(block * block_size as u64, &buf).unwrap();
+    fs.clear_caches();
+}
+
+/// Modify an inode on an in-memory image and fix up its checksum
+#[cfg(all(test, feature = "std"))]
+fn patch_inode(fs: &mut FileSystem<Vec<u8>>, inode_num: u32, patch: impl FnOnce(&mut [u8])) {
+    let inode = fs.read_inode(inode_num).unwrap();
+    let offset = fs.inode_offset(inode_num).unwrap();
+    let mut raw = read_block(&fs.device, offset, fs.superblock.inode_size as u32)
+        .unwrap()
+        .to_vec();
+    patch(&mut raw);
+    let seed = inode_seed(fs.superblock.csum_seed(), inode_num, inode.generation);
+    let checksum = inode_checksum(&raw, seed, true);
+    raw[0x7C..0x7E].copy_from_slice(&checksum.to_le_bytes()[..2]);
+    raw[0x82..0x84].copy_from_slice(&checksum.to_le_bytes()[2..]);
+
+    fs.device.write_at(offset, &raw).unwrap();
+    fs.invalidate_range(offset, raw.len() as u64);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_oversized_file() {
+    let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+    let inode_num = fs.resolve_path("/home/zyu379/test_file.txt").unwrap();
+    // i_size_lo and i_size_high
+    patch_inode(&mut fs, inode_num, |raw| {
+        raw[0x04..0x08].copy_from_slice(&u32::MAX.to_le_bytes());
+        raw[0x6C..0x70].copy_from_slice(&u32::MAX.to_le_bytes());
+    });
+
+    // Refused instead of allocating the claimed size
+    let err = fs.read_file("/home/zyu379/test_file.txt").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    let mut file = fs.open_file("/home/zyu379/test_file.txt").unwrap();
+    let mut buf = [0u8; 21];
+    file.read_at(&mut buf, 0).unwrap();
+    assert_eq!(&buf, b"hello from ext4 test\n");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_extent_near_limits() {
+    let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+    let inode_num = fs.resolve_path("/home/zyu379/test_file.txt").unwrap();
+    let block_size = fs.superblock.block_size() as u64;
+    // The first extent of the root starts at i_block + 12: ee_block,
+    // ee_len, ee_start_hi and ee_start_lo
+    patch_inode(&mut fs, inode_num, |raw| {
+        raw[0x6C..0x70].copy_from_slice(&0x10_0000u32.to_le_bytes());
+        raw[0x34..0x38].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+        raw[0x38..0x3A].copy_from_slice(&5u16.to_le_bytes());
+    });
+
+    // An extent running past the largest logical block is read up to it
+    let mut file = fs.open_file("/home/zyu379/test_file.txt").unwrap();
+    let mut buf = [0u8; 21];
+    let offset = (u32::MAX - 1) as u64 * block_size;
+    file.read_at(&mut buf, offset).unwrap();
+    assert_eq!(&buf, b"hello from ext4 test\n");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_dir_extent_bounds() {
+    let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+    let inode_num = fs.resolve_path("/home").unwrap();
+    let expected = fs.list_dir("/home").unwrap();
+    let block_size = fs.superblock.block_size();
+
+    // Blocks past i_size are not read, however long the extent claims to be
+    patch_inode(&mut fs, inode_num, |raw| {
+        raw[0x04..0x08].copy_from_slice(&block_size.to_le_bytes());
+        raw[0x38..0x3A].copy_from_slice(&32768u16.to_le_bytes());
+    });
+    assert_eq!(fs.list_dir("/home").unwrap(), expected);
+
+    // An extent running past the largest logical block is skipped
+    patch_inode(&mut fs, inode_num, |raw| {
+        raw[0x34..0x38].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+    });
+    assert!(fs.list_dir("/home").unwrap().is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_extent_tree_shape() {
+    let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+    let inode_num = fs.resolve_path("/sparse.bin").unwrap();
+    let root = fs.read_inode(inode_num).unwrap().extent_root.unwrap();
+    assert_eq!(root.header.tree_depth, 1);
+    assert_eq!(root.header.entry_count, 1);
+
+    // A second index sharing the first one's start and child
+    patch_inode(&mut fs, inode_num, |raw| {
+        raw[0x2A..0x2C].copy_from_slice(&2u16.to_le_bytes());
+        raw.copy_within(0x34..0x40, 0x40);
+    });
+    let err = fs.read_file("/sparse.bin").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let mut file = fs.open_file("/sparse.bin").unwrap();
+    let err = file.read_at(&mut [0u8; 16], 0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // Deeper trees than the kernel allows
+    patch_inode(&mut fs, inode_num, |raw| {
+        raw[0x2A..0x2C].copy_from_slice(&1u16.to_le_bytes());
+        raw[0x2E..0x30].copy_from_slice(&(EXT4_MAX_EXTENT_DEPTH + 1).to_le_bytes());
+    });
+    let err = fs.read_inode(inode_num).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_inode_checksum_mismatch() {
+    let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+    let inode_num = fs.resolve_path("/home/zyu379/test_file.txt").unwrap();
+    let offset = fs.inode_offset(inode_num).unwrap();
+
+    fs.read_inode(inode_num).unwrap();
+
+    // Flip a bit in i_mtime without updating the checksum; the parsed
+    // inode stays cached until the range is invalidated
+    fs.device.write_at(offset + 0x10, &[0xFF]).unwrap();
+    fs.read_inode(inode_num).unwrap();
+    fs.invalidate_range(offset + 0x10, 1);
+
+    let err = fs.read_inode(inode_num).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let checksum_err = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<ChecksumError>())
+        .expect("expected a ChecksumError");
+    assert_eq!(checksum_err.kind, ChecksumKind::Inode { inode: inode_num });
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_dir_block_checksum_mismatch() {
+    let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+    let block_size = fs.superblock.block_size() as u64;
+    let first_block = |fs: &mut FileSystem<Vec<u8>>, dir: &str| {
+        let inode_num = fs.resolve_path(dir).unwrap();
+        let inode = fs.read_inode(inode_num).unwrap();
+        let block = fs.read_extents(inode_num, &inode).unwrap()[0].physical_block_start();
+        (inode_num, block)
+    };
+    let (home, home_block) = first_block(&mut fs, "/home/zyu379");
+    let (bigdir, bigdir_block) = first_block(&mut fs, "/bigdir");
+
+    // Flip a byte in the `.` entry's name of a leaf block, and in the first
+    // hash of an htree root
+    fs.device
+        .write_at(home_block * block_size + 8, b",")
+        .unwrap();
+    fs.device
+        .write_at(bigdir_block * block_size + 0x28, &[0xFF])
+        .unwrap();
+    fs.clear_caches();
+
+    let checksum_kind = |err: io::Error| {
+        err.get_ref()
+            .and_then(|e| e.downcast_ref::<ChecksumError>())
+            .expect("expected a ChecksumError")
+            .kind
+    };
+    assert_eq!(
+        checksum_kind(fs.list_dir("/home/zyu379").unwrap_err()),
+        ChecksumKind::DirBlock {
+            inode: home,
+            block: home_block
+        }
+    );
+    assert_eq!(
+        checksum_kind(fs.read_file("/bigdir/file_001.txt").unwrap_err()),
+        ChecksumKind::DxNode {
+            inode: bigdir,
+            block: bigdir_block
+        }
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_xattrs() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let xattrs = fs.xattrs("/home/zyu379/test_file.txt").unwrap();
+    let names: Vec<&str> = xattrs.iter().map(|x| x.name.as_str()).collect();
+    assert!(names.contains(&"user.comment"));
+    assert!(names.contains(&"user.big"));
+
+    // Fits in the inode body
+    let value = fs
+        .get_xattr("/home/zyu379/test_file.txt", "user.comment")
+        .unwrap();
+    assert_eq!(value.as_deref(), Some(&b"hello xattr"[..]));
+
+    // Too large for the inode, so stored in the external xattr block
+    let value = fs
+        .get_xattr("/home/zyu379/test_file.txt", "user.big")
+        .unwrap();
+    assert_eq!(value, Some(vec![b'v'; 300]));
+
+    let value = fs
+        .get_xattr("/home/zyu379/test_file.txt", "user.missing")
+        .unwrap();
+    assert_eq!(value, None);
+
+    // A value offset past the end of the inode is an error, not an empty value
+    let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+    let inode_num = fs.resolve_path("/home/zyu379/test_file.txt").unwrap();
+    patch_inode(&mut fs, inode_num, |raw| {
+        let extra_isize = u16::from_le_bytes([raw[0x80], raw[0x81]]) as usize;
+        // Magic, then e_value_offs of the first entry
+        let entry = EXT4_GOOD_OLD_INODE_SIZE + extra_isize + 4;
+        raw[entry + 2..entry + 4].copy_from_slice(&u16::MAX.to_le_bytes());
+    });
+    let err = fs
+        .get_xattr("/home/zyu379/test_file.txt", "user.comment")
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_acl() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let acl = fs.acl("/home/zyu379/test_file.txt").unwrap();
+    assert_eq!(
+        acl.entries,
+        [
+            AclEntry {
+                tag: AclTag::UserObj,
+                perm: 6
+            },
+            AclEntry {
+                tag: AclTag::User(1000),
+                perm: 6
+            },
+            AclEntry {
+                tag: AclTag::GroupObj,
+                perm: 4
+            },
+            AclEntry {
+                tag: AclTag::Group(100),
+                perm: 4
+            },
+            AclEntry {
+                tag: AclTag::Mask,
+                perm: 6
+            },
+            AclEntry {
+                tag: AclTag::Other,
+                perm: 4
+            },
+        ]
+    );
+
+    let default = fs.default_acl("/home/zyu379").unwrap().unwrap();
+    assert!(default.entries.contains(&AclEntry {
+        tag: AclTag::User(1000),
+        perm: 7
+    }));
+
+    // Without an ACL attribute the permission bits are used
+    let acl = fs.acl("/home").unwrap();
+    assert_eq!(acl.to_string(), "user::rwx\ngroup::r-x\nother::r-x");
+    assert_eq!(fs.default_acl("/home").unwrap(), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_symlinks() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let expected = "hello from ext4 test\n";
+
+    // Fast symlinks: absolute, relative, and through a directory link
+    assert_eq!(
+        fs.read_link("/home/link_abs").unwrap(),
+        b"/home/zyu379/test_file.txt"
+    );
+    for path in [
+        "/home/link_abs",
+        "/home/zyu379/link_rel",
+        "/dirlink/test_file.txt",
+    ] {
+        let content = fs.read_file(path).unwrap();
+        assert_eq!(String::from_utf8_lossy(&content), expected);
+    }
+
+    // Slow symlink whose target does not fit in i_block
+    assert!(fs.read_link("/link_slow").unwrap().len() > 60);
+    let content = fs.read_file("/link_slow").unwrap();
+    assert_eq!(String::from_utf8_lossy(&content), expected);
+
+    assert!(fs.symlink_metadata("/home/link_abs").unwrap().is_symlink());
+    assert!(fs.metadata("/home/link_abs").unwrap().is_file());
+
+    let err = fs.read_file("/loop1").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // A final symlink is refused, but earlier ones are still followed
+    let err = fs
+        .read_file_with("/home/link_abs", FollowSymlinks::NoFollow)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let content = fs
+        .read_file_with("/dirlink/test_file.txt", FollowSymlinks::NoFollow)
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&content), expected);
+    let metadata = fs
+        .metadata_with("/home/link_abs", FollowSymlinks::NoFollow)
+        .unwrap();
+    assert!(metadata.is_symlink());
+    assert!(
+        fs.list_dir_with("/dirlink", FollowSymlinks::NoFollow)
+            .is_err()
+    );
+    assert!(fs.list_dir_with("/dirlink", FollowSymlinks::Follow).is_ok());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_current_dir() {
+    let mut fs = FileSystem::open("ext4.img").unwrap();
+    assert_eq!(fs.current_dir(), b"/");
+
+    fs.set_current_dir("home/zyu379").unwrap();
+    assert_eq!(fs.current_dir(), b"/home/zyu379");
+    let content = fs.read_file("./test_file.txt").unwrap();
+    assert_eq!(String::from_utf8_lossy(&content), "hello from ext4 test\n");
+
+    // ".." goes through the real directory entries
+    fs.set_current_dir("..").unwrap();
+    assert_eq!(fs.current_dir(), b"/home");
+    fs.set_current_dir("../../..").unwrap();
+    assert_eq!(fs.current_dir(), b"/");
+
+    // Symlinks are resolved into the canonical path
+    fs.set_current_dir("/dirlink/.").unwrap();
+    assert_eq!(fs.current_dir(), b"/home/zyu379");
+
+    let err = fs.set_current_dir("test_file.txt").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotADirectory);
+    assert_eq!(fs.current_dir(), b"/home/zyu379");
+
+    // A ".." entry pointing elsewhere than the parent is caught
+    let mut fs = FileSystem::from_device(std::fs::read("ext4.img").unwrap()).unwrap();
+    let bigdir = fs.resolve_path("/bigdir").unwrap();
+    let home = fs.resolve_path("/home/zyu379").unwrap();
+    // The ".." record follows the 12-byte "." record
+    patch_dir_block(&mut fs, home, |buf| {
+        buf[12..16].copy_from_slice(&bigdir.to_le_bytes())
+    });
+
+    assert_eq!(fs.resolve_path("/home/zyu379").unwrap(), home);
+    let err = fs.resolve_path("/home/zyu379/..").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_extract_tree() {
+    let fs = FileSystem::open("ext4.img").unwrap();
+    let dest = TempPath::new("extract");
+
+    let extracted = fs.extract_tree("/", &dest, 4).unwrap();
+    assert!(extracted > 500);
+    assert_eq!(
+        std::fs::read(dest.join("home/zyu379/test_file.txt")).unwrap(),
+        b"hello from ext4 test\n"
+    );
+    assert_eq!(
+        std::fs::read(dest.join("bigdir/file_007.txt")).unwrap(),
+        b"file 007\n"
+    );
+    assert_eq!(
+        std::fs::read_link(dest.join("dirlink")).unwrap(),
+        Path::new("home/zyu379")
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9");
+        assert_eq!(
+            std::fs::read(dest.join("raw").join(name)).unwrap(),
+            b"latin1\n"
+        );
+    }
+
+    // Extracting again never overwrites what is there
+    let err = fs.extract_tree("/home", dest.join("home"), 1).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+    // A symlink in the destination is not written through
+    #[cfg(unix)]
+    {
+        let outside = TempPath::new("outside");
+        let home = TempPath::new("extract_home");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::create_dir(&home).unwrap();
+        std::os::unix::fs::symlink(&*outside, home.join("zyu379")).unwrap();
+        let err = fs.extract_tree("/home", &home, 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+    }
+
+    // Names that are not one path component and directories linked twice
+    // are rejected; the record after "." and ".." is patched
+    let image = std::fs::read("ext4.img").unwrap();
+    let home = fs.resolve_path("/home").unwrap();
+    let user = fs.resolve_path("/home/zyu379").unwrap();
+    let extract_patched = |patch: &dyn Fn(&mut [u8])| {
+        let mut bad = FileSystem::from_device(image.clone()).unwrap();
+        patch_dir_block(&mut bad, user, patch);
+        let dest = TempPath::new("extract_bad");
+        bad.extract_tree("/home", &dest, 1).unwrap_err().kind()
+    };
+    let slash = extract_patched(&|buf| buf[24 + 8] = b'/');
+    assert_eq!(slash, io::ErrorKind::InvalidData);
+    let linked = extract_patched(&|buf| {
+        buf[24..28].copy_from_slice(&home.to_le_bytes());
+        buf[24 + 7] = 2;
+    });
+    assert_eq!(linked, io::ErrorKind::InvalidData);
+
+    // One filesystem serves reads from several threads at once
+    std::thread::scope(|scope| {
+        for n in 0..4 {
+            let fs = &fs;
+            scope.spawn(move || {
+                let path = format!("/bigdir/file_00{}.txt", n + 1);
+                assert_eq!(
+                    fs.read_file(path).unwrap(),
+                    format!("file 00{}\n", n + 1).as_bytes()
+                );
+            });
+        }
+    });
 }
@@ -1,5 +1,5 @@
 use clap::Parser;
-use ext4fs::FileSystem;
+use ext4fs::{BlockDevice, FileSystem, ImageFile, escape_name, read_partitions, unescape_name};
 use std::io::{self, BufRead, Write};
 
 /// ext4fs interactive explorer
@@ -7,14 +7,60 @@ use std::io::{self, BufRead, Write};
 #[command(name = "ext4fs")]
 #[command(about = "Explore an ext4 filesystem image interactively")]
 struct Cli {
-    /// Path to ext4 image
+    /// Path to ext4 image, or a disk image with `#pN` to pick partition N
     #[arg(short, long, default_value = "ext4.img")]
     image: String,
+
+    /// Partition number within a whole-disk image
+    #[arg(short, long)]
+    partition: Option<u32>,
+
+    /// List the partitions of the image and exit
+    #[arg(long)]
+    list_partitions: bool,
+
+    /// Bytes of metadata blocks to keep cached in memory
+    #[arg(long)]
+    cache_size: Option<usize>,
+
+    /// Memory-map the image instead of reading it through file I/O; the
+    /// image must not be modified while the shell runs
+    #[arg(long)]
+    mmap: bool,
 }
 
 fn main() -> io::Result<()> {
     let cli = Cli::parse();
-    let mut fs = FileSystem::open(&cli.image)?;
+    if cli.list_partitions {
+        let image = ImageFile::open(&cli.image)?;
+        for partition in read_partitions(&image)? {
+            println!("{}", partition);
+        }
+        return Ok(());
+    }
+
+    if cli.mmap {
+        let path = match cli.partition {
+            Some(index) => format!("{}#p{}", cli.image, index),
+            None => cli.image.clone(),
+        };
+        // SAFETY: --mmap documents that the image must not change while
+        // the shell runs
+        run(unsafe { FileSystem::open_mmap(&path)? }, &cli)
+    } else {
+        let fs = match cli.partition {
+            Some(index) => FileSystem::open_partition(&cli.image, index)?,
+            None => FileSystem::open(&cli.image)?,
+        };
+        run(fs, &cli)
+    }
+}
+
+/// Run the interactive shell on an opened filesystem
+fn run<D: BlockDevice + Sync>(mut fs: FileSystem<D>, cli: &Cli) -> io::Result<()> {
+    if let Some(bytes) = cli.cache_size {
+        fs.set_cache_capacity(bytes);
+    }
     println!("Opened image: {}", cli.image);
     println!("Type 'help' for available commands. Type 'exit' to quit.");
 
@@ -26,7 +72,9 @@ fn main() -> io::Result<()> {
 
         let mut input = String::new();
         stdin.lock().read_line(&mut input)?;
-        let args: Vec<&str> = input.trim().split_whitespace().collect();
+        let args: Vec<&str> = input.split_whitespace().collect();
+        // Paths may spell non-UTF-8 bytes as `\xNN`, as they are listed
+        let path = |i: usize| unescape_name(args.get(i).copied().unwrap_or("."));
 
         if args.is_empty() {
             continue;
@@ -36,16 +84,40 @@ fn main() -> io::Result<()> {
             "exit" | "quit" => break,
             "help" => {
                 println!("Commands:");
-                println!("  read <path>   - Read and print file content");
-                println!("  ls <path>     - List directory entries");
-                println!("  stat <path>   - Print inode metadata (TODO)");
-                println!("  exit, quit    - Exit the interactive shell");
+                println!("  read <path>     - Read and print file content");
+                println!("  ls [path]       - List directory entries");
+                println!("  ls -d [path]    - List deleted entries still in the directory");
+                println!("  cd <path>       - Change the current directory");
+                println!("  pwd             - Print the current directory");
+                println!("  stat <path>     - Print inode metadata");
+                println!("  readlink <path> - Print the target of a symbolic link");
+                println!("  getfattr <path> - Print extended attributes");
+                println!("  getfacl <path>  - Print POSIX access control lists");
+                println!("  extract <path> <dir> - Copy a directory tree to the host");
+                println!("  cache           - Print block cache statistics");
+                println!("  exit, quit      - Exit the interactive shell");
             }
-            "read" if args.len() == 2 => match fs.read_file(args[1]) {
-                Ok(content) => println!("{}", String::from_utf8_lossy(&content)),
-                Err(e) => eprintln!("Error reading file: {e}"),
-            },
-            "ls" if args.len() == 2 => match fs.list_dir(args[1]) {
+            "read" if args.len() == 2 => {
+                // Stream the content so large files are never held in memory
+                let copied = fs
+                    .open_file(path(1))
+                    .and_then(|mut file| io::copy(&mut file, &mut stdout));
+                match copied {
+                    Ok(_) => println!(),
+                    Err(e) => eprintln!("Error reading file: {e}"),
+                }
+            }
+            "ls" if args.get(1) == Some(&"-d") && args.len() <= 3 => {
+                match fs.list_deleted(path(2)) {
+                    Ok(entries) => {
+                        for entry in entries {
+                            println!("{}", entry);
+                        }
+                    }
+                    Err(e) => eprintln!("Error listing directory: {e}"),
+                }
+            }
+            "ls" if args.len() <= 2 => match fs.list_dir(path(1)) {
                 Ok(entries) => {
                     for entry in entries {
                         println!("{}", entry);
@@ -53,9 +125,38 @@ fn main() -> io::Result<()> {
                 }
                 Err(e) => eprintln!("Error listing directory: {e}"),
             },
-            "stat" => {
-                println!("TODO: '{}' command is not implemented yet.", args[0]);
+            "cd" if args.len() == 2 => {
+                if let Err(e) = fs.set_current_dir(path(1)) {
+                    eprintln!("Error changing directory: {e}");
+                }
             }
+            "extract" if args.len() == 3 => match fs.extract_tree(path(1), args[2], 0) {
+                Ok(files) => println!("Extracted {files} files to {}", args[2]),
+                Err(e) => eprintln!("Error extracting: {e}"),
+            },
+            "cache" => println!("{}", fs.cache_stats()),
+            "pwd" => println!("{}", escape_name(&fs.current_dir())),
+            "stat" if args.len() == 2 => match fs.stat(path(1)) {
+                Ok(stat) => println!("{stat}"),
+                Err(e) => eprintln!("Error reading inode: {e}"),
+            },
+            "readlink" if args.len() == 2 => match fs.read_link(path(1)) {
+                Ok(target) => println!("{}", escape_name(&target)),
+                Err(e) => eprintln!("Error reading link: {e}"),
+            },
+            "getfattr" if args.len() == 2 => match fs.xattrs(path(1)) {
+                Ok(xattrs) => {
+                    println!("# file: {}", args[1]);
+                    for xattr in xattrs {
+                        println!("{}", xattr);
+                    }
+                }
+                Err(e) => eprintln!("Error reading extended attributes: {e}"),
+            },
+            "getfacl" if args.len() == 2 => match print_acl(&mut fs, args[1]) {
+                Ok(()) => {}
+                Err(e) => eprintln!("Error reading ACL: {e}"),
+            },
             _ => {
                 eprintln!("Unknown or malformed command. Type 'help' for available commands.");
             }
@@ -64,3 +165,22 @@ fn main() -> io::Result<()> {
 
     Ok(())
 }
+
+/// Print the access and default ACLs of a path like `getfacl -n`
+fn print_acl<D: BlockDevice>(fs: &mut FileSystem<D>, path: &str) -> io::Result<()> {
+    let bytes = unescape_name(path);
+    let metadata = fs.metadata(&bytes)?;
+    let acl = fs.acl(&bytes)?;
+    let default = fs.default_acl(&bytes)?;
+
+    println!("# file: {}", path);
+    println!("# owner: {}", metadata.uid());
+    println!("# group: {}", metadata.gid());
+    println!("{}", acl);
+    if let Some(default) = default {
+        for line in default.to_string().lines() {
+            println!("default:{}", line);
+        }
+    }
+    Ok(())
+}
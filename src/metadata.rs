@@ -0,0 +1,201 @@
+use crate::inode::{Inode, Timestamp};
+
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+// File type bits of i_mode
+const S_IFMT: u16 = 0xF000;
+const S_IFIFO: u16 = 0x1000;
+const S_IFCHR: u16 = 0x2000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFBLK: u16 = 0x6000;
+const S_IFREG: u16 = 0x8000;
+const S_IFLNK: u16 = 0xA000;
+const S_IFSOCK: u16 = 0xC000;
+
+/// The type of a file, as recorded in its inode or directory entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    RegularFile,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    /// Type bits that do not match any known file type
+    Unknown,
+}
+
+impl FileType {
+    /// Decode the file type from the upper bits of `i_mode`
+    pub(crate) fn from_mode(mode: u16) -> Self {
+        match mode & S_IFMT {
+            S_IFREG => FileType::RegularFile,
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            S_IFIFO => FileType::Fifo,
+            S_IFSOCK => FileType::Socket,
+            _ => FileType::Unknown,
+        }
+    }
+
+    /// Check if this is a directory
+    pub fn is_dir(&self) -> bool {
+        *self == FileType::Directory
+    }
+
+    /// Check if this is a regular file
+    pub fn is_file(&self) -> bool {
+        *self == FileType::RegularFile
+    }
+
+    /// Check if this is a symbolic link
+    pub fn is_symlink(&self) -> bool {
+        *self == FileType::Symlink
+    }
+}
+
+/// Permission bits of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    mode: u16,
+}
+
+impl Permissions {
+    /// Permission bits, including setuid, setgid and sticky (`mode & 0o7777`)
+    pub fn mode(&self) -> u32 {
+        self.mode as u32
+    }
+
+    /// Whether nobody has write permission
+    pub fn readonly(&self) -> bool {
+        self.mode & 0o222 == 0
+    }
+}
+
+/// Metadata about a file, modelled on `std::fs::Metadata`
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    ino: u32,
+    mode: u16,
+    nlink: u16,
+    uid: u32,
+    gid: u32,
+    len: u64,
+    // Only exposed as `SystemTime`, which needs `std`
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    accessed: Timestamp,
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    modified: Timestamp,
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    changed: Timestamp,
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    created: Option<Timestamp>,
+}
+
+impl Metadata {
+    /// Build metadata from a parsed inode
+    pub(crate) fn from_inode(ino: u32, inode: &Inode) -> Self {
+        Self {
+            ino,
+            mode: inode.inode_mode,
+            nlink: inode.links_count,
+            uid: inode.uid,
+            gid: inode.gid,
+            len: inode.inode_size,
+            accessed: inode.atime,
+            modified: inode.mtime,
+            changed: inode.ctime,
+            created: inode.crtime,
+        }
+    }
+
+    /// The type of this file
+    pub fn file_type(&self) -> FileType {
+        FileType::from_mode(self.mode)
+    }
+
+    /// Check if this metadata is for a directory
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    /// Check if this metadata is for a regular file
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    /// Check if this metadata is for a symbolic link
+    pub fn is_symlink(&self) -> bool {
+        self.file_type().is_symlink()
+    }
+
+    /// Size of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the file is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Permission bits of the file
+    pub fn permissions(&self) -> Permissions {
+        Permissions {
+            mode: self.mode & 0o7777,
+        }
+    }
+
+    /// Raw `i_mode`, including the file type bits
+    pub fn mode(&self) -> u32 {
+        self.mode as u32
+    }
+
+    /// Inode number
+    pub fn ino(&self) -> u32 {
+        self.ino
+    }
+
+    /// Number of hard links
+    pub fn nlink(&self) -> u64 {
+        self.nlink as u64
+    }
+
+    /// Owner user id
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Owner group id
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Last access time (`i_atime`)
+    #[cfg(feature = "std")]
+    pub fn accessed(&self) -> SystemTime {
+        self.accessed.to_system_time()
+    }
+
+    /// Last modification time (`i_mtime`)
+    #[cfg(feature = "std")]
+    pub fn modified(&self) -> SystemTime {
+        self.modified.to_system_time()
+    }
+
+    /// Last inode change time (`i_ctime`)
+    #[cfg(feature = "std")]
+    pub fn changed(&self) -> SystemTime {
+        self.changed.to_system_time()
+    }
+
+    /// Creation time (`i_crtime`), if the inode is large enough to store it
+    #[cfg(feature = "std")]
+    pub fn created(&self) -> Option<SystemTime> {
+        self.created.map(|t| t.to_system_time())
+    }
+}
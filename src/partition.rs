@@ -0,0 +1,510 @@
+use crate::bytes::Cursor;
+use crate::checksum::crc32;
+use crate::device::BlockDevice;
+use crate::io;
+use crate::superblock::{SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE, Superblock};
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::LittleEndian;
+
+/// Sector size assumed for MBR addresses
+const MBR_SECTOR_SIZE: u64 = 512;
+
+/// Offset of the four partition entries in an MBR or EBR
+const MBR_OFFSET_TABLE: usize = 0x1BE;
+
+/// Size of one MBR partition entry
+const MBR_ENTRY_SIZE: usize = 16;
+
+/// Offset of the `55 AA` boot signature
+const MBR_OFFSET_SIGNATURE: usize = 0x1FE;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// Partition type of the protective MBR in front of a GPT
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+/// Partition types of extended partitions holding a chain of EBRs
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// Number given to the first logical partition, as Linux does
+const MBR_FIRST_LOGICAL: u32 = 5;
+
+/// Logical partitions followed before the EBR chain is considered corrupt
+const MBR_MAX_LOGICAL: usize = 256;
+
+/// Signature at the start of a GPT header
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// Logical block sizes probed for the GPT header at LBA 1
+const GPT_SECTOR_SIZES: [u64; 2] = [512, 4096];
+
+/// Fixed offsets for GPT header fields
+const GPT_OFFSET_HEADER_SIZE: u64 = 0x0C; // Bytes covered by the header CRC
+const GPT_OFFSET_HEADER_CRC: usize = 0x10; // CRC32 of the header
+const GPT_OFFSET_ENTRIES_LBA: u64 = 0x48; // First LBA of the entry array
+const GPT_OFFSET_ENTRY_COUNT: u64 = 0x50; // Number of entries
+const GPT_OFFSET_ENTRY_SIZE: u64 = 0x54; // Size of one entry
+const GPT_OFFSET_ENTRIES_CRC: u64 = 0x58; // CRC32 of the entry array
+
+/// Size of the GPT header fields defined by the specification
+const GPT_HEADER_SIZE: usize = 92;
+
+/// Smallest valid GPT entry, holding the fields read here
+const GPT_MIN_ENTRY_SIZE: u32 = 128;
+
+/// Upper bound on the entry array read, to reject absurd headers
+const GPT_MAX_ENTRIES_SIZE: u64 = 1 << 20;
+
+/// Length of the UTF-16 partition name in a GPT entry, in code units
+const GPT_NAME_UNITS: usize = 36;
+
+/// A GUID in its on-disk mixed-endian layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl core::fmt::Display for Guid {
+    /// Formats as `0FC63DAF-8483-4772-8E79-3D69D8477DE4`; the first three
+    /// groups are stored little-endian
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+        b[10..]
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+/// The partition type, as recorded by the partition table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    /// MBR system ID, e.g. `0x83` for Linux
+    Mbr(u8),
+    /// GPT partition type GUID
+    Gpt(Guid),
+}
+
+impl core::fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PartitionType::Mbr(id) => write!(f, "0x{:02x}", id),
+            PartitionType::Gpt(guid) => write!(f, "{}", guid),
+        }
+    }
+}
+
+/// One partition of a whole-disk image
+#[derive(Debug, Clone)]
+pub struct Partition {
+    /// Partition number as Linux names it: `p1`-`p4` for MBR primaries,
+    /// `p5` onwards for logical partitions, the entry slot for GPT
+    pub index: u32,
+    /// Byte offset of the partition within the disk
+    pub start: u64,
+    /// Size of the partition in bytes
+    pub size: u64,
+    /// Type ID or GUID
+    pub kind: PartitionType,
+    /// GPT partition name, if set
+    pub name: Option<String>,
+    /// Whether an ext2/3/4 superblock was found in the partition
+    pub is_ext: bool,
+}
+
+impl core::fmt::Display for Partition {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "p{:<3} {:>12} {:>12}  {}",
+            self.index, self.start, self.size, self.kind
+        )?;
+        if let Some(name) = &self.name {
+            write!(f, "  \"{}\"", name)?;
+        }
+        if self.is_ext {
+            write!(f, "  ext")?;
+        }
+        Ok(())
+    }
+}
+
+/// List the partitions of a whole-disk image
+///
+/// A GPT is preferred when the MBR is protective; otherwise the MBR's
+/// primary partitions are listed, followed by the logical partitions of
+/// any extended partition.
+///
+/// # Arguments
+/// * `device` - Whole-disk image
+///
+/// # Returns
+/// Partitions in table order; empty if there is no partition table
+///
+/// # Errors
+/// Returns error if reading the device fails or a table is malformed
+pub fn read_partitions<D: BlockDevice + ?Sized>(device: &D) -> io::Result<Vec<Partition>> {
+    let mut mbr = vec![0u8; MBR_SECTOR_SIZE as usize];
+    if device.size()? < MBR_SECTOR_SIZE {
+        return Ok(Vec::new());
+    }
+    device.read_at(0, &mut mbr)?;
+    if mbr[MBR_OFFSET_SIGNATURE..MBR_OFFSET_SIGNATURE + 2] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let entries = mbr_entries(&mbr);
+    let mut partitions = if entries.iter().any(|e| e.kind == MBR_TYPE_GPT_PROTECTIVE) {
+        read_gpt(device)?
+    } else {
+        read_mbr(device, &entries)?
+    };
+
+    for partition in &mut partitions {
+        partition.is_ext = has_ext_superblock(device, partition)?;
+    }
+    Ok(partitions)
+}
+
+/// Whether a partition starts with an ext2/3/4 filesystem
+fn has_ext_superblock<D: BlockDevice + ?Sized>(
+    device: &D,
+    partition: &Partition,
+) -> io::Result<bool> {
+    if partition.size < SUPERBLOCK_OFFSET + SUPERBLOCK_SIZE as u64 {
+        return Ok(false);
+    }
+    let mut buf = vec![0u8; SUPERBLOCK_SIZE as usize];
+    let Some(offset) = partition.start.checked_add(SUPERBLOCK_OFFSET) else {
+        return Ok(false);
+    };
+    match device.read_at(offset, &mut buf) {
+        Ok(()) => Ok(Superblock::has_magic(&buf)),
+        // The table may claim more than the image holds
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// An entry of an MBR or EBR partition table, in sectors
+struct MbrEntry {
+    kind: u8,
+    lba_start: u32,
+    sectors: u32,
+}
+
+impl MbrEntry {
+    fn is_used(&self) -> bool {
+        self.kind != 0 && self.sectors != 0
+    }
+
+    fn is_extended(&self) -> bool {
+        MBR_TYPES_EXTENDED.contains(&self.kind)
+    }
+}
+
+/// The four table entries of an MBR or EBR sector
+fn mbr_entries(sector: &[u8]) -> Vec<MbrEntry> {
+    (0..4)
+        .map(|i| {
+            let entry = &sector[MBR_OFFSET_TABLE + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+            let mut cursor = Cursor::new(&entry[8..]);
+            MbrEntry {
+                kind: entry[4],
+                lba_start: cursor.read_u32::<LittleEndian>().unwrap(),
+                sectors: cursor.read_u32::<LittleEndian>().unwrap(),
+            }
+        })
+        .collect()
+}
+
+/// Primary partitions, then the logical partitions of the extended one
+fn read_mbr<D: BlockDevice + ?Sized>(
+    device: &D,
+    entries: &[MbrEntry],
+) -> io::Result<Vec<Partition>> {
+    let mut partitions = Vec::new();
+    let mut extended = None;
+    for (slot, entry) in entries.iter().enumerate() {
+        if !entry.is_used() {
+            continue;
+        }
+        if entry.is_extended() {
+            extended.get_or_insert(entry.lba_start as u64);
+        }
+        partitions.push(mbr_partition(slot as u32 + 1, 0, entry));
+    }
+
+    if let Some(extended_start) = extended {
+        read_logical(device, extended_start, &mut partitions)?;
+    }
+    Ok(partitions)
+}
+
+/// Follow the EBR chain of an extended partition
+///
+/// Each EBR describes one logical partition relative to itself, and links
+/// to the next EBR relative to the start of the extended partition.
+fn read_logical<D: BlockDevice + ?Sized>(
+    device: &D,
+    extended_start: u64,
+    partitions: &mut Vec<Partition>,
+) -> io::Result<()> {
+    let mut ebr = vec![0u8; MBR_SECTOR_SIZE as usize];
+    let mut next = Some(extended_start);
+    let mut visited = BTreeSet::new();
+    let mut index = MBR_FIRST_LOGICAL;
+
+    while let Some(lba) = next.take() {
+        if !visited.insert(lba) || visited.len() > MBR_MAX_LOGICAL {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Extended partition has a looping EBR chain",
+            ));
+        }
+        device.read_at(lba * MBR_SECTOR_SIZE, &mut ebr)?;
+        if ebr[MBR_OFFSET_SIGNATURE..MBR_OFFSET_SIGNATURE + 2] != MBR_SIGNATURE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid EBR at sector {}", lba),
+            ));
+        }
+
+        let entries = mbr_entries(&ebr);
+        if entries[0].is_used() {
+            partitions.push(mbr_partition(index, lba, &entries[0]));
+            index += 1;
+        }
+        if entries[1].is_used() && entries[1].is_extended() {
+            next = Some(extended_start + entries[1].lba_start as u64);
+        }
+    }
+    Ok(())
+}
+
+/// A partition from an MBR entry whose start is relative to `base_lba`
+fn mbr_partition(index: u32, base_lba: u64, entry: &MbrEntry) -> Partition {
+    Partition {
+        index,
+        start: (base_lba + entry.lba_start as u64) * MBR_SECTOR_SIZE,
+        size: entry.sectors as u64 * MBR_SECTOR_SIZE,
+        kind: PartitionType::Mbr(entry.kind),
+        name: None,
+        is_ext: false,
+    }
+}
+
+/// Partitions of the GPT following a protective MBR
+///
+/// Both the header and the entry array must match their CRC32; the backup
+/// GPT at the end of the disk is not consulted.
+fn read_gpt<D: BlockDevice + ?Sized>(device: &D) -> io::Result<Vec<Partition>> {
+    let mut header = vec![0u8; GPT_HEADER_SIZE];
+    let mut sector_size = None;
+    for size in GPT_SECTOR_SIZES {
+        if device.read_at(size, &mut header).is_ok() && &header[..8] == GPT_SIGNATURE {
+            sector_size = Some(size);
+            break;
+        }
+    }
+    let sector_size = sector_size.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Protective MBR without a GPT header",
+        )
+    })?;
+
+    // The CRC covers the whole header as sized by itself, which may be
+    // longer than the fields known here but not longer than its sector
+    let mut cursor = Cursor::new(&header[..]);
+    cursor.set_position(GPT_OFFSET_HEADER_SIZE);
+    let header_size = cursor.read_u32::<LittleEndian>()? as usize;
+    if !(GPT_HEADER_SIZE..=sector_size as usize).contains(&header_size) {
+        return Err(invalid_gpt(format!("header size {}", header_size)));
+    }
+    header.resize(header_size, 0);
+    device.read_at(sector_size, &mut header)?;
+    let stored = u32::from_le_bytes(
+        header[GPT_OFFSET_HEADER_CRC..GPT_OFFSET_HEADER_CRC + 4]
+            .try_into()
+            .unwrap(),
+    );
+    header[GPT_OFFSET_HEADER_CRC..GPT_OFFSET_HEADER_CRC + 4].fill(0);
+    if crc32(0, &header) != stored {
+        return Err(invalid_gpt("header checksum mismatch".into()));
+    }
+
+    let mut cursor = Cursor::new(&header[..]);
+    cursor.set_position(GPT_OFFSET_ENTRIES_LBA);
+    let entries_lba = cursor.read_u64::<LittleEndian>()?;
+    cursor.set_position(GPT_OFFSET_ENTRY_COUNT);
+    let entry_count = cursor.read_u32::<LittleEndian>()?;
+    cursor.set_position(GPT_OFFSET_ENTRY_SIZE);
+    let entry_size = cursor.read_u32::<LittleEndian>()?;
+    cursor.set_position(GPT_OFFSET_ENTRIES_CRC);
+    let entries_crc = cursor.read_u32::<LittleEndian>()?;
+
+    let table_size = entry_count as u64 * entry_size as u64;
+    if entry_size < GPT_MIN_ENTRY_SIZE || table_size > GPT_MAX_ENTRIES_SIZE {
+        return Err(invalid_gpt(format!(
+            "entry array of {} entries of {} bytes",
+            entry_count, entry_size
+        )));
+    }
+    let table_offset = entries_lba
+        .checked_mul(sector_size)
+        .ok_or_else(|| invalid_gpt(format!("entry array at LBA {}", entries_lba)))?;
+    let mut table = vec![0u8; table_size as usize];
+    device.read_at(table_offset, &mut table)?;
+    if crc32(0, &table) != entries_crc {
+        return Err(invalid_gpt("entry array checksum mismatch".into()));
+    }
+
+    let mut partitions = Vec::new();
+    for (slot, entry) in table.chunks_exact(entry_size as usize).enumerate() {
+        let kind = Guid(entry[..16].try_into().unwrap());
+        if kind.0 == [0; 16] {
+            continue;
+        }
+
+        let mut cursor = Cursor::new(&entry[32..]);
+        let first_lba = cursor.read_u64::<LittleEndian>()?;
+        let last_lba = cursor.read_u64::<LittleEndian>()?;
+        let _attributes = cursor.read_u64::<LittleEndian>()?;
+        let units: Vec<u16> = (0..GPT_NAME_UNITS)
+            .map(|_| cursor.read_u16::<LittleEndian>())
+            .collect::<Result<_, _>>()?;
+        let len = units.iter().position(|&u| u == 0).unwrap_or(units.len());
+        let name = String::from_utf16_lossy(&units[..len]);
+
+        // Both ends must be byte offsets the disk could have
+        let start = first_lba.checked_mul(sector_size);
+        let end = last_lba
+            .checked_add(1)
+            .and_then(|end| end.checked_mul(sector_size));
+        let (Some(start), Some(end)) = (start, end) else {
+            return Err(invalid_gpt(format!(
+                "partition {} spans LBA {}..={}",
+                slot + 1,
+                first_lba,
+                last_lba
+            )));
+        };
+
+        partitions.push(Partition {
+            index: slot as u32 + 1,
+            start,
+            size: end.saturating_sub(start),
+            kind: PartitionType::Gpt(kind),
+            name: (!name.is_empty()).then_some(name),
+            is_ext: false,
+        });
+    }
+    Ok(partitions)
+}
+
+/// Error for a GPT whose header or entry array is unusable
+fn invalid_gpt(reason: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid GPT: {}", reason),
+    )
+}
+
+#[cfg(test)]
+fn mbr_entry(sector: &mut [u8], slot: usize, kind: u8, lba_start: u32, sectors: u32) {
+    let entry = &mut sector[MBR_OFFSET_TABLE + slot * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+    entry[4] = kind;
+    entry[8..12].copy_from_slice(&lba_start.to_le_bytes());
+    entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    sector[MBR_OFFSET_SIGNATURE..MBR_OFFSET_SIGNATURE + 2].copy_from_slice(&MBR_SIGNATURE);
+}
+
+#[test]
+fn test_mbr_partitions() {
+    let mut disk = vec![0u8; 64 * 1024];
+    // p1 at sector 4; an extended partition at 40 holding two logical ones
+    mbr_entry(&mut disk[..512], 0, 0x83, 4, 16);
+    mbr_entry(&mut disk[..512], 1, 0x05, 40, 80);
+    let ebr = 40 * 512;
+    mbr_entry(&mut disk[ebr..ebr + 512], 0, 0x83, 2, 8);
+    mbr_entry(&mut disk[ebr..ebr + 512], 1, 0x05, 20, 30);
+    let ebr = 60 * 512;
+    mbr_entry(&mut disk[ebr..ebr + 512], 0, 0x07, 4, 24);
+    // Only p5 holds an ext superblock
+    disk[42 * 512 + 1024 + 0x38..][..2].copy_from_slice(&[0x53, 0xEF]);
+
+    let partitions = read_partitions(&disk.as_slice()).unwrap();
+    let summary: Vec<_> = partitions
+        .iter()
+        .map(|p| (p.index, p.start / 512, p.size / 512, p.kind, p.is_ext))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (1, 4, 16, PartitionType::Mbr(0x83), false),
+            (2, 40, 80, PartitionType::Mbr(0x05), false),
+            (5, 42, 8, PartitionType::Mbr(0x83), true),
+            (6, 64, 24, PartitionType::Mbr(0x07), false),
+        ]
+    );
+
+    // An EBR linking back to itself is rejected
+    mbr_entry(&mut disk[ebr..ebr + 512], 1, 0x05, 20, 30);
+    assert!(read_partitions(&disk.as_slice()).is_err());
+}
+
+#[test]
+fn test_gpt_partitions() {
+    let mut disk = vec![0u8; 64 * 1024];
+    mbr_entry(&mut disk[..512], 0, MBR_TYPE_GPT_PROTECTIVE, 1, 127);
+
+    let header = &mut disk[512..1024];
+    header[..8].copy_from_slice(GPT_SIGNATURE);
+    header[0x48..0x50].copy_from_slice(&2u64.to_le_bytes());
+    header[0x50..0x54].copy_from_slice(&4u32.to_le_bytes());
+    header[0x54..0x58].copy_from_slice(&128u32.to_le_bytes());
+
+    // Linux filesystem data in the second slot, named "root"
+    let linux = [
+        0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D,
+        0xE4,
+    ];
+    let entry = &mut disk[1024 + 128..1024 + 256];
+    entry[..16].copy_from_slice(&linux);
+    entry[32..40].copy_from_slice(&34u64.to_le_bytes());
+    entry[40..48].copy_from_slice(&99u64.to_le_bytes());
+    for (i, c) in "root".encode_utf16().enumerate() {
+        entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+    }
+    disk[34 * 512 + 1024 + 0x38..][..2].copy_from_slice(&[0x53, 0xEF]);
+
+    // A header without a valid size and CRC is rejected
+    assert!(read_partitions(&disk.as_slice()).is_err());
+    let entries_crc = crc32(0, &disk[1024..1024 + 4 * 128]);
+    let header = &mut disk[512..1024];
+    header[0x0C..0x10].copy_from_slice(&(GPT_HEADER_SIZE as u32).to_le_bytes());
+    header[0x58..0x5C].copy_from_slice(&entries_crc.to_le_bytes());
+    let header_crc = crc32(0, &header[..GPT_HEADER_SIZE]);
+    header[0x10..0x14].copy_from_slice(&header_crc.to_le_bytes());
+
+    let partitions = read_partitions(&disk.as_slice()).unwrap();
+    assert_eq!(partitions.len(), 1);
+    let root = &partitions[0];
+    assert_eq!((root.index, root.start, root.size), (2, 34 * 512, 66 * 512));
+    assert_eq!(
+        format!("{}", root.kind),
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4"
+    );
+    assert_eq!(root.name.as_deref(), Some("root"));
+    assert!(root.is_ext);
+
+    disk[1024 + 128 + 40] ^= 1;
+    assert!(read_partitions(&disk.as_slice()).is_err());
+}
@@ -0,0 +1,608 @@
+use crate::bytes::Cursor;
+use crate::cache::{Lock, LruCache, lock};
+use crate::device::{BlockDevice, past_end};
+use crate::io;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::BigEndian;
+
+/// `magic` of a qcow image header, `QFI\xfb`
+const QCOW_MAGIC: u32 = 0x5146_49FB;
+
+/// Size of the version 2 header; version 3 headers continue with feature
+/// bits and state their own length
+const QCOW2_HEADER_SIZE: usize = 72;
+
+/// Size of the version 3 header without optional fields
+const QCOW3_HEADER_SIZE: usize = 104;
+
+/// Offset of `header_length` in a version 3 header
+const QCOW3_OFFSET_HEADER_LENGTH: u64 = 100;
+
+// Header extension types
+const EXT_END: u32 = 0;
+const EXT_BACKING_FORMAT: u32 = 0xE279_2ACA;
+
+/// Longest backing format name accepted; real ones are a few letters
+const MAX_BACKING_FORMAT_NAME: u32 = 16;
+
+/// Cluster sizes allowed by the format, 512 bytes to 2 MiB
+const MIN_CLUSTER_BITS: u32 = 9;
+const MAX_CLUSTER_BITS: u32 = 21;
+
+/// Largest L1 table accepted, as in QEMU
+const MAX_L1_SIZE: u64 = 32 << 20;
+
+/// Longest backing file name allowed by the format
+const MAX_BACKING_FILE_NAME: u32 = 1023;
+
+/// L2 tables kept in memory
+const L2_CACHE_TABLES: usize = 32;
+
+// Incompatible feature bits
+const INCOMPAT_DIRTY: u64 = 1 << 0;
+const INCOMPAT_CORRUPT: u64 = 1 << 1;
+const INCOMPAT_COMPRESSION_TYPE: u64 = 1 << 3;
+
+/// Host offset bits of L1 and standard L2 entries
+const ENTRY_OFFSET_MASK: u64 = 0x00FF_FFFF_FFFF_FE00;
+
+/// L2 entry flags
+const L2_COMPRESSED: u64 = 1 << 62;
+const L2_ZERO: u64 = 1 << 0;
+
+/// Where the data of a guest cluster is
+enum Cluster {
+    /// Stored at this offset of the image file
+    Data(u64),
+    /// Reads as zeros
+    Zero,
+    /// Not allocated here; comes from the backing file, if any
+    Unallocated,
+}
+
+/// A QEMU qcow2 disk image, read as the raw disk it represents
+///
+/// Version 2 and 3 images are supported, including zero clusters and
+/// backing files. Encrypted images, compressed clusters, external data
+/// files and extended L2 entries are not. The active state is read;
+/// internal snapshots are ignored.
+///
+/// Clusters that are not allocated in the image are read from the backing
+/// device set with [`set_backing`](Self::set_backing), or as zeros if the
+/// image has no backing file.
+pub struct Qcow2Image<D> {
+    inner: D,
+    cluster_bits: u32,
+    /// Size of the virtual disk in bytes
+    size: u64,
+    /// Offsets of the L2 tables, with flag bits
+    l1: Vec<u64>,
+    /// Recently used L2 tables by offset in the image
+    l2_tables: Lock<LruCache<u64, Arc<[u64]>>>,
+    backing_file: Option<String>,
+    /// Format of the backing file, from the header extension
+    backing_format: Option<String>,
+    backing: Option<Box<dyn BlockDevice + Send + Sync>>,
+}
+
+impl<D: BlockDevice> Qcow2Image<D> {
+    /// Whether a device starts with a qcow image header
+    ///
+    /// # Errors
+    /// Returns error if reading the device fails for another reason than
+    /// it being too short
+    pub fn detect(device: &D) -> io::Result<bool> {
+        let mut magic = [0u8; 4];
+        match device.read_at(0, &mut magic) {
+            Ok(()) => Ok(u32::from_be_bytes(magic) == QCOW_MAGIC),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the header and L1 table of a qcow2 image
+    ///
+    /// # Arguments
+    /// * `inner` - Device holding the image file
+    ///
+    /// # Errors
+    /// Returns `InvalidData` for a malformed header, and `Unsupported` for
+    /// other qcow versions or features this reader does not handle
+    pub fn new(inner: D) -> io::Result<Self> {
+        let mut header = [0u8; QCOW2_HEADER_SIZE];
+        inner.read_at(0, &mut header)?;
+        let mut cursor = Cursor::new(&header);
+        let magic = cursor.read_u32::<BigEndian>()?;
+        let version = cursor.read_u32::<BigEndian>()?;
+        let backing_file_offset = cursor.read_u64::<BigEndian>()?;
+        let backing_file_size = cursor.read_u32::<BigEndian>()?;
+        let cluster_bits = cursor.read_u32::<BigEndian>()?;
+        let size = cursor.read_u64::<BigEndian>()?;
+        let crypt_method = cursor.read_u32::<BigEndian>()?;
+        let l1_size = cursor.read_u32::<BigEndian>()? as u64;
+        let l1_table_offset = cursor.read_u64::<BigEndian>()?;
+
+        if magic != QCOW_MAGIC {
+            return Err(invalid("Not a qcow image"));
+        }
+        if version != 2 && version != 3 {
+            return Err(unsupported(format!("Unsupported qcow version {}", version)));
+        }
+        if crypt_method != 0 {
+            return Err(unsupported("Encrypted qcow2 images are not supported"));
+        }
+        if !(MIN_CLUSTER_BITS..=MAX_CLUSTER_BITS).contains(&cluster_bits) {
+            return Err(invalid(format!(
+                "Invalid qcow2 cluster bits {}",
+                cluster_bits
+            )));
+        }
+
+        let mut header_length = QCOW2_HEADER_SIZE as u64;
+        if version == 3 {
+            let mut features = [0u8; 8];
+            inner.read_at(QCOW2_HEADER_SIZE as u64, &mut features)?;
+            let incompatible = u64::from_be_bytes(features);
+            // The image may be dirty or marked corrupt after a crash, which
+            // only affects refcounts; compressed clusters are refused anyway
+            let unknown =
+                incompatible & !(INCOMPAT_DIRTY | INCOMPAT_CORRUPT | INCOMPAT_COMPRESSION_TYPE);
+            if unknown != 0 {
+                return Err(unsupported(format!(
+                    "Unsupported qcow2 incompatible features 0x{:x}",
+                    unknown
+                )));
+            }
+
+            let mut length = [0u8; 4];
+            inner.read_at(QCOW3_OFFSET_HEADER_LENGTH, &mut length)?;
+            header_length = u32::from_be_bytes(length) as u64;
+            if header_length < QCOW3_HEADER_SIZE as u64 {
+                return Err(invalid(format!(
+                    "Invalid qcow2 header length {}",
+                    header_length
+                )));
+            }
+        }
+        let backing_format = read_backing_format(&inner, header_length, 1 << cluster_bits)?;
+
+        // Each L2 table fills one cluster and maps that many clusters
+        let l2_bits = cluster_bits - 3;
+        let l1_needed = size.div_ceil(1 << (cluster_bits + l2_bits));
+        if l1_size < l1_needed || l1_size * 8 > MAX_L1_SIZE {
+            return Err(invalid(format!("Invalid qcow2 L1 table size {}", l1_size)));
+        }
+        let mut table = vec![0u8; l1_size as usize * 8];
+        inner.read_at(l1_table_offset, &mut table)?;
+        let mut l1 = vec![0u64; l1_size as usize];
+        for (entry, bytes) in l1.iter_mut().zip(table.chunks_exact(8)) {
+            *entry = u64::from_be_bytes(bytes.try_into().unwrap());
+        }
+
+        let backing_file = match backing_file_offset {
+            0 => None,
+            _ if backing_file_size > MAX_BACKING_FILE_NAME => {
+                return Err(invalid("Invalid qcow2 backing file name"));
+            }
+            _ => {
+                let mut name = vec![0u8; backing_file_size as usize];
+                inner.read_at(backing_file_offset, &mut name)?;
+                Some(
+                    String::from_utf8(name)
+                        .map_err(|_| invalid("Invalid qcow2 backing file name"))?,
+                )
+            }
+        };
+
+        Ok(Self {
+            inner,
+            cluster_bits,
+            size,
+            l1,
+            l2_tables: Lock::new(LruCache::new(L2_CACHE_TABLES)),
+            backing_file,
+            backing_format,
+            backing: None,
+        })
+    }
+
+    /// Name of the backing file as stored in the image, usually relative
+    /// to the directory of the image
+    pub fn backing_file(&self) -> Option<&str> {
+        self.backing_file.as_deref()
+    }
+
+    /// Format of the backing file, such as `raw` or `qcow2`, if the image
+    /// declares it
+    ///
+    /// Without one the backing file's format has to be guessed from its
+    /// content, which a raw backing file can fake.
+    pub fn backing_format(&self) -> Option<&str> {
+        self.backing_format.as_deref()
+    }
+
+    /// Read unallocated clusters from `backing`
+    ///
+    /// Parts of the virtual disk beyond the end of the backing device read
+    /// as zeros.
+    ///
+    /// # Arguments
+    /// * `backing` - The opened backing file, in its own format
+    pub fn set_backing(&mut self, backing: Box<dyn BlockDevice + Send + Sync>) {
+        self.backing = Some(backing);
+    }
+
+    /// Consume the image and return the device holding the image file
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Look up a guest cluster in the L1 and L2 tables
+    fn cluster(&self, index: u64) -> io::Result<Cluster> {
+        let l2_bits = self.cluster_bits - 3;
+        let l1_entry = match self.l1.get((index >> l2_bits) as usize) {
+            Some(entry) => entry & ENTRY_OFFSET_MASK,
+            None => return Ok(Cluster::Unallocated),
+        };
+        if l1_entry == 0 {
+            return Ok(Cluster::Unallocated);
+        }
+
+        let entry = self.l2_table(l1_entry)?[(index & ((1 << l2_bits) - 1)) as usize];
+        if entry & L2_COMPRESSED != 0 {
+            return Err(unsupported("Compressed qcow2 clusters are not supported"));
+        }
+        Ok(match entry & ENTRY_OFFSET_MASK {
+            _ if entry & L2_ZERO != 0 => Cluster::Zero,
+            0 => Cluster::Unallocated,
+            offset => Cluster::Data(offset),
+        })
+    }
+
+    /// An L2 table, from the cache or read from the image
+    fn l2_table(&self, offset: u64) -> io::Result<Arc<[u64]>> {
+        if let Some(table) = lock(&self.l2_tables).get(&offset) {
+            return Ok(table.clone());
+        }
+        let mut bytes = vec![0u8; 1 << self.cluster_bits];
+        self.inner.read_at(offset, &mut bytes)?;
+        let table: Arc<[u64]> = bytes
+            .chunks_exact(8)
+            .map(|entry| u64::from_be_bytes(entry.try_into().unwrap()))
+            .collect();
+        lock(&self.l2_tables).insert(offset, table.clone());
+        Ok(table)
+    }
+
+    /// Read a range of the virtual disk that is not allocated in the image
+    fn read_backing(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let Some(backing) = &self.backing else {
+            if let Some(name) = &self.backing_file {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("qcow2 backing file '{}' is not open", name),
+                ));
+            }
+            buf.fill(0);
+            return Ok(());
+        };
+        let available = backing.size()?.saturating_sub(offset).min(buf.len() as u64);
+        let (stored, beyond) = buf.split_at_mut(available as usize);
+        backing.read_at(offset, stored)?;
+        beyond.fill(0);
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Qcow2Image<D> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let end = offset.checked_add(buf.len() as u64);
+        if end.is_none_or(|end| end > self.size) {
+            return Err(past_end());
+        }
+
+        let cluster_size = 1u64 << self.cluster_bits;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = pos & (cluster_size - 1);
+            let n = (cluster_size - within).min((buf.len() - done) as u64) as usize;
+            let out = &mut buf[done..done + n];
+            match self.cluster(pos >> self.cluster_bits)? {
+                Cluster::Data(host) => self.inner.read_at(host + within, out)?,
+                Cluster::Zero => out.fill(0),
+                Cluster::Unallocated => self.read_backing(pos, out)?,
+            }
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+}
+
+/// Walk the header extensions after the fixed header for the backing
+/// file format
+///
+/// # Arguments
+/// * `inner` - Device holding the image file
+/// * `offset` - End of the fixed header, where the extensions start
+/// * `cluster_size` - Size of the first cluster, which must hold them all
+///
+/// # Errors
+/// Returns `InvalidData` if an extension runs past the first cluster or the
+/// format name is not UTF-8
+fn read_backing_format<D: BlockDevice>(
+    inner: &D,
+    mut offset: u64,
+    cluster_size: u64,
+) -> io::Result<Option<String>> {
+    let overrun = || invalid("qcow2 header extensions run past the first cluster");
+    let mut format = None;
+    loop {
+        if offset + 8 > cluster_size {
+            return Err(overrun());
+        }
+        let mut header = [0u8; 8];
+        inner.read_at(offset, &mut header)?;
+        let mut cursor = Cursor::new(&header);
+        let kind = cursor.read_u32::<BigEndian>()?;
+        let len = cursor.read_u32::<BigEndian>()?;
+        if kind == EXT_END {
+            return Ok(format);
+        }
+
+        let data = offset + 8;
+        let end = data + len as u64;
+        if end > cluster_size {
+            return Err(overrun());
+        }
+        if kind == EXT_BACKING_FORMAT {
+            if len > MAX_BACKING_FORMAT_NAME {
+                return Err(invalid("Invalid qcow2 backing file format"));
+            }
+            let mut name = vec![0u8; len as usize];
+            inner.read_at(data, &mut name)?;
+            format = Some(
+                String::from_utf8(name)
+                    .map_err(|_| invalid("Invalid qcow2 backing file format"))?,
+            );
+        }
+        // Extension data is padded to 8 bytes
+        offset = end.next_multiple_of(8);
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn unsupported(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.into())
+}
+
+/// Write a version 3 qcow2 image of `disk` with 64 KiB clusters
+///
+/// Clusters for which `allocate` is false are left to the backing file,
+/// whose format is recorded in a header extension if given; allocated
+/// clusters of zeros are stored as zero clusters. There is no refcount
+/// table, which readers do not need.
+#[cfg(all(test, feature = "std"))]
+pub(crate) fn to_qcow2(
+    disk: &[u8],
+    backing_file: Option<&str>,
+    backing_format: Option<&str>,
+    allocate: impl Fn(u64) -> bool,
+) -> Vec<u8> {
+    const CLUSTER_BITS: u32 = 16;
+    const CLUSTER_SIZE: usize = 1 << CLUSTER_BITS;
+    let clusters = disk.len().div_ceil(CLUSTER_SIZE);
+    let l2_entries = CLUSTER_SIZE / 8;
+    let l1_size = clusters.div_ceil(l2_entries);
+
+    // Header, extensions and backing file name, L1 table, L2 tables, then
+    // data
+    let mut out = vec![0u8; (2 + l1_size) * CLUSTER_SIZE];
+    let mut header = Vec::new();
+    header.extend_from_slice(&QCOW_MAGIC.to_be_bytes());
+    header.extend_from_slice(&3u32.to_be_bytes());
+    let name = backing_file.unwrap_or("").as_bytes();
+    let name_offset = if name.is_empty() { 0 } else { 512 };
+    header.extend_from_slice(&(name_offset as u64).to_be_bytes());
+    header.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    header.extend_from_slice(&CLUSTER_BITS.to_be_bytes());
+    header.extend_from_slice(&(disk.len() as u64).to_be_bytes());
+    header.extend_from_slice(&0u32.to_be_bytes());
+    header.extend_from_slice(&(l1_size as u32).to_be_bytes());
+    header.extend_from_slice(&(CLUSTER_SIZE as u64).to_be_bytes());
+    header.resize(QCOW3_HEADER_SIZE - 8, 0);
+    header.extend_from_slice(&4u32.to_be_bytes());
+    header.extend_from_slice(&(QCOW3_HEADER_SIZE as u32).to_be_bytes());
+    if let Some(format) = backing_format {
+        header.extend_from_slice(&EXT_BACKING_FORMAT.to_be_bytes());
+        header.extend_from_slice(&(format.len() as u32).to_be_bytes());
+        header.extend_from_slice(format.as_bytes());
+        header.resize(header.len().next_multiple_of(8), 0);
+    }
+    // An end of extensions marker of zeros follows
+    out[..header.len()].copy_from_slice(&header);
+    out[name_offset..name_offset + name.len()].copy_from_slice(name);
+
+    for l1_index in 0..l1_size {
+        let l2_offset = (2 + l1_index) * CLUSTER_SIZE;
+        out[CLUSTER_SIZE + l1_index * 8..][..8].copy_from_slice(&(l2_offset as u64).to_be_bytes());
+    }
+    for index in 0..clusters {
+        if !allocate(index as u64) {
+            continue;
+        }
+        let data = &disk[index * CLUSTER_SIZE..disk.len().min((index + 1) * CLUSTER_SIZE)];
+        let entry = if data.iter().all(|&b| b == 0) {
+            L2_ZERO
+        } else {
+            let offset = out.len() as u64;
+            out.extend_from_slice(data);
+            out.resize(out.len().next_multiple_of(CLUSTER_SIZE), 0);
+            offset
+        };
+        out[(2 + index / l2_entries) * CLUSTER_SIZE + (index % l2_entries) * 8..][..8]
+            .copy_from_slice(&entry.to_be_bytes());
+    }
+    out
+}
+
+/// Kind of the error opening an image by path
+#[cfg(all(test, feature = "std"))]
+fn open_error(path: &std::path::Path, backing: crate::BackingFiles) -> Option<io::ErrorKind> {
+    crate::ImageFile::open_with(path, backing)
+        .err()
+        .map(|e| e.kind())
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_qcow2_image() {
+    // An MBR disk with ext4.img as its second primary partition
+    let image = std::fs::read("ext4.img").unwrap();
+    let mut disk = vec![0u8; 2048 * 512];
+    let mut entry = [0u8; 16];
+    entry[4] = 0x83;
+    entry[8..12].copy_from_slice(&2048u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&((image.len() / 512) as u32).to_le_bytes());
+    disk[0x1CE..0x1DE].copy_from_slice(&entry);
+    disk[0x1FE..0x200].copy_from_slice(&[0x55, 0xAA]);
+    disk.extend_from_slice(&image);
+
+    let qcow2 = to_qcow2(&disk, None, None, |_| true);
+    let device = Qcow2Image::new(qcow2.as_slice()).unwrap();
+    assert_eq!(device.size().unwrap(), disk.len() as u64);
+    for (offset, len) in [(0, 4096), (1_000_000, 300_000), (disk.len() - 5000, 5000)] {
+        let mut buf = vec![0u8; len];
+        device.read_at(offset as u64, &mut buf).unwrap();
+        assert_eq!(buf, disk[offset..offset + len]);
+    }
+    let mut buf = [0u8; 2];
+    assert!(device.read_at(disk.len() as u64 - 1, &mut buf).is_err());
+
+    // An overlay holding every other cluster, on top of the raw disk
+    let base = crate::TempPath::new("qcow2_base.img");
+    let base_name = base.file_name().unwrap().to_str().unwrap();
+    let overlay = to_qcow2(&disk, Some(base_name), Some("raw"), |i| i % 2 == 0);
+    let mut device = Qcow2Image::new(overlay.as_slice()).unwrap();
+    assert_eq!(device.backing_file(), Some(base_name));
+    assert_eq!(device.backing_format(), Some("raw"));
+    let mut buf = vec![0u8; 200_000];
+    assert_eq!(
+        device.read_at(1_000_000, &mut buf).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+    device.set_backing(Box::new(disk.clone()));
+    device.read_at(1_000_000, &mut buf).unwrap();
+    assert_eq!(buf, disk[1_000_000..1_200_000]);
+
+    // Opened by path, the backing file is found next to the overlay
+    std::fs::write(&base, &disk).unwrap();
+    let path = crate::TempPath::new("qcow2.img");
+    std::fs::write(&path, &overlay).unwrap();
+    let path = path.to_str().unwrap();
+    let partitions = crate::read_partitions(&crate::ImageFile::open(path).unwrap()).unwrap();
+    assert_eq!(partitions.len(), 1);
+    assert_eq!(partitions[0].index, 2);
+    for fs in [
+        crate::FileSystem::open(path).unwrap(),
+        crate::FileSystem::open(&format!("{}#p2", path)).unwrap(),
+    ] {
+        assert_eq!(
+            fs.read_file("/home/zyu379/test_file.txt").unwrap(),
+            b"hello from ext4 test\n"
+        );
+    }
+
+    // Untrusted images can be opened without their backing files
+    let image = crate::ImageFile::open_with(path, crate::BackingFiles::Ignore).unwrap();
+    assert_eq!(
+        image.read_at(1_000_000, &mut buf).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_qcow2_rejected() {
+    use crate::BackingFiles::{Follow, Ignore};
+
+    let disk = vec![0xA5u8; 3 << 16];
+    let kind = |image: &[u8]| Qcow2Image::new(image).err().map(|e| e.kind());
+    let qcow2 = to_qcow2(&disk, None, None, |_| true);
+    assert_eq!(kind(&qcow2), None);
+
+    // Compressed clusters are found when read
+    let mut compressed = qcow2.clone();
+    let l2_entry = 2 << 16;
+    let entry = u64::from_be_bytes(compressed[l2_entry..l2_entry + 8].try_into().unwrap());
+    compressed[l2_entry..l2_entry + 8].copy_from_slice(&(entry | L2_COMPRESSED).to_be_bytes());
+    let device = Qcow2Image::new(compressed.as_slice()).unwrap();
+    let mut buf = [0u8; 512];
+    assert_eq!(
+        device.read_at(0, &mut buf).unwrap_err().kind(),
+        io::ErrorKind::Unsupported
+    );
+    device.read_at(1 << 16, &mut buf).unwrap();
+
+    // Encrypted images and unknown incompatible features
+    let mut encrypted = qcow2.clone();
+    encrypted[32..36].copy_from_slice(&1u32.to_be_bytes());
+    assert_eq!(kind(&encrypted), Some(io::ErrorKind::Unsupported));
+    let mut incompatible = qcow2.clone();
+    incompatible[72..80].copy_from_slice(&(1u64 << 4).to_be_bytes());
+    assert_eq!(kind(&incompatible), Some(io::ErrorKind::Unsupported));
+
+    // Header extensions must stay within the first cluster
+    let mut overrun = to_qcow2(&disk, None, Some("raw"), |_| true);
+    overrun[108..112].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert_eq!(kind(&overrun), Some(io::ErrorKind::InvalidData));
+
+    // An image that is its own backing file
+    let path = crate::TempPath::new("qcow2_loop.img");
+    let name = path.file_name().unwrap().to_str().unwrap();
+    std::fs::write(&path, to_qcow2(&disk, Some(name), None, |_| false)).unwrap();
+    assert_eq!(open_error(&path, Follow), Some(io::ErrorKind::InvalidData));
+    assert_eq!(open_error(&path, Ignore), None);
+
+    // Backing files are opened as the declared format
+    let base = crate::TempPath::new("qcow2_raw_base.img");
+    std::fs::write(&base, &disk).unwrap();
+    let base_name = base.file_name().unwrap().to_str().unwrap();
+    for (format, error) in [
+        ("raw", None),
+        ("qcow2", Some(io::ErrorKind::InvalidData)),
+        ("vmdk", Some(io::ErrorKind::Unsupported)),
+    ] {
+        std::fs::write(
+            &path,
+            to_qcow2(&disk, Some(base_name), Some(format), |_| false),
+        )
+        .unwrap();
+        assert_eq!(open_error(&path, Follow), error, "{}", format);
+    }
+
+    // A raw backing file is not probed, even if it looks like qcow2
+    std::fs::write(&base, &qcow2).unwrap();
+    std::fs::write(
+        &path,
+        to_qcow2(&qcow2, Some(base_name), Some("raw"), |_| false),
+    )
+    .unwrap();
+    let image = crate::ImageFile::open(&*path).unwrap();
+    let mut buf = vec![0u8; qcow2.len()];
+    image.read_at(0, &mut buf).unwrap();
+    assert_eq!(buf, qcow2);
+}
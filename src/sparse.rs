@@ -0,0 +1,359 @@
+use crate::bytes::Cursor;
+use crate::checksum::crc32;
+use crate::device::{BlockDevice, past_end};
+use crate::io;
+
+use alloc::format;
+use alloc::vec::Vec;
+use byteorder::LittleEndian;
+
+/// `magic` of an Android sparse image header
+const SPARSE_HEADER_MAGIC: u32 = 0xED26_FF3A;
+
+/// Major format version this reader understands
+const SPARSE_MAJOR_VERSION: u16 = 1;
+
+/// Size of the file header in version 1.0; newer minor versions may be larger
+const SPARSE_HEADER_SIZE: u16 = 28;
+
+/// Size of a chunk header in version 1.0
+const CHUNK_HEADER_SIZE: u16 = 12;
+
+/// Bytes of the expanded image checksummed per read by
+/// [`SparseImage::verify_crc32`]
+const VERIFY_READ_SIZE: u64 = 1024 * 1024;
+
+// Chunk types
+const CHUNK_TYPE_RAW: u16 = 0xCAC1;
+const CHUNK_TYPE_FILL: u16 = 0xCAC2;
+const CHUNK_TYPE_DONT_CARE: u16 = 0xCAC3;
+const CHUNK_TYPE_CRC32: u16 = 0xCAC4;
+
+/// Where the content of a chunk comes from
+#[derive(Debug, Clone, Copy)]
+enum ChunkData {
+    /// Stored verbatim at this byte offset of the sparse file
+    Raw(u64),
+    /// Every 4 bytes hold this value, little-endian
+    Fill(u32),
+    /// Not stored; reads as zeros
+    DontCare,
+}
+
+/// A run of output blocks described by one chunk
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    /// First output block covered
+    first_block: u64,
+    /// Number of output blocks covered
+    blocks: u64,
+    data: ChunkData,
+}
+
+/// An Android sparse image (`simg`), read as the raw image it expands to
+///
+/// The chunk table is read once when the image is opened; reads then map
+/// to the stored data of RAW chunks, or are synthesised for FILL and
+/// DONT_CARE chunks, without expanding the image anywhere. The checksums
+/// of CRC32 chunks cover everything before them, so they are only checked
+/// on request by [`verify_crc32`](Self::verify_crc32).
+pub struct SparseImage<D> {
+    inner: D,
+    block_size: u64,
+    /// Size of the expanded image in bytes
+    size: u64,
+    /// Chunks in output order, without gaps
+    chunks: Vec<Chunk>,
+    /// CRC32 chunks: the output block they follow and the checksum of
+    /// all output blocks before it
+    checksums: Vec<(u64, u32)>,
+}
+
+impl<D: BlockDevice> SparseImage<D> {
+    /// Whether a device starts with a sparse image header
+    ///
+    /// # Errors
+    /// Returns error if reading the device fails for another reason than
+    /// it being too short
+    pub fn detect(device: &D) -> io::Result<bool> {
+        let mut magic = [0u8; 4];
+        match device.read_at(0, &mut magic) {
+            Ok(()) => Ok(u32::from_le_bytes(magic) == SPARSE_HEADER_MAGIC),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the header and chunk table of a sparse image
+    ///
+    /// # Arguments
+    /// * `inner` - Device holding the sparse file
+    ///
+    /// # Errors
+    /// Returns `InvalidData` if the header is not a version 1 sparse image
+    /// header or the chunk table is inconsistent
+    pub fn new(inner: D) -> io::Result<Self> {
+        let mut header = [0u8; SPARSE_HEADER_SIZE as usize];
+        inner.read_at(0, &mut header)?;
+        let mut cursor = Cursor::new(&header);
+        let magic = cursor.read_u32::<LittleEndian>()?;
+        let major_version = cursor.read_u16::<LittleEndian>()?;
+        let _minor_version = cursor.read_u16::<LittleEndian>()?;
+        let header_size = cursor.read_u16::<LittleEndian>()?;
+        let chunk_header_size = cursor.read_u16::<LittleEndian>()?;
+        let block_size = cursor.read_u32::<LittleEndian>()? as u64;
+        let total_blocks = cursor.read_u32::<LittleEndian>()? as u64;
+        let total_chunks = cursor.read_u32::<LittleEndian>()?;
+
+        if magic != SPARSE_HEADER_MAGIC || major_version != SPARSE_MAJOR_VERSION {
+            return Err(invalid("Not a version 1 Android sparse image"));
+        }
+        if header_size < SPARSE_HEADER_SIZE
+            || chunk_header_size < CHUNK_HEADER_SIZE
+            || block_size == 0
+            || !block_size.is_multiple_of(4)
+        {
+            return Err(invalid("Invalid sparse image header"));
+        }
+
+        let file_size = inner.size()?;
+        let mut chunks = Vec::new();
+        let mut checksums = Vec::new();
+        let mut offset = header_size as u64;
+        let mut block = 0;
+        for index in 0..total_chunks {
+            let mut chunk_header = [0u8; CHUNK_HEADER_SIZE as usize];
+            inner.read_at(offset, &mut chunk_header)?;
+            let mut cursor = Cursor::new(&chunk_header);
+            let chunk_type = cursor.read_u16::<LittleEndian>()?;
+            let _reserved = cursor.read_u16::<LittleEndian>()?;
+            let blocks = cursor.read_u32::<LittleEndian>()? as u64;
+            let total_size = cursor.read_u32::<LittleEndian>()? as u64;
+
+            let data_offset = offset + chunk_header_size as u64;
+            let (data, data_size) = match chunk_type {
+                CHUNK_TYPE_RAW => (ChunkData::Raw(data_offset), blocks * block_size),
+                CHUNK_TYPE_FILL => {
+                    let mut value = [0u8; 4];
+                    inner.read_at(data_offset, &mut value)?;
+                    (ChunkData::Fill(u32::from_le_bytes(value)), 4)
+                }
+                CHUNK_TYPE_DONT_CARE => (ChunkData::DontCare, 0),
+                CHUNK_TYPE_CRC32 => {
+                    let mut value = [0u8; 4];
+                    inner.read_at(data_offset, &mut value)?;
+                    checksums.push((block, u32::from_le_bytes(value)));
+                    (ChunkData::DontCare, 4)
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "Unknown sparse chunk type 0x{:04x} in chunk {}",
+                        chunk_type, index
+                    )));
+                }
+            };
+            if total_size != chunk_header_size as u64 + data_size {
+                return Err(invalid(format!(
+                    "Sparse chunk {} has size {}, expected {}",
+                    index,
+                    total_size,
+                    chunk_header_size as u64 + data_size
+                )));
+            }
+            offset += total_size;
+            if offset > file_size {
+                return Err(invalid(format!("Sparse chunk {} is truncated", index)));
+            }
+
+            // CRC32 chunks cover no blocks of their own
+            if chunk_type != CHUNK_TYPE_CRC32 && blocks > 0 {
+                chunks.push(Chunk {
+                    first_block: block,
+                    blocks,
+                    data,
+                });
+                block += blocks;
+            }
+        }
+
+        if block != total_blocks {
+            return Err(invalid(format!(
+                "Sparse chunks cover {} blocks, header says {}",
+                block, total_blocks
+            )));
+        }
+        Ok(Self {
+            inner,
+            block_size,
+            size: total_blocks * block_size,
+            chunks,
+            checksums,
+        })
+    }
+
+    /// Check the image against the checksums of its CRC32 chunks
+    ///
+    /// Each checksum covers the whole expanded image up to its chunk, so
+    /// this reads everything before the last one.
+    ///
+    /// # Errors
+    /// Returns `InvalidData` naming the first checksum that does not match,
+    /// or the error of a failed read
+    pub fn verify_crc32(&self) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let (mut crc, mut pos) = (0, 0);
+        for &(block, expected) in &self.checksums {
+            let end = block * self.block_size;
+            while pos < end {
+                let len = (end - pos).min(VERIFY_READ_SIZE) as usize;
+                buf.resize(len, 0);
+                self.read_at(pos, &mut buf)?;
+                crc = crc32(crc, &buf);
+                pos += len as u64;
+            }
+            if crc != expected {
+                return Err(invalid(format!(
+                    "Sparse image CRC32 mismatch after block {} (stored 0x{:08x}, computed 0x{:08x})",
+                    block, expected, crc
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Consume the image and return the device holding the sparse file
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for SparseImage<D> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let end = offset.checked_add(buf.len() as u64);
+        if end.is_none_or(|end| end > self.size) {
+            return Err(past_end());
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let block = pos / self.block_size;
+            let index = self
+                .chunks
+                .partition_point(|chunk| chunk.first_block + chunk.blocks <= block);
+            let chunk = self.chunks[index];
+
+            // Copy up to the end of the chunk
+            let within = pos - chunk.first_block * self.block_size;
+            let available = chunk.blocks * self.block_size - within;
+            let n = available.min((buf.len() - done) as u64) as usize;
+            let out = &mut buf[done..done + n];
+            match chunk.data {
+                ChunkData::Raw(at) => self.inner.read_at(at + within, out)?,
+                ChunkData::Fill(value) => {
+                    let pattern = value.to_le_bytes();
+                    for (i, byte) in out.iter_mut().enumerate() {
+                        *byte = pattern[(within as usize + i) % 4];
+                    }
+                }
+                ChunkData::DontCare => out.fill(0),
+            }
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+}
+
+fn invalid(message: impl Into<alloc::string::String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Convert a raw image to the sparse format, as `img2simg` would
+#[cfg(all(test, feature = "std"))]
+fn to_sparse(image: &[u8], block_size: usize) -> Vec<u8> {
+    let mut chunks: Vec<(u16, u32, Vec<u8>)> = Vec::new();
+    for block in image.chunks(block_size) {
+        let fill = block.chunks(4).all(|word| word == &block[..4]);
+        let (kind, data) = match fill {
+            true if block.iter().all(|&b| b == 0) => (CHUNK_TYPE_DONT_CARE, Vec::new()),
+            true => (CHUNK_TYPE_FILL, block[..4].to_vec()),
+            false => (CHUNK_TYPE_RAW, block.to_vec()),
+        };
+        match chunks.last_mut() {
+            Some(last) if last.0 == kind && (kind == CHUNK_TYPE_RAW || last.2 == data) => {
+                last.1 += 1;
+                if kind == CHUNK_TYPE_RAW {
+                    last.2.extend_from_slice(&data);
+                }
+            }
+            _ => chunks.push((kind, 1, data)),
+        }
+    }
+    // A trailing checksum chunk over the whole image
+    let checksum = crc32(0, image).to_le_bytes().to_vec();
+    chunks.push((CHUNK_TYPE_CRC32, 0, checksum));
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&SPARSE_HEADER_MAGIC.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&SPARSE_HEADER_SIZE.to_le_bytes());
+    out.extend_from_slice(&CHUNK_HEADER_SIZE.to_le_bytes());
+    out.extend_from_slice(&(block_size as u32).to_le_bytes());
+    out.extend_from_slice(&((image.len() / block_size) as u32).to_le_bytes());
+    out.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    for (kind, blocks, data) in chunks {
+        out.extend_from_slice(&kind.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&blocks.to_le_bytes());
+        out.extend_from_slice(&(CHUNK_HEADER_SIZE as u32 + data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&data);
+    }
+    out
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_sparse_image() {
+    let image = std::fs::read("ext4.img").unwrap();
+    let sparse = to_sparse(&image, 4096);
+    assert!(sparse.len() < image.len());
+
+    let device = SparseImage::new(sparse.as_slice()).unwrap();
+    assert_eq!(device.size().unwrap(), image.len() as u64);
+    // Reads spanning chunks of every kind match the raw image
+    for (offset, len) in [(0, 8192), (1024, 100_000), (image.len() - 5000, 5000)] {
+        let mut buf = alloc::vec![0u8; len];
+        device.read_at(offset as u64, &mut buf).unwrap();
+        assert_eq!(buf, image[offset..offset + len]);
+    }
+    let mut buf = [0u8; 2];
+    assert!(device.read_at(image.len() as u64 - 1, &mut buf).is_err());
+
+    // `FileSystem::open` recognises sparse image files
+    let path = crate::TempPath::new("sparse.img");
+    std::fs::write(&path, &sparse).unwrap();
+    let fs = crate::FileSystem::open(path.to_str().unwrap()).unwrap();
+    assert_eq!(
+        fs.read_file("/home/zyu379/test_file.txt").unwrap(),
+        b"hello from ext4 test\n"
+    );
+
+    // Corrupt data still reads, but fails the checksum when asked
+    device.verify_crc32().unwrap();
+    let mut corrupt = sparse.clone();
+    let raw = corrupt.len() / 2;
+    corrupt[raw] ^= 1;
+    let device = SparseImage::new(corrupt.as_slice()).unwrap();
+    let err = device.verify_crc32().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let mut truncated = sparse.clone();
+    truncated.truncate(sparse.len() / 2);
+    assert!(SparseImage::new(truncated.as_slice()).is_err());
+}
@@ -1,14 +1,58 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::{Cursor, Read};
+use crate::bytes::Cursor;
+use crate::checksum::crc32c;
+use crate::group::{EXT4_MIN_DESC_SIZE, EXT4_MIN_DESC_SIZE_64BIT};
+use crate::inode::EXT4_GOOD_OLD_INODE_SIZE;
+use crate::io;
+
+use alloc::string::String;
+use alloc::string::ToString;
+use byteorder::LittleEndian;
+
+/// Byte offset of the primary superblock from the start of the filesystem
+pub(crate) const SUPERBLOCK_OFFSET: u64 = 1024;
+
+/// Size of the superblock
+pub(crate) const SUPERBLOCK_SIZE: u32 = 1024;
+
+/// Largest `s_log_block_size`, for 64 KiB blocks
+const MAX_LOG_BLOCK_SIZE: u32 = 6;
+
+/// `s_magic` of ext2, ext3 and ext4
+const EXT4_SUPER_MAGIC: u16 = 0xEF53;
 
 /// Fixed offsets for superblock fields
 const SUPERBLOCK_OFFSET_INODES_COUNT: u64 = 0x00; // Total inodes count
 const SUPERBLOCK_OFFSET_BLOCKS_COUNT: u64 = 0x04; // Total blocks count
+const SUPERBLOCK_OFFSET_FIRST_DATA_BLOCK: u64 = 0x14; // Block containing the superblock
 const SUPERBLOCK_OFFSET_LOG_BLOCK_SIZE: u64 = 0x18; // Log2 of block size
+const SUPERBLOCK_OFFSET_BLOCKS_PER_GROUP: u64 = 0x20; // Number of blocks per block group
 const SUPERBLOCK_OFFSET_INODES_PER_GROUP: u64 = 0x28; // Number of inodes per block group
+const SUPERBLOCK_OFFSET_MAGIC: usize = 0x38; // Magic signature
 const SUPERBLOCK_OFFSET_INODE_SIZE: u64 = 0x58; // Size of inode structure
+const SUPERBLOCK_OFFSET_FEATURE_INCOMPAT: u64 = 0x60; // Incompatible feature set
+const SUPERBLOCK_OFFSET_FEATURE_RO_COMPAT: u64 = 0x64; // Read-only compatible feature set
+const SUPERBLOCK_OFFSET_UUID: u64 = 0x68; // 128-bit filesystem UUID
 const SUPERBLOCK_OFFSET_VOLUME_NAME: u64 = 0x78; // Volume name/label
 const SUPERBLOCK_VOLUME_NAME_LENGTH: usize = 16; // Maximum volume name length
+const SUPERBLOCK_OFFSET_HASH_SEED: u64 = 0xEC; // HTree hash seed (4 x u32)
+const SUPERBLOCK_OFFSET_DESC_SIZE: u64 = 0xFE; // Group descriptor size with 64bit
+const SUPERBLOCK_OFFSET_BLOCKS_COUNT_HI: u64 = 0x150; // High 32 bits of blocks count
+const SUPERBLOCK_OFFSET_FLAGS: u64 = 0x160; // Miscellaneous flags
+const SUPERBLOCK_OFFSET_CHECKSUM_SEED: u64 = 0x270; // Precomputed metadata checksum seed
+const SUPERBLOCK_OFFSET_ENCODING: u64 = 0x27C; // Filename charset encoding
+
+/// `s_flags`: directory hashes treat name bytes as unsigned chars
+const EXT2_FLAGS_UNSIGNED_HASH: u32 = 0x0002;
+
+/// Incompatible feature flags
+const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x0080;
+const EXT4_FEATURE_INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const EXT4_FEATURE_INCOMPAT_LARGEDIR: u32 = 0x4000;
+const EXT4_FEATURE_INCOMPAT_CASEFOLD: u32 = 0x20000;
+
+/// Read-only compatible feature flags
+const EXT4_FEATURE_RO_COMPAT_HUGE_FILE: u32 = 0x0008;
+const EXT4_FEATURE_RO_COMPAT_METADATA_CSUM: u32 = 0x0400;
 
 /// Represents the ext4 superblock structure
 ///
@@ -21,7 +65,12 @@ pub(crate) struct Superblock {
     pub(crate) inodes_count: u32,
 
     /// Total number of blocks in the filesystem
-    pub(crate) blocks_count: u32,
+    ///
+    /// Includes `s_blocks_count_hi` when the `64bit` feature is enabled
+    pub(crate) blocks_count: u64,
+
+    /// First block of group 0: 1 for 1KiB blocks, otherwise 0
+    pub(crate) first_data_block: u32,
 
     /// Log base 2 of the block size
     ///
@@ -31,19 +80,58 @@ pub(crate) struct Superblock {
     /// - log_block_size = 2 → 4096 bytes
     pub(crate) log_block_size: u32,
 
+    /// Number of blocks per group
+    pub(crate) blocks_per_group: u32,
+
     /// Number of inodes per group
     pub(crate) inodes_per_group: u32,
 
     /// Size of each inode structure in bytes
     pub(crate) inode_size: u16,
 
+    /// Incompatible feature flags (`s_feature_incompat`)
+    pub(crate) feature_incompat: u32,
+
+    /// Read-only compatible feature flags (`s_feature_ro_compat`)
+    pub(crate) feature_ro_compat: u32,
+
+    /// Filesystem UUID
+    pub(crate) uuid: [u8; 16],
+
     /// Volume name/label (up to 16 characters)
     ///
     /// Human-readable name for the filesystem, null-terminated
     pub(crate) volume_name: String,
+
+    /// Size of each group descriptor in bytes
+    ///
+    /// 32 unless the `64bit` feature is enabled, in which case it is
+    /// `s_desc_size` (at least 64)
+    pub(crate) desc_size: u16,
+
+    /// Seed for the htree directory hash
+    pub(crate) hash_seed: [u32; 4],
+
+    /// Miscellaneous flags (`s_flags`)
+    pub(crate) flags: u32,
+
+    /// Checksum seed stored on disk when `csum_seed` is enabled
+    pub(crate) checksum_seed: u32,
+
+    /// Filename encoding of casefolded directories (`s_encoding`)
+    pub(crate) encoding: u16,
 }
 
 impl Superblock {
+    /// Whether a buffer holds an ext2/3/4 superblock, judged by its magic
+    ///
+    /// # Arguments
+    /// * `buf` - Bytes read from the superblock location
+    pub(crate) fn has_magic(buf: &[u8]) -> bool {
+        buf.get(SUPERBLOCK_OFFSET_MAGIC..SUPERBLOCK_OFFSET_MAGIC + 2)
+            .is_some_and(|magic| magic == EXT4_SUPER_MAGIC.to_le_bytes())
+    }
+
     /// Parse a superblock from a byte buffer
     ///
     /// # Arguments
@@ -67,16 +155,28 @@ impl Superblock {
 
         // Read total blocks count (4 bytes at offset 0x04)
         reader.set_position(SUPERBLOCK_OFFSET_BLOCKS_COUNT);
-        let blocks_count = reader
+        let blocks_count_lo = reader
             .read_u32::<LittleEndian>()
             .expect("Failed to read blocks count");
 
+        // Read first data block (4 bytes at offset 0x14)
+        reader.set_position(SUPERBLOCK_OFFSET_FIRST_DATA_BLOCK);
+        let first_data_block = reader
+            .read_u32::<LittleEndian>()
+            .expect("Failed to read first data block");
+
         // Read log block size (4 bytes at offset 0x18)
         reader.set_position(SUPERBLOCK_OFFSET_LOG_BLOCK_SIZE);
         let log_block_size = reader
             .read_u32::<LittleEndian>()
             .expect("Failed to read log block size");
 
+        // Read blocks per group (4 bytes at offset 0x20)
+        reader.set_position(SUPERBLOCK_OFFSET_BLOCKS_PER_GROUP);
+        let blocks_per_group = reader
+            .read_u32::<LittleEndian>()
+            .expect("Failed to read blocks per group");
+
         // Read inodes per group (4 bytes at offset 0x28)
         reader.set_position(SUPERBLOCK_OFFSET_INODES_PER_GROUP);
         let inodes_per_group = reader
@@ -89,6 +189,23 @@ impl Superblock {
             .read_u16::<LittleEndian>()
             .expect("Failed to read inode size");
 
+        // Read incompatible features (4 bytes at offset 0x60)
+        reader.set_position(SUPERBLOCK_OFFSET_FEATURE_INCOMPAT);
+        let feature_incompat = reader
+            .read_u32::<LittleEndian>()
+            .expect("Failed to read incompat features");
+
+        // Read read-only compatible features (4 bytes at offset 0x64)
+        reader.set_position(SUPERBLOCK_OFFSET_FEATURE_RO_COMPAT);
+        let feature_ro_compat = reader
+            .read_u32::<LittleEndian>()
+            .expect("Failed to read ro_compat features");
+
+        // Read filesystem UUID (16 bytes at offset 0x68)
+        reader.set_position(SUPERBLOCK_OFFSET_UUID);
+        let mut uuid = [0u8; 16];
+        reader.read_exact(&mut uuid).expect("Failed to read UUID");
+
         // Read volume name (16 bytes at offset 0x78)
         reader.set_position(SUPERBLOCK_OFFSET_VOLUME_NAME);
         let mut name_buffer = [0u8; SUPERBLOCK_VOLUME_NAME_LENGTH];
@@ -101,16 +218,99 @@ impl Superblock {
             .trim_end_matches('\0')
             .to_string();
 
+        // Read high half of the blocks count (4 bytes at offset 0x150),
+        // only meaningful with the 64bit feature
+        reader.set_position(SUPERBLOCK_OFFSET_BLOCKS_COUNT_HI);
+        let blocks_count_hi = reader
+            .read_u32::<LittleEndian>()
+            .expect("Failed to read blocks count high");
+        let blocks_count = if feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            ((blocks_count_hi as u64) << 32) | blocks_count_lo as u64
+        } else {
+            blocks_count_lo as u64
+        };
+
+        // Read group descriptor size (2 bytes at offset 0xFE)
+        reader.set_position(SUPERBLOCK_OFFSET_DESC_SIZE);
+        let s_desc_size = reader
+            .read_u16::<LittleEndian>()
+            .expect("Failed to read descriptor size") as usize;
+        let desc_size = if feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            s_desc_size.max(EXT4_MIN_DESC_SIZE_64BIT)
+        } else {
+            EXT4_MIN_DESC_SIZE
+        } as u16;
+
+        // Read htree hash seed (16 bytes at offset 0xEC)
+        reader.set_position(SUPERBLOCK_OFFSET_HASH_SEED);
+        let mut hash_seed = [0u32; 4];
+        reader
+            .read_u32_into::<LittleEndian>(&mut hash_seed)
+            .expect("Failed to read hash seed");
+
+        // Read flags (4 bytes at offset 0x160)
+        reader.set_position(SUPERBLOCK_OFFSET_FLAGS);
+        let flags = reader
+            .read_u32::<LittleEndian>()
+            .expect("Failed to read flags");
+
+        // Read checksum seed (4 bytes at offset 0x270)
+        reader.set_position(SUPERBLOCK_OFFSET_CHECKSUM_SEED);
+        let checksum_seed = reader
+            .read_u32::<LittleEndian>()
+            .expect("Failed to read checksum seed");
+
+        // Read filename encoding (2 bytes at offset 0x27C)
+        reader.set_position(SUPERBLOCK_OFFSET_ENCODING);
+        let encoding = reader
+            .read_u16::<LittleEndian>()
+            .expect("Failed to read encoding");
+
         Self {
             inodes_count,
             blocks_count,
+            first_data_block,
             log_block_size,
+            blocks_per_group,
             inodes_per_group,
             inode_size,
+            feature_incompat,
+            feature_ro_compat,
+            uuid,
             volume_name,
+            desc_size,
+            hash_seed,
+            flags,
+            checksum_seed,
+            encoding,
         }
     }
 
+    /// Check the geometry fields that block and inode locations are
+    /// computed from
+    ///
+    /// # Errors
+    /// Returns `InvalidData` if the block size is over 64 KiB, a group
+    /// holds no blocks or inodes, or the inode size is not a power of two
+    /// between 128 bytes and the block size
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        if self.log_block_size > MAX_LOG_BLOCK_SIZE {
+            return Err(invalid("Superblock block size is too large"));
+        }
+        if self.blocks_per_group == 0 || self.inodes_per_group == 0 {
+            return Err(invalid("Superblock has empty block groups"));
+        }
+        let inode_size = self.inode_size as u32;
+        if !inode_size.is_power_of_two()
+            || inode_size < EXT4_GOOD_OLD_INODE_SIZE as u32
+            || inode_size > self.block_size()
+        {
+            return Err(invalid("Superblock inode size is invalid"));
+        }
+        Ok(())
+    }
+
     /// Calculate the actual block size in bytes
     ///
     /// # Returns
@@ -118,10 +318,67 @@ impl Superblock {
     pub(crate) fn block_size(&self) -> u32 {
         1024 << self.log_block_size
     }
+
+    /// Whether a block number lies inside the filesystem's data area
+    ///
+    /// Valid blocks run from `s_first_data_block`, where group 0 starts, up
+    /// to but excluding `s_blocks_count`.
+    pub(crate) fn contains_block(&self, block: u64) -> bool {
+        block >= self.first_data_block as u64 && block < self.blocks_count
+    }
+
+    /// Number of block groups
+    pub(crate) fn group_count(&self) -> u32 {
+        let blocks = self
+            .blocks_count
+            .saturating_sub(self.first_data_block as u64);
+        blocks.div_ceil(self.blocks_per_group.max(1) as u64) as u32
+    }
+
+    /// Number of blocks taken by each group's inode table
+    pub(crate) fn inode_table_blocks(&self) -> u64 {
+        (self.inodes_per_group as u64 * self.inode_size as u64).div_ceil(self.block_size() as u64)
+    }
+
+    /// Whether `i_blocks` may use its high 16 bits and block units
+    pub(crate) fn has_huge_file(&self) -> bool {
+        self.feature_ro_compat & EXT4_FEATURE_RO_COMPAT_HUGE_FILE != 0
+    }
+
+    /// Whether directory hashes use the unsigned-char variants
+    pub(crate) fn has_unsigned_hash(&self) -> bool {
+        self.flags & EXT2_FLAGS_UNSIGNED_HASH != 0
+    }
+
+    /// Whether metadata blocks and inodes carry crc32c checksums
+    pub(crate) fn has_metadata_csum(&self) -> bool {
+        self.feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0
+    }
+
+    /// Whether htree directories may have a third index level
+    pub(crate) fn has_largedir(&self) -> bool {
+        self.feature_incompat & EXT4_FEATURE_INCOMPAT_LARGEDIR != 0
+    }
+
+    /// Whether directories may be marked case-insensitive
+    pub(crate) fn has_casefold(&self) -> bool {
+        self.feature_incompat & EXT4_FEATURE_INCOMPAT_CASEFOLD != 0
+    }
+
+    /// Seed for all metadata checksums
+    ///
+    /// Either stored explicitly (`csum_seed` feature) or derived from the UUID.
+    pub(crate) fn csum_seed(&self) -> u32 {
+        if self.feature_incompat & EXT4_FEATURE_INCOMPAT_CSUM_SEED != 0 {
+            self.checksum_seed
+        } else {
+            crc32c(!0, &self.uuid)
+        }
+    }
 }
 
-impl std::fmt::Display for Superblock {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for Superblock {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
             "EXT4 Filesystem '{}': {} inodes ({} per group), {} blocks ({} bytes each), inode size: {} bytes",
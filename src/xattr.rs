@@ -0,0 +1,200 @@
+use crate::bytes::Cursor;
+use crate::inode::EXT4_GOOD_OLD_INODE_SIZE;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use byteorder::LittleEndian;
+
+/// Magic number at the start of the in-inode xattr area and external xattr blocks
+const EXT4_XATTR_MAGIC: u32 = 0xEA020000;
+
+/// Size of the fixed part of an xattr entry (name follows, padded to 4 bytes)
+const XATTR_ENTRY_HEADER_SIZE: usize = 16;
+
+/// Size of the header of an external xattr block; entries follow it
+const XATTR_BLOCK_HEADER_SIZE: usize = 32;
+
+/// Offset of `i_extra_isize` within the inode
+const INODE_OFFSET_EXTRA_ISIZE: usize = 0x80;
+
+// Attribute name indexes and the prefixes they stand for
+const EXT4_XATTR_INDEX_USER: u8 = 1;
+const EXT4_XATTR_INDEX_POSIX_ACL_ACCESS: u8 = 2;
+const EXT4_XATTR_INDEX_POSIX_ACL_DEFAULT: u8 = 3;
+const EXT4_XATTR_INDEX_TRUSTED: u8 = 4;
+const EXT4_XATTR_INDEX_SECURITY: u8 = 6;
+/// Name index for the `system.` namespace
+pub(crate) const EXT4_XATTR_INDEX_SYSTEM: u8 = 7;
+const EXT4_XATTR_INDEX_RICHACL: u8 = 8;
+
+/// A single on-disk extended attribute entry
+#[derive(Debug, Clone)]
+pub(crate) struct XattrEntry {
+    /// Namespace index (user, trusted, security, system, ...)
+    pub(crate) name_index: u8,
+    /// Attribute name without its namespace prefix
+    pub(crate) name: Vec<u8>,
+    /// Attribute value; empty when stored in a separate inode
+    pub(crate) value: Vec<u8>,
+    /// Inode holding the value (`ea_inode` feature), or 0 if stored inline
+    pub(crate) value_inum: u32,
+    /// Size of the value in bytes
+    pub(crate) value_size: u32,
+}
+
+impl XattrEntry {
+    /// Full attribute name including its namespace prefix
+    pub(crate) fn full_name(&self) -> String {
+        let prefix = match self.name_index {
+            EXT4_XATTR_INDEX_USER => "user.",
+            EXT4_XATTR_INDEX_POSIX_ACL_ACCESS => "system.posix_acl_access",
+            EXT4_XATTR_INDEX_POSIX_ACL_DEFAULT => "system.posix_acl_default",
+            EXT4_XATTR_INDEX_TRUSTED => "trusted.",
+            EXT4_XATTR_INDEX_SECURITY => "security.",
+            EXT4_XATTR_INDEX_SYSTEM => "system.",
+            EXT4_XATTR_INDEX_RICHACL => "system.richacl",
+            _ => "",
+        };
+        format!("{}{}", prefix, String::from_utf8_lossy(&self.name))
+    }
+}
+
+/// An extended attribute of a file, with its full name and value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xattr {
+    /// Full name including the namespace, e.g. `user.comment`
+    pub name: String,
+    /// Raw attribute value
+    pub value: Vec<u8>,
+}
+
+impl core::fmt::Display for Xattr {
+    /// Formats like `getfattr -d`: quoted text, or hex for binary values
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // A single trailing NUL is common for C strings and not shown
+        let text = self.value.strip_suffix(b"\0").unwrap_or(&self.value);
+        match core::str::from_utf8(text) {
+            Ok(s) if !s.chars().any(|c| c.is_control()) => {
+                write!(f, "{}=\"{}\"", self.name, s)
+            }
+            _ => {
+                write!(f, "{}=0x", self.name)?;
+                for byte in &self.value {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parse the xattr entries stored in the inode body after `i_extra_isize`
+///
+/// # Arguments
+/// * `inode_bytes` - Raw on-disk inode (full `s_inode_size` bytes)
+///
+/// # Returns
+/// All entries found in the in-inode area, an empty vector if there is
+/// none, or `None` if an entry's value lies outside the area
+pub(crate) fn parse_inode_xattrs(inode_bytes: &[u8]) -> Option<Vec<XattrEntry>> {
+    if inode_bytes.len() <= INODE_OFFSET_EXTRA_ISIZE + 2 {
+        return Some(Vec::new());
+    }
+
+    let extra_isize = u16::from_le_bytes([
+        inode_bytes[INODE_OFFSET_EXTRA_ISIZE],
+        inode_bytes[INODE_OFFSET_EXTRA_ISIZE + 1],
+    ]) as usize;
+
+    // The xattr area starts with a 4-byte magic right after the extra fields
+    let start = EXT4_GOOD_OLD_INODE_SIZE + extra_isize;
+    if inode_bytes.len() < start + 4 {
+        return Some(Vec::new());
+    }
+
+    let mut cursor = Cursor::new(&inode_bytes[start..]);
+    if cursor.read_u32::<LittleEndian>().unwrap() != EXT4_XATTR_MAGIC {
+        return Some(Vec::new());
+    }
+
+    // In-inode value offsets are relative to the first entry
+    let area = &inode_bytes[start + 4..];
+    parse_entries(area, area)
+}
+
+/// Parse the entries of an external xattr block (referenced by `i_file_acl`)
+///
+/// # Arguments
+/// * `block` - The full xattr block
+///
+/// # Returns
+/// All entries in the block, or `None` if the header magic is wrong or an
+/// entry's value lies outside the block
+pub(crate) fn parse_block_xattrs(block: &[u8]) -> Option<Vec<XattrEntry>> {
+    if block.len() < XATTR_BLOCK_HEADER_SIZE {
+        return None;
+    }
+
+    let mut cursor = Cursor::new(block);
+    if cursor.read_u32::<LittleEndian>().unwrap() != EXT4_XATTR_MAGIC {
+        return None;
+    }
+
+    // Block value offsets are relative to the start of the block
+    parse_entries(&block[XATTR_BLOCK_HEADER_SIZE..], block)
+}
+
+/// Parse a list of xattr entries terminated by four zero bytes
+///
+/// # Arguments
+/// * `entries` - Buffer starting at the first entry
+/// * `values` - Buffer that value offsets are relative to
+///
+/// # Returns
+/// The entries, or `None` if a value stored here lies outside `values`
+fn parse_entries(entries: &[u8], values: &[u8]) -> Option<Vec<XattrEntry>> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+
+    while offset + XATTR_ENTRY_HEADER_SIZE <= entries.len() {
+        let mut cursor = Cursor::new(&entries[offset..]);
+        let name_len = cursor.read_u8().unwrap() as usize;
+        let name_index = cursor.read_u8().unwrap();
+        let value_offs = cursor.read_u16::<LittleEndian>().unwrap() as usize;
+        let value_inum = cursor.read_u32::<LittleEndian>().unwrap();
+        let value_size = cursor.read_u32::<LittleEndian>().unwrap();
+
+        // Four zero bytes mark the end of the list
+        if name_len == 0 && name_index == 0 && value_offs == 0 {
+            break;
+        }
+
+        let name_start = offset + XATTR_ENTRY_HEADER_SIZE;
+        if name_start + name_len > entries.len() {
+            break;
+        }
+        let name = entries[name_start..name_start + name_len].to_vec();
+
+        // Values stored in a separate inode are read by the caller
+        let value = match value_inum {
+            0 => values
+                .get(value_offs..value_offs + value_size as usize)?
+                .to_vec(),
+            _ => Vec::new(),
+        };
+
+        result.push(XattrEntry {
+            name_index,
+            name,
+            value,
+            value_inum,
+            value_size,
+        });
+
+        // Entries are padded to a 4-byte boundary
+        offset = (name_start + name_len + 3) & !3;
+    }
+
+    Some(result)
+}
@@ -17,8 +17,95 @@ sudo mkdir -p mnt/home/zyu379
 # Write content to file
 echo "hello from ext4 test" | sudo tee mnt/home/zyu379/test_file.txt > /dev/null
 
+# Symlinks: fast (target in i_block) absolute, relative and to a directory,
+# a slow one whose target needs a data block, and a loop
+sudo ln -s /home/zyu379/test_file.txt mnt/home/link_abs
+sudo ln -s test_file.txt mnt/home/zyu379/link_rel
+sudo ln -s home/zyu379 mnt/dirlink
+sudo ln -s "home/zyu379/$(printf './%.0s' $(seq 30))test_file.txt" mnt/link_slow
+sudo ln -s loop2 mnt/loop1
+sudo ln -s loop1 mnt/loop2
+
+# Extended attributes: a small one in the inode, a large one in an xattr block
+sudo setfattr -n user.comment -v "hello xattr" mnt/home/zyu379/test_file.txt
+sudo setfattr -n user.big -v "$(head -c 300 /dev/zero | tr '\0' v)" mnt/home/zyu379/test_file.txt
+
+# POSIX ACLs: an access ACL on the file and a default ACL on its directory
+sudo setfacl -m u:1000:rw,g:100:r mnt/home/zyu379/test_file.txt
+sudo setfacl -d -m u:1000:rwx mnt/home/zyu379
+
+# Sparse file with ten separate extents, enough to need an extent tree
+i=0
+for c in a b c d e f g h i j; do
+    head -c 4096 /dev/zero | tr '\0' "$c" | sudo dd of=mnt/sparse.bin bs=4096 seek=$((i * 2)) conv=notrunc 2> /dev/null
+    i=$((i + 1))
+done
+
+# Directory with enough entries that the kernel indexes it with an htree
+sudo mkdir mnt/bigdir
+for n in $(seq -w 1 500); do
+    echo "file $n" | sudo tee "mnt/bigdir/file_$n.txt" > /dev/null
+done
+
+# Directory with a deleted entry whose name is left in the slack space
+sudo mkdir mnt/deleted
+for name in keep1 gone keep2; do
+    echo "$name" | sudo tee "mnt/deleted/$name" > /dev/null
+done
+sudo rm mnt/deleted/gone
+
+# Names that are not valid UTF-8: Latin-1 "café" next to a name spelled
+# with a literal U+FFFD, which a lossy decoding would confuse with it
+sudo mkdir mnt/raw
+echo latin1 | sudo tee "mnt/raw/$(printf 'caf\xe9')" > /dev/null
+echo replacement | sudo tee "mnt/raw/$(printf 'caf\xef\xbf\xbd')" > /dev/null
+
+# Fill 12MB first so the next directory lands above block 8192
+head -c 12M /dev/zero | tr '\0' f | sudo tee mnt/filler.bin > /dev/null
+sync
+sudo mkdir mnt/late
+echo "hello from a high block" | sudo tee mnt/late/hello.txt > /dev/null
+
 # sync to flush writes
 sync
 
 # Step 4: Unmount
 sudo umount mnt
+
+# Step 5: Create a second image with inline_data for small files and directories
+dd if=/dev/zero of=ext4_inline.img bs=1M count=8
+mkfs.ext4 -F -O inline_data ext4_inline.img
+sudo mount -o loop ext4_inline.img mnt
+
+# Fits entirely in i_block
+echo "hello from inline data" | sudo tee mnt/small.txt > /dev/null
+
+# Overflows i_block into the system.data xattr
+head -c 100 /dev/zero | tr '\0' 'x' | sudo tee mnt/medium.txt > /dev/null
+
+# Inline directory with a few entries
+sudo mkdir -p mnt/inline_dir
+for name in a b c; do
+    echo "$name" | sudo tee "mnt/inline_dir/$name" > /dev/null
+done
+
+sync
+sudo umount mnt
+
+# Step 6: Create a third image with a case-insensitive (casefolded) directory
+dd if=/dev/zero of=ext4_casefold.img bs=1M count=8
+mkfs.ext4 -F -O casefold ext4_casefold.img
+sudo mount -o loop ext4_casefold.img mnt
+
+# +F must be set while the directory is still empty; enough entries for an htree
+sudo mkdir mnt/cf
+sudo chattr +F mnt/cf
+for n in $(seq -w 1 300); do
+    echo "entry $n" | sudo tee "mnt/cf/Entry_$n.txt" > /dev/null
+done
+echo readme | sudo tee mnt/cf/README.md > /dev/null
+echo cafe | sudo tee "mnt/cf/$(printf 'Caf\xc3\xa9.txt')" > /dev/null
+echo strasse | sudo tee "mnt/cf/$(printf 'Stra\xc3\x9fe')" > /dev/null
+
+sync
+sudo umount mnt